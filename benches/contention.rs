@@ -0,0 +1,75 @@
+//! 10k concurrent requests spread over 1k pools on a multi threaded runtime.
+//! Every client holds its lease for a moment, so part of the requests queue.
+//!
+//! Every request is a round trip to the inventory actor, which handles one command at a time.
+//! That is slower here than locking each pool on its own, as the inventory did before, but it
+//! keeps the handover, the deadlines, the quotas and preemption in one place, applied in the
+//! order the requests came in. The descriptions of the pools are still a snapshot that queries
+//! read without asking the actor.
+use criterion::{Criterion, criterion_group, criterion_main};
+use futures::future::join_all;
+use rp::inventory::{
+    ClientResourceRequest, Inventory, InventoryManager, LocalRespoClientFactory, Pool, Resource,
+    ResourceRequest, Wait,
+};
+use std::collections::HashMap;
+use tokio::runtime::Runtime;
+use tokio::time::Duration;
+
+const POOL_COUNT: usize = 1_000;
+const REQUEST_COUNT: usize = 10_000;
+const GROUP_COUNT: usize = 50;
+
+fn build_inventory() -> Inventory {
+    Inventory {
+        pools: (0..POOL_COUNT)
+            .map(|index| Pool {
+                name: format!("pool{index}"),
+                attributes: vec![format!("group{}", index % GROUP_COUNT)],
+                location: format!("location{}", index % 3),
+                resources: vec![Resource {
+                    name: "0".into(),
+                    attributes: vec!["RA1".into()],
+                    properties: HashMap::new(),
+                }],
+                allowed: vec![],
+                properties: HashMap::new(),
+            })
+            .collect(),
+    }
+}
+
+async fn concurrent_requests(factory: &LocalRespoClientFactory) {
+    let requests = (0..REQUEST_COUNT).map(|index| {
+        let mut client = factory.create(format!("client{index}"));
+        let request = ResourceRequest {
+            pool_attributes: Some(vec![format!("group{}", index % GROUP_COUNT)]),
+            resource_attributes: Some(vec![vec!["RA1".into()]]),
+            wait: Wait::For(Duration::from_secs(60)),
+            ..Default::default()
+        };
+        tokio::spawn(async move {
+            let lease = client.request(&request).await.unwrap();
+            tokio::task::yield_now().await;
+            drop(lease);
+        })
+    });
+    for result in join_all(requests).await {
+        result.unwrap();
+    }
+}
+
+fn bench_contention(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let _runtime_guard = runtime.enter(); // the inventory actor is spawned on it
+    let factory = LocalRespoClientFactory::new(InventoryManager::new(build_inventory()));
+    let mut group = c.benchmark_group("contention");
+    group.sample_size(10);
+    group.bench_function("1k_pools_10k_requests", |b| {
+        b.iter(|| runtime.block_on(concurrent_requests(&factory)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_contention);
+criterion_main!(benches);
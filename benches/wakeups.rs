@@ -0,0 +1,132 @@
+//! Many clients queue for the same pool; every holder releases it right away.
+//! Measures how long it takes until all queued clients got their turn.
+//! The `drain_queue_wake_all` group is the baseline handing over pools the way it was done
+//! before waiters were queued: every release wakes all waiters, which all try again.
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use futures::future::join_all;
+use rp::inventory::{
+    ClientResourceRequest, Inventory, InventoryManager, LocalRespoClientFactory, Pool, Resource,
+    ResourceRequest, Wait,
+};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::runtime::Runtime;
+use tokio::sync::Notify;
+use tokio::time::Duration;
+
+fn build_inventory(pool_count: usize) -> Inventory {
+    Inventory {
+        pools: (0..pool_count)
+            .map(|index| Pool {
+                name: format!("pool{index}"),
+                attributes: vec!["attr1".into()],
+                location: "location1".into(),
+                resources: vec![Resource {
+                    name: "0".into(),
+                    attributes: vec!["RA1".into()],
+                    properties: HashMap::new(),
+                }],
+                allowed: vec![],
+                properties: HashMap::new(),
+            })
+            .collect(),
+    }
+}
+
+async fn drain_queue(pool_count: usize, waiter_count: usize) {
+    let factory = LocalRespoClientFactory::new(InventoryManager::new(build_inventory(pool_count)));
+    let request = ResourceRequest {
+        wait: Wait::For(Duration::from_secs(60)),
+        ..Default::default()
+    };
+    let waiters = (0..waiter_count).map(|index| {
+        let mut client = factory.create(format!("client{index}"));
+        let request = request.clone();
+        tokio::spawn(async move {
+            let lease = client.request(&request).await.unwrap();
+            tokio::task::yield_now().await;
+            drop(lease);
+        })
+    });
+    for result in join_all(waiters).await {
+        result.unwrap();
+    }
+}
+
+/// Free pools behind one lock, and one notification for all waiters
+struct WakeAll {
+    free: Mutex<Vec<bool>>,
+    released: Notify,
+}
+
+impl WakeAll {
+    async fn claim(&self) -> usize {
+        loop {
+            // registered before looking, so a release in between is not missed
+            let released = self.released.notified();
+            tokio::pin!(released);
+            released.as_mut().enable();
+            {
+                let mut free = self.free.lock().unwrap();
+                if let Some(pool) = free.iter().position(|free| *free) {
+                    free[pool] = false;
+                    return pool;
+                }
+            }
+            released.await;
+        }
+    }
+
+    fn release(&self, pool: usize) {
+        self.free.lock().unwrap()[pool] = true;
+        self.released.notify_waiters();
+    }
+}
+
+async fn drain_queue_wake_all(pool_count: usize, waiter_count: usize) {
+    let pools = Arc::new(WakeAll {
+        free: Mutex::new(vec![true; pool_count]),
+        released: Notify::new(),
+    });
+    let waiters = (0..waiter_count).map(|_| {
+        let pools = pools.clone();
+        tokio::spawn(async move {
+            let pool = pools.claim().await;
+            tokio::task::yield_now().await;
+            pools.release(pool);
+        })
+    });
+    for result in join_all(waiters).await {
+        result.unwrap();
+    }
+}
+
+fn bench_wakeups(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("drain_queue");
+    group.sample_size(10);
+    for (pool_count, waiter_count) in [(1, 100), (1, 400), (4, 400)] {
+        group.bench_with_input(
+            BenchmarkId::new(format!("{pool_count}_pools"), waiter_count),
+            &waiter_count,
+            |b, &waiter_count| b.iter(|| runtime.block_on(drain_queue(pool_count, waiter_count))),
+        );
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("drain_queue_wake_all");
+    group.sample_size(10);
+    for (pool_count, waiter_count) in [(1, 100), (1, 400), (4, 400)] {
+        group.bench_with_input(
+            BenchmarkId::new(format!("{pool_count}_pools"), waiter_count),
+            &waiter_count,
+            |b, &waiter_count| {
+                b.iter(|| runtime.block_on(drain_queue_wake_all(pool_count, waiter_count)))
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_wakeups);
+criterion_main!(benches);
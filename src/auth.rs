@@ -0,0 +1,110 @@
+//! Credentials as sent in the `Authorization` header, shared by the client and the server.
+use std::path::PathBuf;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+pub const TOKEN_ENV: &str = "RP_TOKEN";
+pub const USER_ENV: &str = "RP_USER";
+pub const PASSWORD_ENV: &str = "RP_PASSWORD";
+pub const CREDENTIALS_FILE_ENV: &str = "RP_CREDENTIALS";
+
+/// An API token, or a user name and password.
+/// In a credentials file: `token: <token>`, or `user: <name>` and `password: <password>`.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Credentials {
+    Token { token: String },
+    Password { user: String, password: String },
+}
+
+// keep secrets out of logs
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Credentials::Token { .. } => f.write_str("Token { .. }"),
+            Credentials::Password { user, .. } => f
+                .debug_struct("Password")
+                .field("user", user)
+                .finish_non_exhaustive(),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum CredentialsError {
+    #[error("{0} is set, but {1} is not")]
+    Incomplete(&'static str, &'static str),
+    #[error("Cannot read credentials file {0:?}: {1}")]
+    Unreadable(PathBuf, std::io::Error),
+    #[error("Invalid credentials file {0:?}: {1}")]
+    Invalid(PathBuf, String),
+}
+
+impl Credentials {
+    /// The value of the `Authorization` header
+    pub fn authorization(&self) -> String {
+        match self {
+            Credentials::Token { token } => format!("Bearer {}", token),
+            Credentials::Password { user, password } => {
+                format!(
+                    "Basic {}",
+                    STANDARD.encode(format!("{}:{}", user, password))
+                )
+            }
+        }
+    }
+
+    /// Parse an `Authorization` header, `Bearer` and `Basic` schemes are understood
+    pub fn from_authorization(value: &str) -> Option<Credentials> {
+        let (scheme, rest) = value.trim().split_once(' ')?;
+        let rest = rest.trim();
+        if scheme.eq_ignore_ascii_case("bearer") {
+            return Some(Credentials::Token { token: rest.into() });
+        }
+        if scheme.eq_ignore_ascii_case("basic") {
+            let decoded = String::from_utf8(STANDARD.decode(rest).ok()?).ok()?;
+            let (user, password) = decoded.split_once(':')?;
+            return Some(Credentials::Password {
+                user: user.into(),
+                password: password.into(),
+            });
+        }
+        None
+    }
+
+    /// The credentials of this user: `$RP_TOKEN`, or `$RP_USER` with `$RP_PASSWORD`,
+    /// or the file at `$RP_CREDENTIALS`, or else `~/.config/respo/credentials.yaml` if it exists.
+    pub fn from_env() -> Result<Option<Credentials>, CredentialsError> {
+        if let Ok(token) = std::env::var(TOKEN_ENV) {
+            return Ok(Some(Credentials::Token { token }));
+        }
+        match (std::env::var(USER_ENV), std::env::var(PASSWORD_ENV)) {
+            (Ok(user), Ok(password)) => return Ok(Some(Credentials::Password { user, password })),
+            (Ok(_), Err(_)) => return Err(CredentialsError::Incomplete(USER_ENV, PASSWORD_ENV)),
+            (Err(_), Ok(_)) => return Err(CredentialsError::Incomplete(PASSWORD_ENV, USER_ENV)),
+            (Err(_), Err(_)) => {}
+        }
+        if let Some(path) = std::env::var_os(CREDENTIALS_FILE_ENV) {
+            return Self::from_file(PathBuf::from(path)).map(Some);
+        }
+        let default_path = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .map(|config| config.join("respo").join("credentials.yaml"));
+        match default_path {
+            Some(path) if path.exists() => Self::from_file(path).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn from_file(path: PathBuf) -> Result<Credentials, CredentialsError> {
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) => return Err(CredentialsError::Unreadable(path, e)),
+        };
+        serde_saphyr::from_str(&content).map_err(|e| CredentialsError::Invalid(path, e.to_string()))
+    }
+}
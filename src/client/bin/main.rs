@@ -1,9 +1,23 @@
-use rp::client::{RemoteRespoClientFactory, create_client_name};
-use rp::inventory::ResourceRequest;
-use std::{error::Error, process::Command};
+mod output;
+mod run;
 
-use clap::{Parser, Subcommand};
+use rp::auth::Credentials;
+use rp::client::http::Timeouts;
+use rp::client::lease::RemoteLease;
+use rp::client::{ClientResourceRequestError, RemoteRespoClientFactory, create_client_name};
+use rp::inventory::{ResourceRequest, Wait};
+use rp::logging::{LogConfig, LogFormat, init_logging};
+use rp::tls::ClientTlsConfig;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+
+use clap::{Args, Parser, Subcommand};
+use serde::Serialize;
 use std::process::ExitCode;
+use std::time::Duration;
+
+/// How often the queue progress of a waiting request is shown
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
 
 /// Resource pool client tool
 #[derive(Parser, Debug)]
@@ -12,75 +26,339 @@ use std::process::ExitCode;
 struct Cli {
     #[command(subcommand)]
     command: Commands,
-    #[arg(short, long)]
-    spec_file: Option<String>,
+    #[command(flatten)]
+    request: RequestArgs,
     #[arg(short, long)]
     url: Option<String>,
-    #[arg(short, long)]
+    #[arg(long)]
+    /// log filter, like `debug`; defaults to $RUST_LOG, then `warn`
+    log_level: Option<String>,
+    #[arg(long, default_value = "text")]
+    /// log format: text, pretty or json
+    log_format: LogFormat,
+    #[arg(long)]
+    /// certificate authority of a https server, PEM; defaults to $RP_CA_CERT
+    ca_cert: Option<PathBuf>,
+    #[arg(long)]
+    /// client certificate, PEM; defaults to $RP_CLIENT_CERT
+    client_cert: Option<PathBuf>,
+    #[arg(long)]
+    /// key of the client certificate, PEM; defaults to $RP_CLIENT_KEY
+    client_key: Option<PathBuf>,
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "5s")]
+    /// time to connect to the server
+    connect_timeout: Duration,
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "30s")]
+    /// time for the server to answer, on top of the time the request waits
+    response_timeout: Duration,
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "10s")]
+    /// how long to retry when the server can't be reached, or as long as the request waits
+    retry: Duration,
+}
+
+/// The request of lock and while, given before or after the command
+#[derive(Args, Debug)]
+struct RequestArgs {
+    #[arg(short, long, global = true)]
+    /// YAML or JSON file with the fields of the request; the flags given take precedence
+    spec_file: Option<PathBuf>,
+    #[arg(short, long, global = true)]
+    /// name of the pool
     name: Option<String>,
+    #[arg(long, global = true)]
+    /// location of the pool
+    location: Option<String>,
+    #[arg(long, value_delimiter = ',', global = true)]
+    /// attributes of the pool, comma separated or repeated
+    pool_attr: Vec<String>,
+    #[arg(long, global = true)]
+    /// attributes of a resource of the pool, comma separated; repeated for more resources
+    resource_attr: Vec<String>,
+    #[arg(short, long, alias = "timeout", global = true)]
+    /// how long to wait for a pool in use: nowait (the default), forever, or a duration like
+    /// 90s or 2h. The queue progress is shown while waiting on a terminal.
+    wait: Option<Wait>,
+    #[arg(long, global = true)]
+    /// urgency of the request, a high enough one may preempt leases of a lower one
+    priority: Option<u32>,
 }
 
-#[derive(Subcommand, Debug)]
-enum Commands {
-    /// Locks a pool for maintenance
-    Lock,
-    /// Locks a pool while the shell command specified as arguments is running
-    While { shell_command: Vec<String> },
+impl RequestArgs {
+    fn is_given(&self) -> bool {
+        self.spec_file.is_some()
+            || self.name.is_some()
+            || self.location.is_some()
+            || !self.pool_attr.is_empty()
+            || !self.resource_attr.is_empty()
+            || self.wait.is_some()
+            || self.priority.is_some()
+    }
 }
 
-async fn whilerun(shell_command: Vec<String>) -> Result<(), Box<dyn Error>> {
-    // Setup command
-    let mut command = Command::new(shell_command[0].clone());
-    command.args(shell_command[1..].iter());
+/// The spec file, if any, with the flags given taking precedence
+fn resource_request(args: &RequestArgs) -> Result<ResourceRequest, String> {
+    let mut request = match &args.spec_file {
+        Some(path) => read_spec_file(path)?,
+        None => ResourceRequest::default(),
+    };
+    if args.name.is_some() {
+        request.by_name = args.name.clone();
+    }
+    if args.location.is_some() {
+        request.location = args.location.clone();
+    }
+    if !args.pool_attr.is_empty() {
+        request.pool_attributes = Some(args.pool_attr.clone());
+    }
+    if !args.resource_attr.is_empty() {
+        let resources = args
+            .resource_attr
+            .iter()
+            .map(|attributes| attributes.split(',').map(String::from).collect())
+            .collect();
+        request.resource_attributes = Some(resources);
+    }
+    if let Some(wait) = args.wait {
+        request.wait = wait;
+    }
+    if args.priority.is_some() {
+        request.priority = args.priority;
+    }
+    Ok(request)
+}
 
-    // Pipe stdin so we can issue commands to tell Python what we want it to do
+fn read_spec_file(path: &Path) -> Result<ResourceRequest, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+    let request = if path
+        .extension()
+        .is_some_and(|extension| extension == "json")
+    {
+        serde_json::from_str(&text).map_err(|e| e.to_string())
+    } else {
+        serde_saphyr::from_str(&text).map_err(|e| e.to_string())
+    };
+    request.map_err(|e| format!("invalid spec file {}: {}", path.display(), e))
+}
 
-    // Spawn the process & take ownership of stdin
-    let mut child = command.spawn()?;
+/// Request a lease. While the request waits, how the pools it waits for are used
+/// is shown on stderr when that is a terminal.
+async fn request_lease(
+    factory: &mut RemoteRespoClientFactory,
+    client_name: String,
+    request: &ResourceRequest,
+) -> Result<RemoteLease, ClientResourceRequestError> {
+    let mut client = factory.create(client_name.clone());
+    if request.wait == Wait::NoWait || !std::io::stderr().is_terminal() {
+        return client.request(request).await;
+    }
+    let observer = factory.create(client_name);
+    let progress = async {
+        loop {
+            tokio::time::sleep(PROGRESS_INTERVAL).await;
+            if let Ok(status) = observer.status().await {
+                output::print_progress(&status, request);
+            }
+        }
+    };
+    let lease = tokio::select! {
+        lease = client.request(request) => lease,
+        _ = progress => unreachable!("the progress is shown until the request is served"),
+    };
+    output::clear_progress();
+    lease
+}
 
-    // Make sure the process has exited before we exit
-    child.wait()?;
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Locks a pool for maintenance
+    Lock,
+    /// Locks a pool while the shell command specified as arguments is running, with the lease
+    /// in its environment: RP_POOL_NAME, RP_LOCATION, RP_RES_<RESOURCE>_<PROPERTY>, ... and
+    /// RP_LEASE_FILE, the lease as JSON. Exits with the exit code of the command.
+    While {
+        #[arg(long, value_parser = run::parse_template)]
+        /// TEMPLATE:OUTPUT, a file rendered before the command runs, its {{RP_...}} replaced;
+        /// repeated for more files
+        template: Vec<run::Template>,
+        shell_command: Vec<String>,
+    },
+    /// Lists the pools with their holder and queue
+    Status {
+        #[arg(long)]
+        /// print JSON instead of a table
+        json: bool,
+    },
+    /// Shows a pool in full, with the properties of its resources
+    Show {
+        pool: String,
+        #[arg(long)]
+        /// print JSON instead of a table
+        json: bool,
+    },
+    /// Lists my active leases
+    Leases {
+        #[arg(long)]
+        /// print JSON instead of a table
+        json: bool,
+    },
+}
 
-    Ok(())
+/// Print the answer of a query command as JSON, or for humans with `print`
+fn print_answer<T: Serialize>(
+    answer: Result<T, ClientResourceRequestError>,
+    json: bool,
+    print: impl Fn(&T),
+) -> ExitCode {
+    match answer {
+        Ok(answer) if json => {
+            let json =
+                serde_json::to_string_pretty(&answer).expect("the answer serializes to json");
+            println!("{json}");
+            ExitCode::SUCCESS
+        }
+        Ok(answer) => {
+            print(&answer);
+            ExitCode::SUCCESS
+        }
+        Err(x) => {
+            tracing::error!("An error occured: {}", x);
+            ExitCode::from(1)
+        }
+    }
 }
+
 #[tokio::main]
 async fn main() -> ExitCode {
     let args = Cli::parse();
-    let server_url = args.url.or_else(|| std::env::var("RP_SERVER").ok());
+    if let Err(e) = init_logging(&LogConfig {
+        level: args.log_level.clone(),
+        default_level: "warn".into(),
+        format: args.log_format,
+        file: None,
+    }) {
+        eprintln!("could not set up logging: {}", e);
+        return ExitCode::FAILURE;
+    }
+    let Some(server_url) = args.url.or_else(|| std::env::var("RP_SERVER").ok()) else {
+        tracing::error!("No server specified, give --url or set $RP_SERVER");
+        return ExitCode::FAILURE;
+    };
+    let credentials = match Credentials::from_env() {
+        Ok(credentials) => credentials,
+        Err(e) => {
+            tracing::error!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let tls_env = ClientTlsConfig::from_env();
+    let tls_config = ClientTlsConfig {
+        ca: args.ca_cert.or(tls_env.ca),
+        certificate: args.client_cert.or(tls_env.certificate),
+        key: args.client_key.or(tls_env.key),
+    };
+    let tls = if tls_config == ClientTlsConfig::default() {
+        None
+    } else {
+        match tls_config.connector() {
+            Ok(connector) => Some(connector),
+            Err(e) => {
+                tracing::error!("{}", e);
+                return ExitCode::FAILURE;
+            }
+        }
+    };
+    let timeouts = Timeouts {
+        connect: args.connect_timeout,
+        response: args.response_timeout,
+        retry: args.retry,
+    };
+    let factory = || {
+        let factory = RemoteRespoClientFactory::new(server_url.clone())
+            .with_credentials(credentials.clone())
+            .with_timeouts(timeouts);
+        match &tls {
+            Some(connector) => factory.with_tls(connector.clone()),
+            None => factory,
+        }
+    };
 
+    if !matches!(args.command, Commands::Lock | Commands::While { .. }) && args.request.is_given() {
+        tracing::error!("Only lock and while take a request");
+        return ExitCode::FAILURE;
+    }
     match args.command {
         Commands::Lock => {
-            let mut factory =
-                RemoteRespoClientFactory::new(server_url.expect("No server specified"));
-            let mut client = factory.create("test_client".into());
-
-            let ok_request = ResourceRequest {
-                by_name: Some(args.name.unwrap()),
-                ..Default::default()
+            let request = match resource_request(&args.request) {
+                Ok(request) => request,
+                Err(x) => {
+                    tracing::error!("{}", x);
+                    return ExitCode::from(1);
+                }
             };
-            assert!(client.request(&ok_request).await.is_ok());
+            let mut factory = factory();
+            match request_lease(&mut factory, "test_client".into(), &request).await {
+                // the lease is not kept beyond the command, see While
+                Ok(lease) => {
+                    if let Err(x) = lease.release().await {
+                        tracing::warn!("Could not release the lease: {}", x);
+                    }
+                }
+                Err(x) => {
+                    tracing::error!("An error occured: {}", x);
+                    return ExitCode::from(1);
+                }
+            }
         }
-        Commands::While { shell_command } => {
-            let mut factory =
-                RemoteRespoClientFactory::new(server_url.expect("No server specified"));
-            let mut client = factory.create(create_client_name());
-
-            let ok_request = ResourceRequest {
-                by_name: Some(args.name.expect("No pool name specified")),
-                ..Default::default()
+        Commands::While {
+            template,
+            shell_command,
+        } => {
+            let request = match resource_request(&args.request) {
+                Ok(request) => request,
+                Err(x) => {
+                    tracing::error!("{}", x);
+                    return ExitCode::from(1);
+                }
             };
-            let lease = client.request(&ok_request).await;
+            let mut factory = factory();
+            let lease = request_lease(&mut factory, create_client_name(), &request).await;
             match lease {
-                Ok(_lease) => {
-                    // FIXME: will not using it here cause a drop before we go out of scope?
-                    whilerun(shell_command).await.unwrap()
+                Ok(lease) => {
+                    // the command is stopped when the lease is lost while it runs
+                    let outcome = run::run(&lease, &shell_command, &template).await;
+                    if let Err(x) = lease.release().await {
+                        tracing::warn!("Could not release the lease: {}", x);
+                    }
+                    match outcome {
+                        Ok(code) => return code,
+                        Err(x) => {
+                            tracing::error!("An error occured: {}", x);
+                            return ExitCode::from(1);
+                        }
+                    }
                 }
                 Err(x) => {
-                    println!("An error occured: {:?}", x);
+                    tracing::error!("An error occured: {:?}", x);
                     return ExitCode::from(1); // Specific error code
                 }
             }
         }
+        Commands::Status { json } => {
+            let client = factory().create(create_client_name());
+            return print_answer(client.status().await, json, output::print_status);
+        }
+        Commands::Show { pool, json } => {
+            let client = factory().create(create_client_name());
+            return print_answer(client.pool(&pool).await, json, output::print_pool);
+        }
+        Commands::Leases { json } => {
+            let client = factory().create(create_client_name());
+            return print_answer(client.leases().await, json, |leases: &Vec<_>| {
+                output::print_leases(leases)
+            });
+        }
     }
     ExitCode::SUCCESS
 }
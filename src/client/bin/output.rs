@@ -0,0 +1,166 @@
+//! Human readable output of the query commands, and of the progress of waiting requests
+use rp::inventory::{InventoryStatus, LeaseStatus, PoolDetails, ResourceRequest};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+
+/// Columns padded to their widest cell, the last one not padded
+fn print_table(headers: &[&str], rows: Vec<Vec<String>>) {
+    let mut widths: Vec<usize> = headers.iter().map(|header| header.len()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let headers = headers.iter().map(|header| header.to_string()).collect();
+    for row in std::iter::once(headers).chain(rows) {
+        let line: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    }
+}
+
+fn or_dash(value: Option<&str>) -> String {
+    value.unwrap_or("-").to_string()
+}
+
+/// The properties as one line of JSON, sorted by name
+fn properties(properties: &HashMap<String, Value>) -> String {
+    if properties.is_empty() {
+        return "-".into();
+    }
+    let sorted: BTreeMap<&String, &Value> = properties.iter().collect();
+    serde_json::to_string(&sorted).expect("the properties serialize to json")
+}
+
+fn time(at: &chrono::DateTime<chrono::Utc>) -> String {
+    at.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+pub fn print_status(status: &InventoryStatus) {
+    let rows = status
+        .pools
+        .iter()
+        .map(|pool| {
+            vec![
+                pool.name.clone(),
+                pool.location.clone(),
+                or_dash(pool.holder.as_deref()),
+                pool.queue_length.to_string(),
+                if pool.maintenance { "maintenance" } else { "" }.into(),
+            ]
+        })
+        .collect();
+    print_table(&["POOL", "LOCATION", "HOLDER", "QUEUE", ""], rows);
+}
+
+pub fn print_leases(leases: &[LeaseStatus]) {
+    let rows = leases
+        .iter()
+        .map(|lease| {
+            vec![
+                lease.lease_id.to_string(),
+                lease.pool.clone(),
+                lease.location.clone(),
+                time(&lease.granted_at),
+                or_dash(lease.expires_at.map(|at| time(&at)).as_deref()),
+                or_dash(lease.revoked_at.map(|at| time(&at)).as_deref()),
+            ]
+        })
+        .collect();
+    print_table(
+        &["LEASE", "POOL", "LOCATION", "GRANTED", "EXPIRES", "REVOKED"],
+        rows,
+    );
+}
+
+pub fn print_pool(details: &PoolDetails) {
+    let pool = &details.pool;
+    let status = &details.status;
+    let holder = match &details.lease {
+        Some(lease) => match lease.expires_at {
+            Some(expires_at) => format!(
+                "{} (lease {}, expires {})",
+                lease.client,
+                lease.lease_id,
+                time(&expires_at)
+            ),
+            None => format!("{} (lease {})", lease.client, lease.lease_id),
+        },
+        None => or_dash(status.holder.as_deref()),
+    };
+    let allowed = if pool.allowed.is_empty() {
+        "anybody".into()
+    } else {
+        pool.allowed.join(", ")
+    };
+    print_table(
+        &["POOL", &pool.name],
+        vec![
+            vec!["location".into(), pool.location.clone()],
+            vec!["attributes".into(), pool.attributes.join(", ")],
+            vec!["properties".into(), properties(&pool.properties)],
+            vec!["allowed".into(), allowed],
+            vec!["holder".into(), holder],
+            vec!["queue".into(), status.queue_length.to_string()],
+            vec!["maintenance".into(), status.maintenance.to_string()],
+        ],
+    );
+    println!();
+    let rows = pool
+        .resources
+        .iter()
+        .map(|resource| {
+            vec![
+                resource.name.clone(),
+                resource.attributes.join(", "),
+                properties(&resource.properties),
+            ]
+        })
+        .collect();
+    print_table(&["RESOURCE", "ATTRIBUTES", "PROPERTIES"], rows);
+}
+
+/// One line telling how the pools a request may get are used, redrawn in place
+pub fn print_progress(status: &InventoryStatus, request: &ResourceRequest) {
+    let pools: Vec<String> = status
+        .pools
+        .iter()
+        .filter(|pool| {
+            request
+                .by_name
+                .as_ref()
+                .is_none_or(|name| *name == pool.name)
+        })
+        .filter(|pool| {
+            request
+                .location
+                .as_ref()
+                .is_none_or(|location| *location == pool.location)
+        })
+        .filter(|pool| {
+            request
+                .pool_attributes
+                .iter()
+                .flatten()
+                .all(|attribute| pool.attributes.contains(attribute))
+        })
+        .map(|pool| match (&pool.holder, pool.maintenance) {
+            (_, true) => format!("{} in maintenance", pool.name),
+            (Some(holder), _) => {
+                format!(
+                    "{} held by {}, {} queued",
+                    pool.name, holder, pool.queue_length
+                )
+            }
+            (None, _) => format!("{} free, {} queued", pool.name, pool.queue_length),
+        })
+        .collect();
+    eprint!("\r\x1b[Kwaiting: {}", pools.join("; "));
+}
+
+pub fn clear_progress() {
+    eprint!("\r\x1b[K");
+}
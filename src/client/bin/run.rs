@@ -0,0 +1,141 @@
+//! Runs the command of `client while` with the lease exported to it: as environment
+//! variables, as a JSON file named by `RP_LEASE_FILE`, and in the rendered templates.
+use rp::client::export;
+use rp::client::lease::RemoteLease;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::process::ExitStatusExt;
+use std::path::PathBuf;
+use std::process::{ExitCode, ExitStatus};
+use tokio::process::{Child, Command};
+use tokio::signal::unix::{Signal, SignalKind, signal};
+
+/// A template rendered with the variables of the lease before the command starts
+#[derive(Debug, Clone)]
+pub struct Template {
+    pub source: PathBuf,
+    pub output: PathBuf,
+}
+
+/// `TEMPLATE:OUTPUT`
+pub fn parse_template(value: &str) -> Result<Template, String> {
+    match value.split_once(':') {
+        Some((source, output)) if !source.is_empty() && !output.is_empty() => Ok(Template {
+            source: source.into(),
+            output: output.into(),
+        }),
+        _ => Err(format!("expected TEMPLATE:OUTPUT, got {value:?}")),
+    }
+}
+
+/// The lease as JSON, readable by the user only and removed once the command is done
+struct LeaseFile(PathBuf);
+
+impl LeaseFile {
+    fn write(lease: &RemoteLease) -> Result<LeaseFile, String> {
+        let path = std::env::temp_dir().join(format!(
+            "rp-lease-{}-{}.json",
+            lease.id(),
+            std::process::id()
+        ));
+        let json = serde_json::to_string_pretty(lease.lease()).expect("the lease serializes");
+        // a new file, not whatever somebody else put there under the same name
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)
+            .map_err(|e| format!("could not create {}: {}", path.display(), e))?;
+        // removed from here on, when the write fails too
+        let lease_file = LeaseFile(path);
+        file.write_all(json.as_bytes())
+            .map_err(|e| format!("could not write {}: {}", lease_file.0.display(), e))?;
+        Ok(lease_file)
+    }
+}
+
+impl Drop for LeaseFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+fn render(template: &Template, variables: &BTreeMap<String, String>) -> Result<(), String> {
+    let source = std::fs::read_to_string(&template.source)
+        .map_err(|e| format!("could not read {}: {}", template.source.display(), e))?;
+    let rendered = export::render(&source, variables)
+        .map_err(|e| format!("template {}: {}", template.source.display(), e))?;
+    std::fs::write(&template.output, rendered)
+        .map_err(|e| format!("could not write {}: {}", template.output.display(), e))
+}
+
+/// The exit code of the command, 128 + the signal when killed by one like a shell does
+fn exit_code(status: ExitStatus) -> ExitCode {
+    let code = match (status.code(), status.signal()) {
+        (Some(code), _) => code,
+        (None, Some(signal)) => 128 + signal,
+        (None, None) => 1,
+    };
+    ExitCode::from(u8::try_from(code).unwrap_or(1))
+}
+
+fn forward(child: &Child, signal: libc::c_int) {
+    if let Some(pid) = child.id() {
+        // SAFETY: kill has no memory safety requirements, the pid is our running child
+        unsafe {
+            libc::kill(pid as libc::pid_t, signal);
+        }
+    }
+}
+
+async fn next_signal(signals: &mut [(Signal, libc::c_int)]) -> Option<libc::c_int> {
+    let receiving = signals
+        .iter_mut()
+        .map(|(stream, number)| Box::pin(async move { stream.recv().await.map(|_| *number) }));
+    futures::future::select_all(receiving).await.0
+}
+
+/// Run the command until it exits, returning its exit code, or until the lease is lost,
+/// killing it. SIGTERM, SIGHUP, SIGUSR1 and SIGUSR2 are forwarded to the command. SIGINT
+/// is not: from a terminal it reaches the command in the same process group already.
+pub async fn run(
+    lease: &RemoteLease,
+    shell_command: &[String],
+    templates: &[Template],
+) -> Result<ExitCode, String> {
+    let (program, args) = shell_command.split_first().ok_or("no command given")?;
+    let mut variables = export::environment(lease);
+    let lease_file = LeaseFile::write(lease)?;
+    variables.insert("RP_LEASE_FILE".into(), lease_file.0.display().to_string());
+    for template in templates {
+        render(template, &variables)?;
+    }
+
+    let mut signals = Vec::new();
+    for (kind, number) in [
+        (SignalKind::terminate(), libc::SIGTERM),
+        (SignalKind::hangup(), libc::SIGHUP),
+        (SignalKind::user_defined1(), libc::SIGUSR1),
+        (SignalKind::user_defined2(), libc::SIGUSR2),
+    ] {
+        signals.push((signal(kind).map_err(|e| e.to_string())?, number));
+    }
+    let mut interrupt = signal(SignalKind::interrupt()).map_err(|e| e.to_string())?;
+
+    // killed when no longer awaited
+    let mut child = Command::new(program)
+        .args(args)
+        .envs(&variables)
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("could not run {program}: {e}"))?;
+    loop {
+        tokio::select! {
+            status = child.wait() => return status.map(exit_code).map_err(|e| e.to_string()),
+            lost = lease.lost() => return Err(lost.to_string()),
+            Some(number) = next_signal(&mut signals) => forward(&child, number),
+            _ = interrupt.recv() => {}
+        }
+    }
+}
@@ -0,0 +1,111 @@
+//! The lease as environment variables, for the command run by `client while`:
+//! - `RP_LEASE_ID`, `RP_POOL_NAME`, `RP_LOCATION`, `RP_POOL_ATTRIBUTES` and `RP_RESOURCES`,
+//!   lists joined with commas
+//! - `RP_PROP_<KEY>` for the properties of the pool
+//! - `RP_RES_<RESOURCE>_<KEY>` for the properties of every resource of the pool
+//!
+//! Names are upper cased with anything but letters and digits replaced by `_`. Nested maps
+//! add their keys to the name and lists the position of their items, like `RP_RES_DUT_PORTS_0`.
+//! Templates refer to the variables as `{{RP_POOL_NAME}}`.
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+use thiserror::Error;
+
+use crate::inventory::PoolLease;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum TemplateError {
+    #[error("unknown variable {0:?}")]
+    UnknownVariable(String),
+    #[error("unclosed {{{{ at offset {0}")]
+    Unclosed(usize),
+}
+
+/// The variables of the lease, by name
+pub fn environment(lease: &PoolLease) -> BTreeMap<String, String> {
+    let pool = lease.pool();
+    let mut variables = BTreeMap::from([
+        ("RP_LEASE_ID".to_string(), lease.id().to_string()),
+        ("RP_POOL_NAME".to_string(), pool.name.clone()),
+        ("RP_LOCATION".to_string(), pool.location.clone()),
+        ("RP_POOL_ATTRIBUTES".to_string(), pool.attributes.join(",")),
+    ]);
+    let names: Vec<&str> = pool.resources.iter().map(|r| r.name.as_str()).collect();
+    variables.insert("RP_RESOURCES".into(), names.join(","));
+    for (key, value) in &pool.properties {
+        flatten(
+            &mut variables,
+            format!("RP_PROP_{}", variable_name(key)),
+            value,
+        );
+    }
+    for resource in &pool.resources {
+        let prefix = format!("RP_RES_{}", variable_name(&resource.name));
+        for (key, value) in &resource.properties {
+            flatten(
+                &mut variables,
+                format!("{prefix}_{}", variable_name(key)),
+                value,
+            );
+        }
+    }
+    variables
+}
+
+fn variable_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c.to_ascii_uppercase(),
+            false => '_',
+        })
+        .collect()
+}
+
+fn flatten(variables: &mut BTreeMap<String, String>, name: String, value: &Value) {
+    match value {
+        Value::Null => {
+            variables.insert(name, String::new());
+        }
+        Value::String(value) => {
+            variables.insert(name, value.clone());
+        }
+        Value::Bool(_) | Value::Number(_) => {
+            variables.insert(name, value.to_string());
+        }
+        Value::Array(items) => {
+            for (index, item) in items.iter().enumerate() {
+                flatten(variables, format!("{name}_{index}"), item);
+            }
+        }
+        Value::Object(map) => {
+            for (key, item) in map {
+                flatten(variables, format!("{name}_{}", variable_name(key)), item);
+            }
+        }
+    }
+}
+
+/// Replace the `{{NAME}}` placeholders of the template, spaces around the name allowed
+pub fn render(
+    template: &str,
+    variables: &BTreeMap<String, String>,
+) -> Result<String, TemplateError> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let offset = template.len() - rest.len() + start;
+        let end = rest[start..]
+            .find("}}")
+            .ok_or(TemplateError::Unclosed(offset))?;
+        let name = rest[start + 2..start + end].trim();
+        let value = variables
+            .get(name)
+            .ok_or_else(|| TemplateError::UnknownVariable(name.into()))?;
+        rendered.push_str(value);
+        rest = &rest[start + end + 2..];
+    }
+    rendered.push_str(rest);
+    Ok(rendered)
+}
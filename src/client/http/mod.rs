@@ -1,79 +1,178 @@
+//! The HTTP side of the remote client: one pooled hyper client per factory, so the clients
+//! it creates reuse their connections.
+mod connector;
+
+use crate::auth::Credentials;
 use crate::client::ClientResourceRequestError;
-use crate::inventory::{PoolLease, ResourceRequest};
-use http_body_util::BodyExt;
-use http_body_util::Empty;
+use crate::inventory::{PoolLease, ResourceRequest, Wait};
+use connector::Connector;
+use http_body_util::{BodyExt, Full};
 use hyper::Request;
 use hyper::body::Bytes;
-use hyper_util::rt::TokioIo;
-use tokio::net::TcpStream;
+use hyper_util::client::legacy::Client;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::rt::TokioExecutor;
+use serde::de::DeserializeOwned;
+use tokio::time::{Duration, timeout};
+use tokio_rustls::TlsConnector;
+use tracing::debug;
 
-pub fn build_query(client_name: Option<String>, request: &ResourceRequest) -> String {
-    let mut query: Vec<String> = Vec::new();
-    if let Some(client_name) = client_name {
-        let mut client_name_part = String::from("client_name=");
-        client_name_part.push_str(client_name.as_str());
-        query.push(client_name_part);
-    }
-    if let Some(location) = request.location.clone() {
-        let mut location_part = String::from("location=");
-        location_part.push_str(location.as_str());
-        query.push(location_part);
-    }
-    if let Some(attribute_list) = request.pool_attributes.clone() {
-        let mut attribute_part = String::from("pool_attributes=");
-        attribute_part.push_str(attribute_list.join(",").as_str());
-    }
-    if let Some(pool_name) = request.by_name.clone() {
-        let mut pool_name_part = String::from("by_name=");
-        pool_name_part.push_str(pool_name.as_str());
-        query.push(pool_name_part);
-    }
-    query.join("&")
+/// How long the client waits for the server
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timeouts {
+    pub connect: Duration,
+    /// for the response, on top of the time the request waits to be served
+    pub response: Duration,
+    /// how long connection failures are retried, when the request waits less
+    pub retry: Duration,
 }
 
-pub async fn try_request(url: String) -> Result<PoolLease, ClientResourceRequestError> {
-    let url = url.parse::<hyper::Uri>()?;
-    dbg!(&url);
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            connect: Duration::from_secs(5),
+            response: Duration::from_secs(30),
+            retry: Duration::from_secs(10),
+        }
+    }
+}
 
-    let host = url
-        .host()
-        .ok_or(ClientResourceRequestError::InvalidHostError)?;
-    let port = url.port_u16().unwrap_or(80);
+#[derive(Clone)]
+pub struct HttpClient {
+    client: Client<Connector, Full<Bytes>>,
+    timeouts: Timeouts,
+}
 
-    let address = format!("{}:{}", host, port);
+impl HttpClient {
+    /// `https://` urls are connected with the `tls` connector,
+    /// or one trusting the well known certificate authorities when not given.
+    pub fn new(tls: Option<TlsConnector>, timeouts: Timeouts) -> HttpClient {
+        let mut http = HttpConnector::new();
+        http.enforce_http(false);
+        http.set_connect_timeout(Some(timeouts.connect));
+        HttpClient {
+            client: Client::builder(TokioExecutor::new()).build(Connector::new(http, tls)),
+            timeouts,
+        }
+    }
 
-    let stream = TcpStream::connect(address)
-        .await
-        .expect("unable to connect");
+    pub fn timeouts(&self) -> &Timeouts {
+        &self.timeouts
+    }
 
-    let io = TokioIo::new(stream);
+    /// Request a lease once, the request is sent as JSON body of a POST
+    pub async fn try_request(
+        &self,
+        url: &str,
+        request: &ResourceRequest,
+        credentials: Option<&Credentials>,
+    ) -> Result<PoolLease, ClientResourceRequestError> {
+        let url = url.parse::<hyper::Uri>()?;
+        if url.host().is_none() {
+            return Err(ClientResourceRequestError::InvalidHostError);
+        }
+        debug!(%url, "requesting");
 
-    let (mut sender, conn) = hyper::client::conn::http1::handshake(io)
-        .await
-        .expect("create client failed");
+        let mut req = Request::builder()
+            .method(hyper::Method::POST)
+            .uri(url)
+            .header(hyper::header::CONTENT_TYPE, "application/json");
+        if let Some(credentials) = credentials {
+            req = req.header(hyper::header::AUTHORIZATION, credentials.authorization());
+        }
+        let req = req.body(Full::new(Bytes::from(serde_json::to_vec(request)?)))?;
 
-    // Spawn a task to poll the connection, driving the HTTP state
-    tokio::task::spawn(async move {
-        if let Err(err) = conn.await {
-            println!("Connection failed: {:?}", err);
+        let exchange = async {
+            let res = self.client.request(req).await?;
+            debug!(status = %res.status(), "response");
+            let status = res.status();
+            if status == hyper::StatusCode::UNAUTHORIZED {
+                return Err(ClientResourceRequestError::Unauthorized);
+            }
+            let body = res.into_body().collect().await?.to_bytes();
+            match status {
+                hyper::StatusCode::OK => Ok(body),
+                hyper::StatusCode::CONFLICT => Err(ClientResourceRequestError::InventoryError(
+                    serde_json::from_slice(&body)?,
+                )),
+                status => Err(ClientResourceRequestError::Refused {
+                    status,
+                    message: String::from_utf8_lossy(&body).into_owned(),
+                }),
+            }
+        };
+        let body = match request.wait {
+            Wait::NoWait => timeout(self.timeouts.response, exchange).await,
+            Wait::For(wait) => timeout(wait.saturating_add(self.timeouts.response), exchange).await,
+            Wait::Forever => Ok(exchange.await),
         }
-    });
+        .map_err(|_| ClientResourceRequestError::TimedOut)??;
 
-    let authority = url.authority().unwrap().clone();
+        Ok(serde_json::from_slice(&body)?)
+    }
 
-    let req = Request::builder()
-        .uri(url)
-        .header(hyper::header::HOST, authority.as_str())
-        .body(Empty::<Bytes>::new())?;
+    /// POST to an endpoint taking its arguments in the query string, like /unlock
+    pub async fn post(
+        &self,
+        url: &str,
+        credentials: Option<&Credentials>,
+    ) -> Result<(), ClientResourceRequestError> {
+        self.send(hyper::Method::POST, url, credentials)
+            .await
+            .map(|_| ())
+    }
 
-    let res = sender.send_request(req).await?;
-    println!("Response status: {}", res.status());
+    /// POST to an endpoint answering with JSON, like /renew
+    pub async fn post_for<T: DeserializeOwned>(
+        &self,
+        url: &str,
+        credentials: Option<&Credentials>,
+    ) -> Result<T, ClientResourceRequestError> {
+        let body = self.send(hyper::Method::POST, url, credentials).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
 
-    // asynchronously read the body as bytes
-    let body = res.into_body().collect().await?;
-    let body_bytes = body.to_bytes();
+    /// GET the JSON answer of a query endpoint, like /status
+    pub async fn get<T: DeserializeOwned>(
+        &self,
+        url: &str,
+        credentials: Option<&Credentials>,
+    ) -> Result<T, ClientResourceRequestError> {
+        let body = self.send(hyper::Method::GET, url, credentials).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
 
-    let result = serde_json::from_slice(&body_bytes)?;
+    /// Send a request without body, and return the body of the answer when it is a success
+    async fn send(
+        &self,
+        method: hyper::Method,
+        url: &str,
+        credentials: Option<&Credentials>,
+    ) -> Result<Bytes, ClientResourceRequestError> {
+        let url = url.parse::<hyper::Uri>()?;
+        let mut req = Request::builder().method(method).uri(url);
+        if let Some(credentials) = credentials {
+            req = req.header(hyper::header::AUTHORIZATION, credentials.authorization());
+        }
+        let req = req.body(Full::new(Bytes::new()))?;
 
-    Ok(result)
+        let exchange = async {
+            let res = self.client.request(req).await?;
+            let status = res.status();
+            let body = res.into_body().collect().await?.to_bytes();
+            let message = || String::from_utf8_lossy(&body).into_owned();
+            match status {
+                hyper::StatusCode::OK => Ok(body),
+                hyper::StatusCode::UNAUTHORIZED => Err(ClientResourceRequestError::Unauthorized),
+                hyper::StatusCode::GONE => Err(ClientResourceRequestError::NotHeld(message())),
+                status => Err(ClientResourceRequestError::Refused {
+                    status,
+                    message: message(),
+                }),
+            }
+        };
+        timeout(self.timeouts.response, exchange)
+            .await
+            .map_err(|_| ClientResourceRequestError::TimedOut)?
+    }
 }
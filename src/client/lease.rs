@@ -0,0 +1,188 @@
+//! The lease of a remote client. The server keeps it as long as the client renews it,
+//! the guard does so in the background and releases it when dropped.
+use crate::auth::Credentials;
+use crate::client::ClientResourceRequestError;
+use crate::client::http::HttpClient;
+use crate::inventory::{DEFAULT_LEASE_TIME, PoolLease};
+use crate::preemption::{Renewal, Revocation};
+
+use thiserror::Error;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::{Duration, Instant, sleep};
+use tracing::{debug, warn};
+
+/// Why a remote lease was lost while the guard was held
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum LeaseLost {
+    #[error("The server ended the lease: {0}")]
+    Ended(String),
+    #[error("The server could not be reached for the lease time: {0}")]
+    Unreachable(String),
+}
+
+/// Renews and releases a lease kept by the server
+#[derive(Clone)]
+pub(crate) struct Keeper {
+    pub(crate) http: HttpClient,
+    pub(crate) credentials: Option<Credentials>,
+    pub(crate) renew_url: String,
+    pub(crate) unlock_url: String,
+}
+
+impl Keeper {
+    async fn renew(&self) -> Result<Renewal, ClientResourceRequestError> {
+        self.http
+            .post_for(&self.renew_url, self.credentials.as_ref())
+            .await
+    }
+
+    async fn release(&self) -> Result<(), ClientResourceRequestError> {
+        self.http
+            .post(&self.unlock_url, self.credentials.as_ref())
+            .await
+    }
+}
+
+/// A pool leased from a remote server. Holding it is holding the pool: a background task
+/// renews the lease, and dropping the guard releases it.
+/// `lost` tells when the pool is no longer held before that, `revocation` when it is preempted.
+pub struct RemoteLease {
+    lease: PoolLease,
+    keeper: Option<Keeper>, // taken once released
+    heartbeat: JoinHandle<()>,
+    lost: watch::Receiver<Option<LeaseLost>>,
+    revocation: watch::Receiver<Option<Revocation>>,
+}
+
+impl RemoteLease {
+    /// Renews the lease every `interval` while held. When not given, a third of its lease
+    /// time, or its grace period when shorter so a revocation is heard of before it happens.
+    pub(crate) fn new(lease: PoolLease, keeper: Keeper, interval: Option<Duration>) -> RemoteLease {
+        // the server gives the leases it keeps a lease time
+        let leasetime = lease.leasetime().unwrap_or(DEFAULT_LEASE_TIME);
+        let interval = interval.unwrap_or_else(|| match lease.grace_period() {
+            Some(grace_period) if !grace_period.is_zero() => grace_period.min(leasetime / 3),
+            _ => leasetime / 3,
+        });
+        let (lost_sender, lost) = watch::channel(None);
+        let (revocation_sender, revocation) = watch::channel(None);
+        let heartbeat = tokio::spawn(heartbeat(
+            keeper.clone(),
+            interval,
+            leasetime,
+            lost_sender,
+            revocation_sender,
+        ));
+        RemoteLease {
+            lease,
+            keeper: Some(keeper),
+            heartbeat,
+            lost,
+            revocation,
+        }
+    }
+
+    /// The lease as the server granted it, also reachable through deref
+    pub fn lease(&self) -> &PoolLease {
+        &self.lease
+    }
+
+    /// Release the pool now and wait for the server to confirm
+    pub async fn release(mut self) -> Result<(), ClientResourceRequestError> {
+        self.heartbeat.abort();
+        match self.keeper.take() {
+            Some(keeper) => keeper.release().await,
+            None => Ok(()),
+        }
+    }
+
+    /// Resolves when the server no longer holds the lease, because it was revoked,
+    /// force released or it expired, or when the server could not be reached to renew it
+    pub async fn lost(&self) -> LeaseLost {
+        let mut lost = self.lost.clone();
+        match lost.wait_for(Option::is_some).await {
+            Ok(lost) => lost.clone().expect("waited for the loss"),
+            Err(_) => LeaseLost::Ended("the renewal stopped".into()),
+        }
+    }
+
+    /// Resolves when the server reports the lease preempted at a renewal, with when it
+    /// will be revoked. Resolves to None when the lease is lost otherwise.
+    pub async fn revocation(&self) -> Option<Revocation> {
+        let mut revocation = self.revocation.clone();
+        revocation.wait_for(Option::is_some).await.ok()?.clone()
+    }
+}
+
+impl std::ops::Deref for RemoteLease {
+    type Target = PoolLease;
+
+    fn deref(&self) -> &PoolLease {
+        &self.lease
+    }
+}
+
+impl std::fmt::Debug for RemoteLease {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RemoteLease")
+            .field("lease", &self.lease)
+            .field("released", &self.keeper.is_none())
+            .finish()
+    }
+}
+
+impl Drop for RemoteLease {
+    fn drop(&mut self) {
+        self.heartbeat.abort();
+        // outside of a runtime there is nothing to send with, the lease expires on the server
+        if let (Some(keeper), Ok(runtime)) =
+            (self.keeper.take(), tokio::runtime::Handle::try_current())
+        {
+            runtime.spawn(async move {
+                if let Err(e) = keeper.release().await {
+                    debug!(error = %e, "releasing the dropped lease");
+                }
+            });
+        }
+    }
+}
+
+/// Renew the lease until it is lost, passing on the revocation the server reports.
+/// Failing to reach the server is retried at the next beat, until the lease would have expired.
+async fn heartbeat(
+    keeper: Keeper,
+    interval: Duration,
+    leasetime: Duration,
+    lost: watch::Sender<Option<LeaseLost>>,
+    revocation: watch::Sender<Option<Revocation>>,
+) {
+    let mut renewed = Instant::now();
+    loop {
+        sleep(interval).await;
+        match keeper.renew().await {
+            Ok(renewal) => {
+                renewed = Instant::now();
+                // a revocation called off is reported as none
+                let notice = renewal.revocation.map(Revocation::from);
+                revocation.send_if_modified(|current| {
+                    let modified =
+                        current.as_ref().map(|r| &r.by) != notice.as_ref().map(|r| &r.by);
+                    if modified {
+                        *current = notice;
+                    }
+                    modified
+                });
+            }
+            Err(ClientResourceRequestError::NotHeld(message)) => {
+                lost.send_replace(Some(LeaseLost::Ended(message)));
+                return;
+            }
+            Err(e) if renewed.elapsed() >= leasetime => {
+                lost.send_replace(Some(LeaseLost::Unreachable(e.to_string())));
+                return;
+            }
+            Err(e) => warn!(error = %e, "could not renew the lease"),
+        }
+    }
+}
@@ -1,9 +1,24 @@
+pub mod export;
 pub mod http;
-use crate::client::http::{build_query, try_request};
+pub mod lease;
+use crate::auth::Credentials;
+use crate::client::http::{HttpClient, Timeouts};
+use crate::client::lease::{Keeper, RemoteLease};
+use crate::query::LockQuery;
 
-use crate::inventory::{PoolLease, ResourceRequest, ResourceRequestError};
+use crate::inventory::{
+    InventoryStatus, LeaseStatus, PoolDetails, ResourceRequest, ResourceRequestError, Wait,
+};
+use crate::tls::TlsError;
 
 use thiserror::Error;
+use tokio::time::{Duration, Instant, sleep};
+use tokio_rustls::TlsConnector;
+use tracing::{debug, warn};
+
+/// Backoff between the attempts to reach a server that can't be reached
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
 
 #[derive(Error, Debug)]
 pub enum ClientResourceRequestError {
@@ -21,14 +36,72 @@ pub enum ClientResourceRequestError {
     HyperError(#[from] hyper::Error),
     #[error("IO error")]
     IoError(#[from] std::io::Error),
+    #[error("The server did not accept the credentials")]
+    Unauthorized,
+    #[error("TLS error: {0}")]
+    TlsError(#[from] TlsError),
+    #[error("Connection error: {0}")]
+    ConnectionError(#[from] hyper_util::client::legacy::Error),
+    #[error("The server did not answer in time")]
+    TimedOut,
+    #[error("The lease is not held: {0}")]
+    NotHeld(String),
+    #[error("The server refused with {status}: {message}")]
+    Refused {
+        status: hyper::StatusCode,
+        message: String,
+    },
+}
+
+impl ClientResourceRequestError {
+    /// Whether the server could not be reached, so the request was never sent.
+    /// Asking again is safe then. A connection lost after the request was sent is not
+    /// one of those: the server may have granted the lease already, asking again would
+    /// leave that lease with nobody to release it.
+    /// A failing TLS handshake is not one either, it fails again.
+    pub fn is_connection_failure(&self) -> bool {
+        let ClientResourceRequestError::ConnectionError(e) = self else {
+            return false;
+        };
+        e.is_connect() && unreachable(e)
+    }
+}
+
+/// Whether the network failed somewhere in the chain of errors
+fn unreachable(error: &(dyn std::error::Error + 'static)) -> bool {
+    use std::io::ErrorKind;
+    let mut source = Some(error);
+    while let Some(error) = source {
+        if let Some(error) = error.downcast_ref::<std::io::Error>() {
+            return matches!(
+                error.kind(),
+                ErrorKind::ConnectionRefused
+                    | ErrorKind::ConnectionReset
+                    | ErrorKind::ConnectionAborted
+                    | ErrorKind::NotConnected
+                    | ErrorKind::TimedOut
+                    | ErrorKind::HostUnreachable
+                    | ErrorKind::NetworkUnreachable
+            );
+        }
+        source = error.source();
+    }
+    false
 }
 pub struct RemoteRespoClientFactory {
     url: String,
+    credentials: Option<Credentials>,
+    tls: Option<TlsConnector>,
+    http: HttpClient, // shared by the clients, and so are its connections
+    heartbeat: Option<Duration>,
 }
 
 pub struct RemoteRespoClient {
     name: String,
     url: String,
+    credentials: Option<Credentials>,
+    http: HttpClient,
+    heartbeat: Option<Duration>, // a third of the lease time when not set
 }
 pub fn create_client_name() -> String {
     format!(
@@ -39,27 +112,152 @@ pub fn create_client_name() -> String {
 }
 
 impl RemoteRespoClient {
+    /// Request a lease, kept by the server while the returned guard is held.
+    /// Connection failures are retried with backoff for as long as the request
+    /// waits, or the retry timeout when that is longer.
     pub async fn request(
         &mut self,
         request: &ResourceRequest,
-    ) -> Result<PoolLease, ClientResourceRequestError> {
-        println!("request: {:?}", request);
-        let request = build_query(Some(self.name.clone()), request);
-        try_request(format!("{}?{}", self.url, request)).await
-        // TODO: launch a thread keeping the connection alive.
-        // shutdown the thread in the drop() of the lease.
+    ) -> Result<RemoteLease, ClientResourceRequestError> {
+        debug!(?request, client = %self.name, "request");
+        let query = LockQuery {
+            client_name: Some(self.name.clone()),
+            keep: true,
+            ..Default::default()
+        };
+        let url = format!("{}?{}", self.url, query.encode());
+        let started = Instant::now();
+        let retry = self.http.timeouts().retry;
+        let retry_until = match request.wait {
+            Wait::NoWait => Some(started + retry),
+            Wait::For(wait) => started.checked_add(wait.max(retry)),
+            Wait::Forever => None,
+        };
+        let mut attempt = request.clone();
+        let mut backoff = INITIAL_BACKOFF;
+        loop {
+            match self
+                .http
+                .try_request(&url, &attempt, self.credentials.as_ref())
+                .await
+            {
+                Err(e)
+                    if e.is_connection_failure()
+                        && retry_until.is_none_or(|until| Instant::now() + backoff < until) =>
+                {
+                    warn!(error = %e, ?backoff, "server unreachable, retrying");
+                    sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                    // the time spent retrying counts as waiting
+                    if let Wait::For(wait) = request.wait {
+                        attempt.wait = Wait::For(wait.saturating_sub(started.elapsed()));
+                    }
+                }
+                result => {
+                    let lease = result?;
+                    let keeper = Keeper {
+                        http: self.http.clone(),
+                        credentials: self.credentials.clone(),
+                        renew_url: endpoint_url(&self.url, &format!("renew?lease={}", lease.id())),
+                        unlock_url: endpoint_url(
+                            &self.url,
+                            &format!("unlock?lease={}", lease.id()),
+                        ),
+                    };
+                    return Ok(RemoteLease::new(lease, keeper, self.heartbeat));
+                }
+            }
+        }
+    }
+}
+
+impl RemoteRespoClient {
+    /// The pools the client may use with their holder and queue, the use of the quotas
+    /// and the leases on those pools
+    pub async fn status(&self) -> Result<InventoryStatus, ClientResourceRequestError> {
+        let query = url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("client_name", &self.name)
+            .finish();
+        let url = endpoint_url(&self.url, &format!("status?{query}"));
+        self.http.get(&url, self.credentials.as_ref()).await
+    }
+
+    /// The pool in full, with the properties of its resources.
+    /// Refused with 404 Not Found when there is no such pool, or the client may not use it.
+    pub async fn pool(&self, name: &str) -> Result<PoolDetails, ClientResourceRequestError> {
+        let query = url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("name", name)
+            .append_pair("client_name", &self.name)
+            .finish();
+        let url = endpoint_url(&self.url, &format!("pool?{query}"));
+        self.http.get(&url, self.credentials.as_ref()).await
+    }
+
+    /// The leases of the client, by its name or the identity the server knows it by
+    pub async fn leases(&self) -> Result<Vec<LeaseStatus>, ClientResourceRequestError> {
+        let query = url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("mine", "true")
+            .append_pair("client_name", &self.name)
+            .finish();
+        let url = endpoint_url(&self.url, &format!("leases?{query}"));
+        self.http.get(&url, self.credentials.as_ref()).await
+    }
+}
+
+/// The url of another endpoint of the server of the `/lock` url, which may be the bare server url
+fn endpoint_url(lock_url: &str, endpoint: &str) -> String {
+    let authority = lock_url.find("://").map_or(0, |scheme| scheme + 3);
+    match lock_url[authority..].rfind('/') {
+        Some(path) => format!("{}/{endpoint}", &lock_url[..authority + path]),
+        None => format!("{lock_url}/{endpoint}"),
     }
 }
 
 impl RemoteRespoClientFactory {
     pub fn new(url: String) -> RemoteRespoClientFactory {
-        RemoteRespoClientFactory { url }
+        RemoteRespoClientFactory {
+            url,
+            credentials: None,
+            tls: None,
+            http: HttpClient::new(None, Timeouts::default()),
+            heartbeat: None,
+        }
+    }
+
+    /// Authenticate the requests of the clients, see `Credentials::from_env`
+    pub fn with_credentials(
+        mut self,
+        credentials: Option<Credentials>,
+    ) -> RemoteRespoClientFactory {
+        self.credentials = credentials;
+        self
+    }
+
+    /// Connect to `https://` servers with this connector, see `ClientTlsConfig::connector`
+    pub fn with_tls(mut self, connector: TlsConnector) -> RemoteRespoClientFactory {
+        self.tls = Some(connector);
+        self.http = HttpClient::new(self.tls.clone(), *self.http.timeouts());
+        self
+    }
+
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> RemoteRespoClientFactory {
+        self.http = HttpClient::new(self.tls.clone(), timeouts);
+        self
+    }
+
+    /// How often the leases are renewed, a third of their lease time by default
+    pub fn with_heartbeat(mut self, interval: Duration) -> RemoteRespoClientFactory {
+        self.heartbeat = Some(interval);
+        self
     }
 
     pub fn create(&mut self, name: String) -> RemoteRespoClient {
         RemoteRespoClient {
             name,
             url: self.url.clone(),
+            credentials: self.credentials.clone(),
+            http: self.http.clone(),
+            heartbeat: self.heartbeat,
         }
     }
 }
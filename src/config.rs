@@ -1,5 +1,12 @@
 use crate::inventory::Inventory;
+use crate::preemption::PreemptionPolicy;
+use crate::quotas::Quota;
+use crate::server::auth::AuthConfig;
+use crate::server::tls::TlsConfig;
+use crate::server::webhooks::WebhookConfig;
+use serde::Deserialize;
 use serde_saphyr::from_reader;
+use std::path::PathBuf;
 pub struct InventoryLoader;
 
 impl InventoryLoader {
@@ -10,3 +17,38 @@ impl InventoryLoader {
         parsed
     }
 }
+
+/// The server configuration file: the inventory, plus the settings of the server itself.
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct ServerConfig {
+    #[serde(flatten)]
+    pub inventory: Inventory,
+    #[serde(default)]
+    pub webhooks: Vec<WebhookConfig>,
+    /// file keeping the webhook deliveries that are not done yet, so they survive a restart
+    #[serde(default)]
+    pub webhook_outbox: Option<PathBuf>,
+    /// JSON lines file every lease is appended to when it ends, the source of the usage reports
+    #[serde(default)]
+    pub lease_history: Option<PathBuf>,
+    /// when given, only authenticated clients are served
+    #[serde(default)]
+    pub auth: Option<AuthConfig>,
+    /// when given, the server only speaks https
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    #[serde(default)]
+    pub quotas: Vec<Quota>,
+    /// when given, urgent requests may revoke preemptible leases
+    #[serde(default)]
+    pub preemption: Option<PreemptionPolicy>,
+}
+
+pub struct ServerConfigLoader;
+
+impl ServerConfigLoader {
+    pub fn load<T: std::io::Read>(file: T) -> ServerConfig {
+        let parsed: ServerConfig = from_reader(file).unwrap();
+        parsed
+    }
+}
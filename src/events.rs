@@ -0,0 +1,107 @@
+//! Events published by the inventory actor, and the filter subscribers use to pick theirs.
+use crate::inventory::Pool;
+use serde::{Deserialize, Serialize};
+
+/// What happened to the inventory, published by the actor in the order it happened
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event")]
+pub enum InventoryEvent {
+    LeaseGranted {
+        lease_id: u64,
+        pool: String,
+        client: String,
+    },
+    LeaseReleased {
+        lease_id: u64,
+        pool: String,
+        client: String,
+    },
+    LeaseExpired {
+        lease_id: u64,
+        pool: String,
+        client: String,
+    },
+    /// The warning to the holder: the lease is revoked for `by` after the grace period
+    LeasePreempted {
+        lease_id: u64,
+        pool: String,
+        client: String,
+        by: String,
+        grace_seconds: u64,
+    },
+    LeaseRevoked {
+        lease_id: u64,
+        pool: String,
+        client: String,
+    },
+    Queued {
+        client: String,
+        pools: Vec<String>,
+    },
+    InventoryUpdated,
+    MaintenanceChanged {
+        pool: String,
+        maintenance: bool,
+    },
+}
+
+impl InventoryEvent {
+    /// The name of the event, as used in the `event` tag of its serialisation
+    pub fn name(&self) -> &'static str {
+        match self {
+            InventoryEvent::LeaseGranted { .. } => "LeaseGranted",
+            InventoryEvent::LeaseReleased { .. } => "LeaseReleased",
+            InventoryEvent::LeaseExpired { .. } => "LeaseExpired",
+            InventoryEvent::LeasePreempted { .. } => "LeasePreempted",
+            InventoryEvent::LeaseRevoked { .. } => "LeaseRevoked",
+            InventoryEvent::Queued { .. } => "Queued",
+            InventoryEvent::InventoryUpdated => "InventoryUpdated",
+            InventoryEvent::MaintenanceChanged { .. } => "MaintenanceChanged",
+        }
+    }
+
+    /// The pools the event is about, empty when it is about the whole inventory
+    pub fn pools(&self) -> Vec<&str> {
+        match self {
+            InventoryEvent::LeaseGranted { pool, .. }
+            | InventoryEvent::LeaseReleased { pool, .. }
+            | InventoryEvent::LeaseExpired { pool, .. }
+            | InventoryEvent::LeasePreempted { pool, .. }
+            | InventoryEvent::LeaseRevoked { pool, .. }
+            | InventoryEvent::MaintenanceChanged { pool, .. } => vec![pool.as_str()],
+            InventoryEvent::Queued { pools, .. } => pools.iter().map(String::as_str).collect(),
+            InventoryEvent::InventoryUpdated => vec![],
+        }
+    }
+}
+
+/// Selects the events about a pool and/or the pools at a location.
+/// Events about the whole inventory pass any filter.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EventFilter {
+    pub pool: Option<String>,
+    pub location: Option<String>,
+}
+
+impl EventFilter {
+    pub fn matches(&self, event: &InventoryEvent, pools: &[Pool]) -> bool {
+        let concerned = event.pools();
+        if concerned.is_empty() {
+            return true;
+        }
+        concerned.iter().any(|name| {
+            if let Some(wanted_pool) = &self.pool
+                && wanted_pool != name
+            {
+                return false;
+            }
+            if let Some(wanted_location) = &self.location {
+                // pools removed from the inventory have no location anymore
+                return pools
+                    .iter()
+                    .any(|pool| pool.name == *name && pool.location == *wanted_location);
+            }
+            true
+        })
+    }
+}
@@ -0,0 +1,244 @@
+//! Lease history: every lease that ended is appended to a JSON lines file,
+//! the usage reports are aggregated from it.
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Days, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tracing::{error, warn};
+
+/// A lease that ended, who held which pool, when, how long and how long they waited for it
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct LeaseHistoryEntry {
+    pub lease_id: u64,
+    pub client: String,
+    pub pool: String,
+    pub location: String,
+    pub requested_at: DateTime<Utc>,
+    pub granted_at: DateTime<Utc>,
+    pub ended_at: DateTime<Utc>,
+    pub waited_seconds: f64,
+    pub held_seconds: f64,
+    /// the lease ran out instead of being released
+    pub expired: bool,
+}
+
+/// The append-only history file. Entries are written by a background task,
+/// in the order the leases ended.
+#[derive(Debug, Clone)]
+pub struct LeaseHistory {
+    path: PathBuf,
+    entries: mpsc::UnboundedSender<LeaseHistoryEntry>,
+}
+
+impl LeaseHistory {
+    /// Start appending to the file, this has to be called from within a tokio runtime
+    pub fn open(path: PathBuf) -> LeaseHistory {
+        let (entries, mut receiver) = mpsc::unbounded_channel::<LeaseHistoryEntry>();
+        let file_path = path.clone();
+        tokio::spawn(async move {
+            while let Some(entry) = receiver.recv().await {
+                let mut line = serde_json::to_vec(&entry).expect("entries serialize to json");
+                line.push(b'\n');
+                let written = async {
+                    let mut file = OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(&file_path)
+                        .await?;
+                    file.write_all(&line).await?;
+                    file.flush().await
+                };
+                if let Err(e) = written.await {
+                    error!(
+                        lease_id = entry.lease_id,
+                        "could not write the lease to the history {:?}: {}", file_path, e
+                    );
+                }
+            }
+        });
+        LeaseHistory { path, entries }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub(crate) fn record(&self, entry: LeaseHistoryEntry) {
+        let _ = self.entries.send(entry);
+    }
+
+    /// The entries of leases that overlap the period. Lines that can't be read,
+    /// like one cut short by a crash, are skipped with a warning.
+    pub async fn read(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> io::Result<Vec<LeaseHistoryEntry>> {
+        let content = match tokio::fs::read_to_string(&self.path).await {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let mut entries = Vec::new();
+        for (index, line) in content.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry: LeaseHistoryEntry = match serde_json::from_str(line) {
+                Ok(entry) => entry,
+                Err(e) => {
+                    warn!(path = ?self.path, line = index + 1, "skipping a history line: {}", e);
+                    continue;
+                }
+            };
+            if entry.granted_at < to && entry.ended_at > from {
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+}
+
+/// What the usage is aggregated by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UsageGrouping {
+    Pool,
+    Location,
+    User,
+    Day,
+}
+
+impl std::str::FromStr for UsageGrouping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pool" => Ok(UsageGrouping::Pool),
+            "location" => Ok(UsageGrouping::Location),
+            "user" => Ok(UsageGrouping::User),
+            "day" => Ok(UsageGrouping::Day),
+            _ => Err(format!("cannot group by {:?}", s)),
+        }
+    }
+}
+
+/// One line of the usage report, only the fields grouped by are set
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct UsageRow {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pool: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub day: Option<NaiveDate>,
+    /// leases granted in the period
+    pub leases: u64,
+    pub held_seconds: f64,
+    /// total waiting time of the leases granted in the period
+    pub waited_seconds: f64,
+    /// held time divided by the length of the period, or of the day:
+    /// the average number of pools of the group in use
+    pub utilisation: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct UsageReport {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub rows: Vec<UsageRow>,
+}
+
+/// pool, location, user and day of a row
+type UsageKey = (
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<NaiveDate>,
+);
+
+fn seconds(from: DateTime<Utc>, to: DateTime<Utc>) -> f64 {
+    (to - from).num_milliseconds().max(0) as f64 / 1000.0
+}
+
+fn start_of(day: NaiveDate) -> DateTime<Utc> {
+    day.and_hms_opt(0, 0, 0).expect("midnight exists").and_utc()
+}
+
+/// Aggregate the history over the period. The held time of a lease is clipped to the
+/// period and, when grouping by day, split over the days (UTC) it spans.
+pub fn usage_report(
+    entries: &[LeaseHistoryEntry],
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    grouping: &[UsageGrouping],
+) -> UsageReport {
+    let by = |grouping_key| grouping.contains(&grouping_key);
+    let mut rows: BTreeMap<UsageKey, UsageRow> = BTreeMap::new();
+
+    for entry in entries {
+        let start = entry.granted_at.max(from);
+        let end = entry.ended_at.min(to);
+        if start >= end {
+            continue;
+        }
+        // the pieces of the lease to account for, with the day they're in when grouping by day
+        let mut pieces: Vec<(Option<NaiveDate>, DateTime<Utc>, DateTime<Utc>)> = Vec::new();
+        if by(UsageGrouping::Day) {
+            let mut day = start.date_naive();
+            while start_of(day) < end {
+                let next = day + Days::new(1);
+                pieces.push((Some(day), start.max(start_of(day)), end.min(start_of(next))));
+                day = next;
+            }
+        } else {
+            pieces.push((None, start, end));
+        }
+        for (day, piece_start, piece_end) in pieces {
+            let key = (
+                by(UsageGrouping::Pool).then(|| entry.pool.clone()),
+                by(UsageGrouping::Location).then(|| entry.location.clone()),
+                by(UsageGrouping::User).then(|| entry.client.clone()),
+                day,
+            );
+            let row = rows.entry(key.clone()).or_insert_with(|| UsageRow {
+                pool: key.0,
+                location: key.1,
+                user: key.2,
+                day: key.3,
+                ..Default::default()
+            });
+            row.held_seconds += seconds(piece_start, piece_end);
+            // the lease counts on the day, and in the period, it was granted
+            if entry.granted_at == piece_start {
+                row.leases += 1;
+                row.waited_seconds += entry.waited_seconds;
+            }
+        }
+    }
+
+    let rows = rows
+        .into_values()
+        .map(|mut row| {
+            let window = match row.day {
+                Some(day) => seconds(
+                    start_of(day).max(from),
+                    start_of(day + Days::new(1)).min(to),
+                ),
+                None => seconds(from, to),
+            };
+            if window > 0.0 {
+                row.utilisation = row.held_seconds / window;
+            }
+            row
+        })
+        .collect();
+    UsageReport { from, to, rows }
+}
@@ -6,12 +6,17 @@ This crate provides core logic for a resource leasing system, where clients can
 requests to available resources, handling pool attributes, resource attributes, and location constraints.
 
 Main Components:
-- Resource: Represents an individual entity with attributes and properties.
+- Resource: Represents an individual entity with attributes and properties, free form YAML/JSON values.
 - Pool: A collection of resources, with its own attributes and location.
 - Inventory: Holds all pools
-- InventoryManager: Manages resource allocation.
+- InventoryManager: Handle to the inventory actor, a task that owns the lease state, queues the clients
+  waiting for pools and expires leases. It accepts commands over a channel and publishes InventoryEvents.
 - ResourceRequest: Describes a client's requirements for resource allocation.
+- Wait: How long a request waits for matching pools in use: not at all, up to a duration, or until served.
+- Caller: The client making a request and its groups; a pool can be restricted to some users and groups.
 - PoolLease: Represents a successful lease of a pool, including resource pairing.
+  The lease is the ownership token: dropping it releases the pool.
+  A preemptible lease can be revoked for a request of higher priority, see the preemption module.
 - InventoryResourceRequest: Trait for handling resource requests and matching logic.
 
 Matching Logic:
@@ -23,51 +28,279 @@ Unit tests are provided for core matching scenarios.
 See README.md for usage, roadmap, and further details.
 */
 
-use derivative::Derivative;
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::sync::{Arc, Weak};
+use crate::events::InventoryEvent;
+use crate::history::{LeaseHistory, LeaseHistoryEntry};
+use crate::metrics::Metrics;
+use crate::preemption::{PreemptionPolicy, Revocation};
+use crate::quotas::{Quota, QuotaUsage};
+use arc_swap::ArcSwap;
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::sync::Arc;
 use thiserror::Error;
-use tokio::sync::{Mutex, Notify};
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
 use tokio::time::{Duration, Instant, sleep_until};
+use tracing::{Instrument, Span, debug, info, info_span};
 
-const DEFAULT_LEASE_TIME: Duration = Duration::from_secs(1234); // TODO: read default lease time from config file
+/// Lease time of the leases handed out by the server
+pub const DEFAULT_LEASE_TIME: Duration = Duration::from_secs(1234); // TODO: read default lease time from config file
+const EVENT_CAPACITY: usize = 1024;
+/// How often the waiters are checked for clients that went away
+const ABANDONED_SWEEP_INTERVAL: Duration = Duration::from_secs(10);
 
 type AttributeSet = Vec<String>; // TODO:  Use BTreeSet
 type AttributeMatch = Vec<(AttributeSet, Resource)>;
+type LeaseReply = oneshot::Sender<Result<PoolLease, ResourceRequestError>>;
+
+type Properties = HashMap<String, serde_json::Value>;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Resource {
+    /// unique in its pool; in a list of resources the position when not given
+    #[serde(default)]
+    pub name: String,
     pub attributes: AttributeSet,
-    pub properties: HashMap<String, String>,
+    /// free form data of the user domain: strings, numbers, lists and maps
+    pub properties: Properties,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Derivative)]
-#[derivative(PartialEq)]
+impl Resource {
+    /// The properties read into a user defined struct, like `{ socket: String }`
+    pub fn properties_as<T: DeserializeOwned>(&self) -> Result<T, serde_json::Error> {
+        serde_json::from_value(serde_json::to_value(&self.properties)?)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Pool {
+    /// given by the key in a map of pools, required in a list of them
+    #[serde(default)]
     pub name: String,
     pub attributes: AttributeSet,
     pub location: String,
+    /// a list, or a map keyed by resource name
+    #[serde(alias = "entities", deserialize_with = "list_or_map")]
     pub resources: Vec<Resource>,
-    #[serde(skip_serializing, skip_deserializing)]
-    #[derivative(PartialEq = "ignore")]
-    pub user: Weak<Mutex<InnerClient>>,
+    /// users and groups that may lease the pool, anybody may when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed: Vec<String>,
+    /// free form data of the user domain, like for resources
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub properties: Properties,
+}
+
+impl Pool {
+    /// The properties read into a user defined struct
+    pub fn properties_as<T: DeserializeOwned>(&self) -> Result<T, serde_json::Error> {
+        serde_json::from_value(serde_json::to_value(&self.properties)?)
+    }
 }
 
 #[derive(Debug, Deserialize, PartialEq)]
 pub struct Inventory {
+    /// a list, or a map keyed by pool name
+    #[serde(deserialize_with = "list_or_map")]
     pub pools: Vec<Pool>,
 }
-#[derive(Debug, Clone)]
-pub struct InventoryManager(pub Arc<Mutex<Inventory>>);
 
-#[derive(Debug, Clone, Default, Serialize)]
+/// Pools and resources, which can be written as map keyed by their name
+trait Named {
+    fn set_name(&mut self, name: String);
+    /// named after its position in a list, when not named
+    fn set_default_name(&mut self, _index: usize) {}
+}
+
+impl Named for Pool {
+    fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+}
+
+impl Named for Resource {
+    fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+
+    fn set_default_name(&mut self, index: usize) {
+        if self.name.is_empty() {
+            self.name = index.to_string();
+        }
+    }
+}
+
+/// A list of items, or a map of them keyed by name, in the order written
+fn list_or_map<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + Named,
+{
+    struct ListOrMap<T>(std::marker::PhantomData<T>);
+
+    impl<'de, T: Deserialize<'de> + Named> serde::de::Visitor<'de> for ListOrMap<T> {
+        type Value = Vec<T>;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str("a list, or a map keyed by name")
+        }
+
+        fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<T>, A::Error> {
+            let mut items = Vec::new();
+            while let Some(mut item) = seq.next_element::<T>()? {
+                item.set_default_name(items.len());
+                items.push(item);
+            }
+            Ok(items)
+        }
+
+        fn visit_map<A: serde::de::MapAccess<'de>>(self, mut map: A) -> Result<Vec<T>, A::Error> {
+            let mut items = Vec::new();
+            while let Some((name, mut item)) = map.next_entry::<String, T>()? {
+                item.set_name(name);
+                items.push(item);
+            }
+            Ok(items)
+        }
+    }
+
+    deserializer.deserialize_any(ListOrMap(std::marker::PhantomData))
+}
+
+#[derive(Debug, Serialize, Deserialize, Error)]
+pub enum InventoryError {
+    #[error("Pool {0} of the list has no name")]
+    UnnamedPool(usize),
+    #[error("Pool name {0:?} is used more than once")]
+    DuplicatePoolName(String),
+    #[error("There is no pool named {0:?}")]
+    UnknownPool(String),
+    #[error("Resource name {resource:?} is used more than once in pool {pool:?}")]
+    DuplicateResourceName { pool: String, resource: String },
+}
+
+impl Inventory {
+    /// Pools are addressed by name, so they need one and names have to be unique,
+    /// and so do the names of the resources of a pool
+    pub fn validate(&self) -> Result<(), InventoryError> {
+        let mut names = HashSet::new();
+        for (index, pool) in self.pools.iter().enumerate() {
+            if pool.name.is_empty() {
+                return Err(InventoryError::UnnamedPool(index));
+            }
+            if !names.insert(&pool.name) {
+                return Err(InventoryError::DuplicatePoolName(pool.name.clone()));
+            }
+            let mut resource_names = HashSet::new();
+            for resource in &pool.resources {
+                if !resource_names.insert(&resource.name) {
+                    return Err(InventoryError::DuplicateResourceName {
+                        pool: pool.name.clone(),
+                        resource: resource.name.clone(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Who asks for a pool: the client name and the groups it is a member of
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Caller {
+    pub name: String,
+    pub groups: Vec<String>,
+}
+
+impl Caller {
+    pub fn new(name: &str) -> Caller {
+        Caller {
+            name: name.into(),
+            groups: Vec::new(),
+        }
+    }
+
+    /// Whether the pool allows the caller, by name or through one of its groups
+    pub fn may_use(&self, pool: &Pool) -> bool {
+        pool.allowed.is_empty()
+            || pool
+                .allowed
+                .iter()
+                .any(|allowed| *allowed == self.name || self.groups.contains(allowed))
+    }
+}
+
+/// How long a request waits when the pools matching it are in use
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Wait {
+    /// fail with `InUse` right away
+    #[default]
+    NoWait,
+    /// queue, and fail with `TimeOut` when not served in time
+    For(Duration),
+    /// queue until served
+    Forever,
+}
+
+#[derive(Debug, Error)]
+#[error("Invalid wait {0:?}: expected nowait, forever, or a duration like 90s or 2h")]
+pub struct WaitParseError(String);
+
+impl std::str::FromStr for Wait {
+    type Err = WaitParseError;
+
+    /// `nowait`, `forever`, a humantime duration like `1h 30m`, or a number of seconds
+    fn from_str(s: &str) -> Result<Wait, WaitParseError> {
+        match s.trim() {
+            "nowait" => Ok(Wait::NoWait),
+            "forever" => Ok(Wait::Forever),
+            s => match s.parse::<u64>() {
+                Ok(seconds) => Ok(Wait::For(Duration::from_secs(seconds))),
+                Err(_) => humantime::parse_duration(s)
+                    .map(Wait::For)
+                    .map_err(|_| WaitParseError(s.into())),
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for Wait {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Wait::NoWait => f.write_str("nowait"),
+            Wait::For(duration) => write!(f, "{}", humantime::format_duration(*duration)),
+            Wait::Forever => f.write_str("forever"),
+        }
+    }
+}
+
+impl Serialize for Wait {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Wait {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Wait, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// The body of `POST /lock`, missing fields take their default
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct ResourceRequest {
     pub location: Option<String>,
     pub pool_attributes: Option<Vec<String>>, // TODO:  Use btreeset
     pub resource_attributes: Option<Vec<AttributeSet>>,
-    pub timeout: Option<Duration>,
+    pub wait: Wait,
     pub by_name: Option<String>, // This will be used to take a pool offline for maintenance
+    /// urgency of the request, a high enough one may preempt leases of a lower one
+    pub priority: Option<u32>,
+    /// whether the lease may be revoked for an urgent request
+    pub preemptible: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, Error)]
@@ -80,43 +313,1318 @@ pub enum ResourceRequestError {
     InUse,
     #[error("The matching resource(s) are in use, and the given maximum time has been exceeded")]
     TimeOut,
+    #[error("The lease is not held: it was released, it expired or it was granted remotely")]
+    NotHeld,
+    #[error("The quota of {0:?} allows no more leases or waiting requests for now")]
+    QuotaExceeded(String),
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// A granted pool. Holding the lease is holding the pool: dropping it
+/// releases the pool and hands it over to the next client waiting for it.
+/// A lease received from a remote server carries no guard, see `client::lease::RemoteLease`.
+#[derive(Debug, Serialize, Deserialize)]
 pub struct PoolLease {
-    leasetime: Duration,
+    /// names the lease in the events, and on the server for a remote client keeping it
+    #[serde(default)]
+    id: u64,
+    leasetime: Option<Duration>,
+    #[serde(default)]
+    grace_period: Option<Duration>,
     pool: Pool,
     pairing: Option<AttributeMatch>,
     #[serde(skip_serializing, skip_deserializing)]
-    notify: Arc<Notify>,
+    guard: Option<LeaseGuard>,
 }
 
-impl Drop for PoolLease {
+#[derive(Debug)]
+struct LeaseGuard {
+    commands: mpsc::UnboundedSender<Command>,
+    lease_id: u64,
+    revocation: watch::Receiver<Option<Revocation>>,
+}
+
+impl Drop for LeaseGuard {
     fn drop(&mut self) {
-        println!("notifying waiters so they retry");
-        // the client should be dropped for this to work! not the lease!
-        self.notify.notify_waiters()
+        // the actor is gone when the runtime shuts down, there is nothing left to release then
+        let _ = self.commands.send(Command::Release {
+            lease_id: self.lease_id,
+        });
     }
 }
+
+impl PoolLease {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// How long the lease lasts without renewal, None when it lasts until released
+    pub fn leasetime(&self) -> Option<Duration> {
+        self.leasetime
+    }
+
+    /// How long the lease is kept once preempted, None when it is not preemptible
+    pub fn grace_period(&self) -> Option<Duration> {
+        self.grace_period
+    }
+
+    pub fn pool(&self) -> &Pool {
+        &self.pool
+    }
+
+    pub fn name(&self) -> &str {
+        &self.pool.name
+    }
+
+    pub fn location(&self) -> &str {
+        &self.pool.location
+    }
+
+    pub fn attributes(&self) -> &[String] {
+        &self.pool.attributes
+    }
+
+    /// All resources of the pool, also those no resource attributes were requested for
+    pub fn resources(&self) -> &[Resource] {
+        &self.pool.resources
+    }
+
+    pub fn resource(&self, name: &str) -> Option<&Resource> {
+        self.pool
+            .resources
+            .iter()
+            .find(|resource| resource.name == name)
+    }
+
+    /// The requested resource attributes, each with the resource it was paired with
+    pub fn pairing(&self) -> impl Iterator<Item = (&[String], &Resource)> {
+        self.pairing
+            .iter()
+            .flatten()
+            .map(|(spec, resource)| (spec.as_slice(), resource))
+    }
+
+    /// The resource paired with the requested resource attributes `spec`, in any order.
+    /// Without such a request, the first resource of the pool having the attributes.
+    pub fn resource_for<S: AsRef<str>>(
+        &self,
+        spec: impl IntoIterator<Item = S>,
+    ) -> Option<&Resource> {
+        let spec: Vec<String> = spec.into_iter().map(|s| s.as_ref().to_string()).collect();
+        self.pairing()
+            .find(|(requested, _)| requested.len() == spec.len() && matches(requested, &spec))
+            .map(|(_, resource)| resource)
+            .or_else(|| {
+                self.pool
+                    .resources
+                    .iter()
+                    .find(|resource| matches(&spec, &resource.attributes))
+            })
+    }
+
+    /// Release the pool now, equivalent to dropping the lease
+    pub fn release(mut self) {
+        self.guard.take();
+    }
+
+    /// Extend the lease by its lease time, counting from now. A lease without lease time
+    /// is renewed as long as it is held.
+    pub async fn renew(&self) -> Result<(), ResourceRequestError> {
+        let guard = self.guard.as_ref().ok_or(ResourceRequestError::NotHeld)?;
+        let (reply, receiver) = oneshot::channel();
+        guard
+            .commands
+            .send(Command::Renew {
+                lease_id: guard.lease_id,
+                reply,
+            })
+            .map_err(|_| ResourceRequestError::NotHeld)?;
+        receiver.await.unwrap_or(Err(ResourceRequestError::NotHeld))
+    }
+
+    /// Resolves when the lease is preempted, with when it will be revoked.
+    /// Resolves to None when the lease ends otherwise, or carries no guard.
+    /// A revocation is called off when the request it was for goes away.
+    pub async fn revocation(&self) -> Option<Revocation> {
+        let mut receiver = self.guard.as_ref()?.revocation.clone();
+        receiver.wait_for(Option::is_some).await.ok()?.clone()
+    }
+
+    /// The revocation announced for the lease, if any, without waiting for one
+    pub fn pending_revocation(&self) -> Option<Revocation> {
+        self.guard.as_ref()?.revocation.borrow().clone()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PoolStatus {
+    pub name: String,
+    pub location: String,
+    pub attributes: AttributeSet,
+    pub holder: Option<String>,
+    pub queue_length: usize,
+    pub maintenance: bool,
+}
+
+/// A lease being held
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct LeaseStatus {
+    pub lease_id: u64,
+    pub pool: String,
+    pub location: String,
+    pub client: String,
+    pub granted_at: DateTime<Utc>,
+    /// None when the lease lasts until released
+    pub expires_at: Option<DateTime<Utc>>,
+    pub preemptible: bool,
+    /// when it is revoked, once preempted
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct InventoryStatus {
+    pub pools: Vec<PoolStatus>,
+    #[serde(default)]
+    pub quotas: Vec<QuotaUsage>,
+    /// by lease id
+    #[serde(default)]
+    pub leases: Vec<LeaseStatus>,
+}
+
+/// A pool in full: its description, its status and the lease on it
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PoolDetails {
+    pub pool: Pool,
+    pub status: PoolStatus,
+    pub lease: Option<LeaseStatus>,
+}
+
+#[derive(Debug)]
+enum Command {
+    Request {
+        request: Box<ResourceRequest>,
+        caller: Caller,
+        leasetime: Option<Duration>,
+        span: Span, // of the caller, the parent of the lease span
+        reply: LeaseReply,
+    },
+    Release {
+        lease_id: u64,
+    },
+    Renew {
+        lease_id: u64,
+        reply: oneshot::Sender<Result<(), ResourceRequestError>>,
+    },
+    Status {
+        reply: oneshot::Sender<InventoryStatus>,
+    },
+    UpdateInventory {
+        inventory: Inventory,
+        reply: oneshot::Sender<Result<(), InventoryError>>,
+    },
+    SetMaintenance {
+        pool: String,
+        maintenance: bool,
+        reply: oneshot::Sender<Result<(), InventoryError>>,
+    },
+    ForceRelease {
+        pool: String,
+        reply: oneshot::Sender<Result<(), InventoryError>>,
+    },
+    SetQuotas {
+        quotas: Vec<Quota>,
+        reply: oneshot::Sender<()>,
+    },
+    SetPreemption {
+        policy: Option<PreemptionPolicy>,
+        reply: oneshot::Sender<()>,
+    },
+}
+
+/// Handle to the inventory actor.
+/// The actor stops once all handles and leases are dropped.
+#[derive(Debug, Clone)]
+pub struct InventoryManager {
+    commands: mpsc::UnboundedSender<Command>,
+    pools: Arc<ArcSwap<Vec<Pool>>>, // description of the pools, readable without asking the actor
+    events: broadcast::Sender<InventoryEvent>,
+    metrics: Metrics,
+    history: Option<LeaseHistory>,
+}
+
 impl InventoryManager {
+    /// Spawns the actor, this has to be called from within a tokio runtime
     pub fn new(inner: Inventory) -> InventoryManager {
-        InventoryManager(Arc::new(Mutex::new(inner)))
+        Self::start(inner, None)
+    }
+
+    /// Like `new`, recording every lease that ends in the history
+    pub fn with_history(inner: Inventory, history: LeaseHistory) -> InventoryManager {
+        Self::start(inner, Some(history))
+    }
+
+    fn start(inner: Inventory, history: Option<LeaseHistory>) -> InventoryManager {
+        let (commands, receiver) = mpsc::unbounded_channel();
+        let pools = Arc::new(ArcSwap::from_pointee(inner.pools));
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        let metrics = Metrics::new();
+        let actor = InventoryActor {
+            commands: receiver,
+            handle: commands.downgrade(),
+            slots: pools.load().iter().map(|_| PoolSlot::default()).collect(),
+            pools: pools.clone(),
+            leases: HashMap::new(),
+            waiters: BTreeMap::new(),
+            deadlines: BTreeSet::new(),
+            events: events.clone(),
+            metrics: metrics.clone(),
+            history: history.clone(),
+            quotas: Vec::new(),
+            preemption: None,
+            held_today: HashMap::new(),
+            today: Utc::now().date_naive(),
+            day_started: start_of_day(),
+            sweep: None,
+            midnight: None,
+            next_id: 0,
+        };
+        tokio::spawn(actor.run());
+        InventoryManager {
+            commands,
+            pools,
+            events,
+            metrics,
+            history,
+        }
+    }
+
+    async fn call<T>(&self, command: impl FnOnce(oneshot::Sender<T>) -> Command) -> T {
+        let (reply, receiver) = oneshot::channel();
+        self.commands
+            .send(command(reply))
+            .expect("the inventory actor stopped");
+        receiver.await.expect("the inventory actor stopped")
+    }
+
+    /// Lease a pool for `client_name`. When the matching pools are in use and the request
+    /// waits, the client is queued until one of them is handed over or its wait is over.
+    pub async fn request_for(
+        &self,
+        request: &ResourceRequest,
+        client_name: &str,
+    ) -> Result<PoolLease, ResourceRequestError> {
+        self.request_as(request, &Caller::new(client_name)).await
+    }
+
+    /// Like `request_for`. Pools that don't allow the caller are treated as if they didn't exist.
+    pub async fn request_as(
+        &self,
+        request: &ResourceRequest,
+        caller: &Caller,
+    ) -> Result<PoolLease, ResourceRequestError> {
+        self.request_leased(request, caller, None).await
+    }
+
+    /// Like `request_as`, the lease expiring when it isn't renewed within `leasetime`.
+    /// For holders that may go away without releasing, like remote clients.
+    pub async fn request_expiring(
+        &self,
+        request: &ResourceRequest,
+        caller: &Caller,
+        leasetime: Duration,
+    ) -> Result<PoolLease, ResourceRequestError> {
+        self.request_leased(request, caller, Some(leasetime)).await
+    }
+
+    async fn request_leased(
+        &self,
+        request: &ResourceRequest,
+        caller: &Caller,
+        leasetime: Option<Duration>,
+    ) -> Result<PoolLease, ResourceRequestError> {
+        self.call(|reply| Command::Request {
+            request: Box::new(request.clone()),
+            caller: caller.clone(),
+            leasetime,
+            span: Span::current(),
+            reply,
+        })
+        .await
+    }
+
+    pub async fn status(&self) -> InventoryStatus {
+        self.call(|reply| Command::Status { reply }).await
+    }
+
+    /// The pool named `name` in full, None when there is no such pool
+    pub async fn pool(&self, name: &str) -> Option<PoolDetails> {
+        let status = self.status().await;
+        let pool = self.pools().iter().find(|pool| pool.name == name)?.clone();
+        Some(PoolDetails {
+            pool,
+            status: status.pools.into_iter().find(|pool| pool.name == name)?,
+            lease: status.leases.into_iter().find(|lease| lease.pool == name),
+        })
+    }
+
+    /// Returns whether the pool is currently leased
+    pub async fn is_leased(&self, pool_name: &str) -> bool {
+        self.status()
+            .await
+            .pools
+            .iter()
+            .any(|pool| pool.name == pool_name && pool.holder.is_some())
+    }
+
+    /// Replace the inventory. Leases on pools that keep their name carry over,
+    /// queued clients are matched again against the new pools.
+    pub async fn update_inventory(&self, inventory: Inventory) -> Result<(), InventoryError> {
+        inventory.validate()?;
+        self.call(|reply| Command::UpdateInventory { inventory, reply })
+            .await
+    }
+
+    /// Take a pool out of service, or put it back. A pool in maintenance is not granted,
+    /// clients keep waiting for it. A lease on it stays valid until released.
+    pub async fn set_maintenance(
+        &self,
+        pool: &str,
+        maintenance: bool,
+    ) -> Result<(), InventoryError> {
+        self.call(|reply| Command::SetMaintenance {
+            pool: pool.into(),
+            maintenance,
+            reply,
+        })
+        .await
+    }
+
+    /// End the lease on a pool, whoever holds it. The pool is handed over to the next client
+    /// waiting for it, the guard of the former holder no longer releases anything.
+    pub async fn force_release(&self, pool: &str) -> Result<(), InventoryError> {
+        self.call(|reply| Command::ForceRelease {
+            pool: pool.into(),
+            reply,
+        })
+        .await
+    }
+
+    /// Replace the quotas. Leases and waiters over the new quotas are left alone,
+    /// they only count against the next requests.
+    pub async fn set_quotas(&self, quotas: Vec<Quota>) {
+        self.call(|reply| Command::SetQuotas { quotas, reply })
+            .await
+    }
+
+    /// Allow urgent requests to preempt leases, or no longer when None.
+    /// Revocations already announced go ahead.
+    pub async fn set_preemption(&self, policy: Option<PreemptionPolicy>) {
+        self.call(|reply| Command::SetPreemption { policy, reply })
+            .await
+    }
+
+    /// The current description of the pools
+    pub fn pools(&self) -> Arc<Vec<Pool>> {
+        self.pools.load_full()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<InventoryEvent> {
+        self.events.subscribe()
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    pub fn history(&self) -> Option<&LeaseHistory> {
+        self.history.as_ref()
+    }
+}
+
+#[derive(Debug, Default)]
+struct PoolSlot {
+    lease_id: Option<u64>,
+    maintenance: bool,
+    queue: VecDeque<u64>, // ids of the waiters for this pool, first come first served
+}
+
+impl PoolSlot {
+    fn is_free(&self) -> bool {
+        self.lease_id.is_none() && !self.maintenance
+    }
+}
+
+#[derive(Debug)]
+struct LeaseRecord {
+    pool_index: Option<usize>, // None once the pool was removed from the inventory
+    caller: Caller,
+    pool: String, // kept for the history, the pool may be gone by the time the lease ends
+    location: String,
+    waited: Duration,
+    granted: Instant,
+    granted_at: DateTime<Utc>,
+    leasetime: Option<Duration>,
+    expires: Option<Instant>, // None when the lease lasts until released
+    priority: u32,
+    preemptible: bool,
+    revocation: watch::Sender<Option<Revocation>>,
+    revoking: Option<Revoking>,
+    span: Span,
+}
+
+/// A lease being preempted
+#[derive(Debug, Clone, Copy)]
+struct Revoking {
+    at: Instant,
+    waiter_id: u64, // of the request it is preempted for
+}
+
+/// A waiter is queued on every pool that could satisfy its request,
+/// the first of those pools to be released is handed over to it.
+#[derive(Debug)]
+struct WaiterRecord {
+    request: ResourceRequest,
+    caller: Caller,
+    leasetime: Option<Duration>, // of the lease to grant
+    arrived: Instant,
+    deadline: Option<Instant>,
+    span: Span,
+    reply: LeaseReply,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Deadline {
+    Lease(u64),
+    Waiter(u64),
+    Revoke(u64), // lease id
+    Sweep,
+    Midnight,
+}
+
+/// How a lease ended
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Ending {
+    Released,
+    Expired,
+    Revoked,
+}
+
+/// Single owner of the lease state. Commands are handled one at a time, in the order
+/// they were sent, so no locking is needed and the outcome doesn't depend on timing.
+struct InventoryActor {
+    commands: mpsc::UnboundedReceiver<Command>,
+    handle: mpsc::WeakUnboundedSender<Command>, // given to leases, weak so the actor can stop
+    pools: Arc<ArcSwap<Vec<Pool>>>,
+    slots: Vec<PoolSlot>, // same order as the pools
+    leases: HashMap<u64, LeaseRecord>,
+    waiters: BTreeMap<u64, WaiterRecord>, // ordered by arrival
+    deadlines: BTreeSet<(Instant, Deadline)>,
+    events: broadcast::Sender<InventoryEvent>,
+    metrics: Metrics,
+    history: Option<LeaseHistory>,
+    quotas: Vec<Quota>,
+    preemption: Option<PreemptionPolicy>,
+    held_today: HashMap<String, Duration>, // by quota subject, by the leases that ended since midnight
+    today: NaiveDate,
+    day_started: Option<Instant>, // None when today started before the first instant there is
+    sweep: Option<Instant>,       // scheduled while clients are waiting
+    midnight: Option<Instant>,    // scheduled while clients over their quota are waiting
+    next_id: u64,
+}
+
+impl InventoryActor {
+    async fn run(mut self) {
+        loop {
+            let next_deadline = self.deadlines.first().map(|(deadline, _)| *deadline);
+            tokio::select! {
+                biased;
+                command = self.commands.recv() => match command {
+                    Some(command) => self.handle(command),
+                    None => break,
+                },
+                _ = sleep_until(next_deadline.unwrap_or_else(Instant::now)), if next_deadline.is_some() => {
+                    self.expire(Instant::now());
+                }
+            }
+        }
+    }
+
+    fn handle(&mut self, command: Command) {
+        self.roll_day();
+        match command {
+            Command::Request {
+                request,
+                caller,
+                leasetime,
+                span,
+                reply,
+            } => {
+                let arrived = Instant::now();
+                // a wait too long to end is a wait forever
+                let deadline = match request.wait {
+                    Wait::For(wait) => arrived.checked_add(wait),
+                    Wait::NoWait | Wait::Forever => None,
+                };
+                let waiter_id = self.new_id();
+                self.place(
+                    waiter_id,
+                    WaiterRecord {
+                        request: *request,
+                        caller,
+                        leasetime,
+                        arrived,
+                        deadline,
+                        span,
+                        reply,
+                    },
+                );
+            }
+            Command::Release { lease_id } => self.release(lease_id, Ending::Released),
+            Command::Renew { lease_id, reply } => {
+                let _ = reply.send(self.renew(lease_id));
+            }
+            Command::Status { reply } => {
+                let _ = reply.send(self.status());
+            }
+            Command::UpdateInventory { inventory, reply } => {
+                self.update_inventory(inventory);
+                let _ = reply.send(Ok(()));
+            }
+            Command::SetMaintenance {
+                pool,
+                maintenance,
+                reply,
+            } => {
+                let _ = reply.send(self.set_maintenance(pool, maintenance));
+            }
+            Command::ForceRelease { pool, reply } => {
+                let _ = reply.send(self.force_release(pool));
+            }
+            Command::SetQuotas { quotas, reply } => {
+                info!(quotas = quotas.len(), "quotas set");
+                self.quotas = quotas;
+                self.hand_over_free();
+                let _ = reply.send(());
+            }
+            Command::SetPreemption { policy, reply } => {
+                info!(?policy, "preemption set");
+                self.preemption = policy;
+                let _ = reply.send(());
+            }
+        }
+    }
+
+    fn new_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn publish(&self, event: InventoryEvent) {
+        // nobody listening is fine
+        let _ = self.events.send(event);
+    }
+
+    fn refuse(&self, waiter: WaiterRecord, error: ResourceRequestError) {
+        waiter
+            .span
+            .in_scope(|| debug!(client = %waiter.caller.name, %error, "request refused"));
+        self.metrics.request_failed(&error);
+        let _ = waiter.reply.send(Err(error));
+    }
+
+    /// Forget the hours of yesterday, letting in the clients that were over their hours
+    fn roll_day(&mut self) {
+        let today = Utc::now().date_naive();
+        if today != self.today {
+            self.today = today;
+            self.day_started = start_of_day();
+            self.held_today.clear();
+            self.hand_over_free();
+        }
+    }
+
+    /// Roll the day at the next midnight (UTC), for the clients held back by their quota
+    fn schedule_midnight(&mut self) {
+        if self.midnight.is_some() {
+            return;
+        }
+        let tomorrow = self
+            .today
+            .succ_opt()
+            .unwrap_or(self.today)
+            .and_time(NaiveTime::MIN);
+        let until = (tomorrow.and_utc() - Utc::now())
+            .to_std()
+            .unwrap_or_default();
+        let at = Instant::now() + until;
+        self.midnight = Some(at);
+        self.deadlines.insert((at, Deadline::Midnight));
+    }
+
+    /// How long the lease has been held since midnight (UTC)
+    fn held_since_midnight(&self, lease: &LeaseRecord, now: Instant) -> Duration {
+        let since = self
+            .day_started
+            .map_or(lease.granted, |day_started| lease.granted.max(day_started));
+        now.saturating_duration_since(since)
+    }
+
+    fn quota_usage(&self, quota: &Quota) -> QuotaUsage {
+        let now = Instant::now();
+        let leases: Vec<&LeaseRecord> = self
+            .leases
+            .values()
+            .filter(|lease| quota.applies_to(&lease.caller))
+            .collect();
+        let held = self
+            .held_today
+            .get(&quota.subject)
+            .copied()
+            .unwrap_or_default()
+            + leases
+                .iter()
+                .map(|lease| self.held_since_midnight(lease, now))
+                .sum::<Duration>();
+        QuotaUsage {
+            quota: quota.clone(),
+            leases: leases.len(),
+            hours_today: held.as_secs_f64() / 3600.0,
+            queued: self
+                .waiters
+                .values()
+                .filter(|waiter| quota.applies_to(&waiter.caller))
+                .count(),
+        }
+    }
+
+    /// The subject of the first quota of the caller that allows no other lease,
+    /// or no other waiter when queueing
+    fn exceeded_quota(&self, caller: &Caller, queueing: bool) -> Option<String> {
+        self.quotas
+            .iter()
+            .filter(|quota| quota.applies_to(caller))
+            .map(|quota| self.quota_usage(quota))
+            .find(|usage| {
+                if queueing {
+                    usage.queue_exceeds()
+                } else {
+                    usage.lease_exceeds()
+                }
+            })
+            .map(|usage| usage.quota.subject)
+    }
+
+    /// Grant the waiter a free pool, or queue it on the pools in use that match.
+    /// A waiter over its lease quota is queued on the free pools too, until one of its leases ends.
+    fn place(&mut self, waiter_id: u64, waiter: WaiterRecord) {
+        let over_quota = self.exceeded_quota(&waiter.caller, false);
+        let pools = self.pools.load_full();
+        let mut candidates: Vec<usize> = Vec::new();
+
+        for (pool_index, potential_pool) in pools.iter().enumerate() {
+            if !waiter.caller.may_use(potential_pool) {
+                continue;
+            }
+            let Some(pairing) = request_matches(&waiter.request, potential_pool) else {
+                continue;
+            };
+            if self.slots[pool_index].is_free() && over_quota.is_none() {
+                let lease = self.grant(pool_index, &waiter, pairing);
+                // a lease the client gave up on comes back here and releases itself
+                let _ = waiter.reply.send(lease.ok_or(ResourceRequestError::InUse));
+                return;
+            }
+            candidates.push(pool_index);
+        }
+        if candidates.is_empty() {
+            self.refuse(waiter, ResourceRequestError::Impossible);
+            return;
+        }
+        if waiter.request.wait == Wait::NoWait {
+            let error = match over_quota {
+                Some(subject) => ResourceRequestError::QuotaExceeded(subject),
+                None => ResourceRequestError::InUse,
+            };
+            self.refuse(waiter, error);
+            return;
+        }
+        if let Some(subject) = self.exceeded_quota(&waiter.caller, true) {
+            self.refuse(waiter, ResourceRequestError::QuotaExceeded(subject));
+            return;
+        }
+        if over_quota.is_some() {
+            self.schedule_midnight();
+        }
+        for pool_index in &candidates {
+            self.slots[*pool_index].queue.push_back(waiter_id);
+        }
+        if let Some(deadline) = waiter.deadline {
+            self.deadlines
+                .insert((deadline, Deadline::Waiter(waiter_id)));
+        }
+        if self.sweep.is_none() {
+            let at = Instant::now() + ABANDONED_SWEEP_INTERVAL;
+            self.sweep = Some(at);
+            self.deadlines.insert((at, Deadline::Sweep));
+        }
+        waiter
+            .span
+            .in_scope(|| debug!(client = %waiter.caller.name, pools = candidates.len(), "queued"));
+        self.publish(InventoryEvent::Queued {
+            client: waiter.caller.name.clone(),
+            pools: candidates
+                .iter()
+                .map(|pool_index| pools[*pool_index].name.clone())
+                .collect(),
+        });
+        // no use revoking a lease the waiter could not take
+        let reclaimed = match over_quota {
+            Some(_) => None,
+            None => self.preempt(waiter_id, &waiter, &candidates),
+        };
+        if let Some(pool_index) = reclaimed {
+            // first in line for the pool it reclaims
+            let queue = &mut self.slots[pool_index].queue;
+            queue.retain(|queued| *queued != waiter_id);
+            queue.push_front(waiter_id);
+        }
+        self.waiters.insert(waiter_id, waiter);
+    }
+
+    /// Announce the revocation of a preemptible lease on one of the pools, for an urgent waiter.
+    /// Returns the pool reclaimed for the waiter, if any.
+    fn preempt(
+        &mut self,
+        waiter_id: u64,
+        waiter: &WaiterRecord,
+        candidates: &[usize],
+    ) -> Option<usize> {
+        let policy = self.preemption.as_ref()?;
+        let priority = waiter
+            .request
+            .priority
+            .filter(|priority| *priority >= policy.min_priority)?;
+        let at = Instant::now() + policy.grace_period();
+        let grace_seconds = policy.grace_period_secs;
+        let lease_on = |pool_index: &usize| {
+            let lease_id = self.slots[*pool_index].lease_id?;
+            Some((*pool_index, lease_id, self.leases.get(&lease_id)?))
+        };
+        // a waiter queued again after an inventory update keeps the pool it reclaims
+        if let Some((pool_index, _, _)) = candidates
+            .iter()
+            .filter_map(lease_on)
+            .find(|(_, _, lease)| lease.revoking.is_some_and(|r| r.waiter_id == waiter_id))
+        {
+            return Some(pool_index);
+        }
+        let preemptible = |lease: &LeaseRecord| {
+            lease.preemptible && lease.revoking.is_none() && lease.priority < priority
+        };
+        let (pool_index, lease_id, _) = candidates
+            .iter()
+            .filter_map(lease_on)
+            .find(|(_, _, lease)| preemptible(lease))?;
+        let lease = self.leases.get_mut(&lease_id)?;
+        lease.revoking = Some(Revoking { at, waiter_id });
+        let _ = lease.revocation.send(Some(Revocation {
+            by: waiter.caller.name.clone(),
+            at,
+        }));
+        lease
+            .span
+            .in_scope(|| info!(by = %waiter.caller.name, grace_seconds, "preempted"));
+        self.deadlines.insert((at, Deadline::Revoke(lease_id)));
+        self.metrics.lease_preempted(&lease.pool, &lease.location);
+        let event = InventoryEvent::LeasePreempted {
+            lease_id,
+            pool: lease.pool.clone(),
+            client: lease.caller.name.clone(),
+            by: waiter.caller.name.clone(),
+            grace_seconds,
+        };
+        self.publish(event);
+        Some(pool_index)
+    }
+
+    /// Mark a free pool as leased. Returns None when no handle is left to send the release.
+    fn grant(
+        &mut self,
+        pool_index: usize,
+        waiter: &WaiterRecord,
+        pairing: Option<AttributeMatch>,
+    ) -> Option<PoolLease> {
+        let commands = self.handle.upgrade()?;
+        let lease_id = self.new_id();
+        let granted = Instant::now();
+        let waited = granted - waiter.arrived;
+        let expires = waiter.leasetime.map(|leasetime| granted + leasetime);
+        let pool = self.pools.load()[pool_index].clone();
+        self.slots[pool_index].lease_id = Some(lease_id);
+        let (revocation, revocation_receiver) = watch::channel(None);
+        let span = info_span!(
+            parent: &waiter.span,
+            "lease",
+            lease_id,
+            client = %waiter.caller.name,
+            pool = %pool.name
+        );
+        span.in_scope(|| info!(?waited, "granted"));
+        self.leases.insert(
+            lease_id,
+            LeaseRecord {
+                pool_index: Some(pool_index),
+                caller: waiter.caller.clone(),
+                pool: pool.name.clone(),
+                location: pool.location.clone(),
+                waited,
+                granted,
+                granted_at: Utc::now(),
+                leasetime: waiter.leasetime,
+                expires,
+                priority: waiter.request.priority.unwrap_or(0),
+                preemptible: waiter.request.preemptible,
+                revocation,
+                revoking: None,
+                span,
+            },
+        );
+        if let Some(expires) = expires {
+            self.deadlines.insert((expires, Deadline::Lease(lease_id)));
+        }
+        self.metrics
+            .lease_granted(&pool.name, &pool.location, waited);
+        self.publish(InventoryEvent::LeaseGranted {
+            lease_id,
+            pool: pool.name.clone(),
+            client: waiter.caller.name.clone(),
+        });
+        let grace_period = match &self.preemption {
+            Some(policy) if waiter.request.preemptible => Some(policy.grace_period()),
+            _ => None,
+        };
+        Some(PoolLease {
+            id: lease_id,
+            leasetime: waiter.leasetime,
+            grace_period,
+            pool,
+            pairing,
+            guard: Some(LeaseGuard {
+                commands,
+                lease_id,
+                revocation: revocation_receiver,
+            }),
+        })
+    }
+
+    /// Free the pool of the lease, or hand it over to the first client queued for it.
+    /// Only that client is woken up.
+    fn release(&mut self, lease_id: u64, ending: Ending) {
+        let Some(lease) = self.leases.remove(&lease_id) else {
+            return; // expired before
+        };
+        if let Some(expires) = lease.expires {
+            self.deadlines.remove(&(expires, Deadline::Lease(lease_id)));
+        }
+        if let Some(revoking) = lease.revoking {
+            self.deadlines
+                .remove(&(revoking.at, Deadline::Revoke(lease_id)));
+        }
+        let held = lease.granted.elapsed();
+        lease.span.in_scope(|| info!(?held, ?ending, "lease ended"));
+        let held_today = self.held_since_midnight(&lease, Instant::now());
+        let mut under_quota = false;
+        for quota in &self.quotas {
+            if quota.applies_to(&lease.caller) {
+                *self.held_today.entry(quota.subject.clone()).or_default() += held_today;
+                under_quota = true;
+            }
+        }
+        if let Some(history) = &self.history {
+            // wall clock times are derived from the monotonic durations, so they add up
+            let held = chrono::Duration::from_std(held).unwrap_or_default();
+            let waited = chrono::Duration::from_std(lease.waited).unwrap_or_default();
+            history.record(LeaseHistoryEntry {
+                lease_id,
+                client: lease.caller.name.clone(),
+                pool: lease.pool.clone(),
+                location: lease.location.clone(),
+                requested_at: lease.granted_at - waited,
+                granted_at: lease.granted_at,
+                ended_at: lease.granted_at + held,
+                waited_seconds: lease.waited.as_secs_f64(),
+                held_seconds: held.as_seconds_f64(),
+                expired: ending == Ending::Expired,
+            });
+        }
+        self.metrics.lease_ended(&lease.pool, &lease.location, held);
+        let pool = lease.pool;
+        let client = lease.caller.name;
+        self.publish(match ending {
+            Ending::Released => InventoryEvent::LeaseReleased {
+                lease_id,
+                pool,
+                client,
+            },
+            Ending::Expired => InventoryEvent::LeaseExpired {
+                lease_id,
+                pool,
+                client,
+            },
+            Ending::Revoked => InventoryEvent::LeaseRevoked {
+                lease_id,
+                pool,
+                client,
+            },
+        });
+        // None once the pool was removed from the inventory
+        if let Some(pool_index) = lease.pool_index {
+            self.slots[pool_index].lease_id = None;
+            self.hand_over(pool_index);
+        }
+        if under_quota {
+            self.hand_over_free();
+        }
+    }
+
+    /// Hand over the pool to the first client queued for it that is within its lease quota.
+    /// The clients over their quota keep their place.
+    fn hand_over(&mut self, pool_index: usize) {
+        if !self.slots[pool_index].is_free() {
+            return;
+        }
+        let mut held_back = Vec::new();
+        while let Some(waiter_id) = self.slots[pool_index].queue.pop_front() {
+            // the waiter may have been served by another pool already
+            let Some(waiter) = self.waiters.get(&waiter_id) else {
+                continue;
+            };
+            if !waiter.reply.is_closed() && self.exceeded_quota(&waiter.caller, false).is_some() {
+                held_back.push(waiter_id);
+                continue;
+            }
+            let Some(waiter) = self.remove_waiter(waiter_id) else {
+                continue;
+            };
+            if waiter.reply.is_closed() {
+                continue;
+            }
+            let pairing = request_matches(&waiter.request, &self.pools.load()[pool_index])
+                .expect("queued on pools that match");
+            let lease = self.grant(pool_index, &waiter, pairing);
+            let _ = waiter.reply.send(lease.ok_or(ResourceRequestError::InUse));
+            break;
+        }
+        if !held_back.is_empty() {
+            self.schedule_midnight();
+        }
+        let queue = &mut self.slots[pool_index].queue;
+        for waiter_id in held_back.into_iter().rev() {
+            queue.push_front(waiter_id);
+        }
+    }
+
+    /// Hand over every free pool, once a lease or quota change may let a held back client in
+    fn hand_over_free(&mut self) {
+        for pool_index in 0..self.slots.len() {
+            self.hand_over(pool_index);
+        }
+    }
+
+    /// Take the waiter out of the queues, it no longer needs the lease revoked for it
+    fn remove_waiter(&mut self, waiter_id: u64) -> Option<WaiterRecord> {
+        let waiter = self.take_waiter(waiter_id)?;
+        self.cancel_revocation(waiter_id);
+        Some(waiter)
+    }
+
+    fn take_waiter(&mut self, waiter_id: u64) -> Option<WaiterRecord> {
+        let waiter = self.waiters.remove(&waiter_id)?;
+        if let Some(deadline) = waiter.deadline {
+            self.deadlines
+                .remove(&(deadline, Deadline::Waiter(waiter_id)));
+        }
+        Some(waiter)
+    }
+
+    /// Let the holder keep a lease revoked for a waiter that is gone
+    fn cancel_revocation(&mut self, waiter_id: u64) {
+        let Some((lease_id, lease)) = self
+            .leases
+            .iter_mut()
+            .find(|(_, lease)| lease.revoking.is_some_and(|r| r.waiter_id == waiter_id))
+        else {
+            return;
+        };
+        if let Some(revoking) = lease.revoking.take() {
+            self.deadlines
+                .remove(&(revoking.at, Deadline::Revoke(*lease_id)));
+        }
+        lease.revocation.send_replace(None);
+        lease.span.in_scope(|| info!("revocation cancelled"));
+    }
+
+    fn renew(&mut self, lease_id: u64) -> Result<(), ResourceRequestError> {
+        let lease = self
+            .leases
+            .get_mut(&lease_id)
+            .ok_or(ResourceRequestError::NotHeld)?;
+        if let (Some(expires), Some(leasetime)) = (lease.expires, lease.leasetime) {
+            self.deadlines.remove(&(expires, Deadline::Lease(lease_id)));
+            let expires = Instant::now() + leasetime;
+            lease.expires = Some(expires);
+            self.deadlines.insert((expires, Deadline::Lease(lease_id)));
+        }
+        lease.span.in_scope(|| debug!("renewed"));
+        Ok(())
+    }
+
+    /// Time out the waiters and expire the leases whose deadline passed
+    fn expire(&mut self, now: Instant) {
+        while let Some((deadline, what)) = self.deadlines.first().copied() {
+            if deadline > now {
+                break;
+            }
+            match what {
+                Deadline::Lease(lease_id) => self.release(lease_id, Ending::Expired),
+                Deadline::Revoke(lease_id) => self.release(lease_id, Ending::Revoked),
+                Deadline::Waiter(waiter_id) => {
+                    if let Some(waiter) = self.remove_waiter(waiter_id) {
+                        self.refuse(waiter, ResourceRequestError::TimeOut);
+                    }
+                }
+                Deadline::Sweep => {
+                    self.deadlines.remove(&(deadline, what));
+                    self.sweep = None;
+                    self.forget_abandoned();
+                }
+                Deadline::Midnight => {
+                    self.deadlines.remove(&(deadline, what));
+                    self.midnight = None;
+                    self.roll_day();
+                    // the wall clock may lag behind, or the clients still be over another quota
+                    if self
+                        .waiters
+                        .values()
+                        .any(|waiter| self.exceeded_quota(&waiter.caller, false).is_some())
+                    {
+                        self.schedule_midnight();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Drop the waiters whose client went away, so they no longer count as queued.
+    /// Sweeps again later while clients are waiting.
+    fn forget_abandoned(&mut self) {
+        let abandoned: Vec<u64> = self
+            .waiters
+            .iter()
+            .filter(|(_, waiter)| waiter.reply.is_closed())
+            .map(|(waiter_id, _)| *waiter_id)
+            .collect();
+        for waiter_id in abandoned {
+            if let Some(waiter) = self.remove_waiter(waiter_id) {
+                waiter
+                    .span
+                    .in_scope(|| debug!(client = %waiter.caller.name, "abandoned"));
+            }
+        }
+        for slot in &mut self.slots {
+            slot.queue
+                .retain(|waiter_id| self.waiters.contains_key(waiter_id));
+        }
+        if !self.waiters.is_empty() {
+            let at = Instant::now() + ABANDONED_SWEEP_INTERVAL;
+            self.sweep = Some(at);
+            self.deadlines.insert((at, Deadline::Sweep));
+        }
+    }
+
+    fn status(&self) -> InventoryStatus {
+        let pools = self.pools.load();
+        let mut leases: Vec<LeaseStatus> = self
+            .leases
+            .iter()
+            .map(|(lease_id, lease)| self.lease_status(*lease_id, lease))
+            .collect();
+        leases.sort_by_key(|lease| lease.lease_id);
+        InventoryStatus {
+            pools: pools
+                .iter()
+                .zip(&self.slots)
+                .map(|(pool, slot)| PoolStatus {
+                    name: pool.name.clone(),
+                    location: pool.location.clone(),
+                    attributes: pool.attributes.clone(),
+                    holder: slot
+                        .lease_id
+                        .and_then(|lease_id| self.leases.get(&lease_id))
+                        .map(|lease| lease.caller.name.clone()),
+                    queue_length: slot
+                        .queue
+                        .iter()
+                        .filter(|waiter_id| self.waiters.contains_key(waiter_id))
+                        .count(),
+                    maintenance: slot.maintenance,
+                })
+                .collect(),
+            quotas: self
+                .quotas
+                .iter()
+                .map(|quota| self.quota_usage(quota))
+                .collect(),
+            leases,
+        }
+    }
+
+    fn lease_status(&self, lease_id: u64, lease: &LeaseRecord) -> LeaseStatus {
+        let now = Instant::now();
+        let wall_clock = |at: Instant| {
+            Utc::now()
+                + chrono::Duration::from_std(at.saturating_duration_since(now))
+                    .unwrap_or(chrono::Duration::MAX)
+        };
+        LeaseStatus {
+            lease_id,
+            pool: lease.pool.clone(),
+            location: lease.location.clone(),
+            client: lease.caller.name.clone(),
+            granted_at: lease.granted_at,
+            expires_at: lease.expires.map(wall_clock),
+            preemptible: lease.preemptible,
+            revoked_at: lease.revoking.map(|revoking| wall_clock(revoking.at)),
+        }
+    }
+
+    fn set_maintenance(&mut self, pool: String, maintenance: bool) -> Result<(), InventoryError> {
+        let pool_index = self
+            .pools
+            .load()
+            .iter()
+            .position(|candidate| candidate.name == pool)
+            .ok_or_else(|| InventoryError::UnknownPool(pool.clone()))?;
+        if self.slots[pool_index].maintenance == maintenance {
+            return Ok(());
+        }
+        self.slots[pool_index].maintenance = maintenance;
+        info!(%pool, maintenance, "maintenance changed");
+        self.publish(InventoryEvent::MaintenanceChanged { pool, maintenance });
+        self.hand_over(pool_index);
+        Ok(())
+    }
+
+    fn force_release(&mut self, pool: String) -> Result<(), InventoryError> {
+        let pool_index = self
+            .pools
+            .load()
+            .iter()
+            .position(|candidate| candidate.name == pool)
+            .ok_or_else(|| InventoryError::UnknownPool(pool.clone()))?;
+        if let Some(lease_id) = self.slots[pool_index].lease_id {
+            info!(%pool, lease_id, "force release");
+            self.release(lease_id, Ending::Released);
+        }
+        Ok(())
+    }
+
+    fn update_inventory(&mut self, inventory: Inventory) {
+        let old_pools = self.pools.load_full();
+        let mut slots: Vec<PoolSlot> = inventory
+            .pools
+            .iter()
+            .map(|pool| PoolSlot {
+                maintenance: old_pools
+                    .iter()
+                    .zip(&self.slots)
+                    .any(|(old_pool, slot)| old_pool.name == pool.name && slot.maintenance),
+                ..Default::default()
+            })
+            .collect();
+        for lease in self.leases.values_mut() {
+            lease.pool_index = lease.pool_index.and_then(|old_index| {
+                let name = &old_pools[old_index].name;
+                inventory.pools.iter().position(|pool| pool.name == *name)
+            });
+        }
+        for (lease_id, lease) in &self.leases {
+            if let Some(pool_index) = lease.pool_index {
+                slots[pool_index].lease_id = Some(*lease_id);
+            }
+        }
+        self.slots = slots;
+        info!(pools = inventory.pools.len(), "inventory updated");
+        self.pools.store(Arc::new(inventory.pools));
+        self.publish(InventoryEvent::InventoryUpdated);
+
+        // queue everybody again, in order of arrival, keeping the leases revoked for them
+        let waiter_ids: Vec<u64> = self.waiters.keys().copied().collect();
+        for waiter_id in &waiter_ids {
+            if let Some(waiter) = self.take_waiter(*waiter_id) {
+                self.place(*waiter_id, waiter);
+            }
+        }
+        for waiter_id in waiter_ids {
+            if !self.waiters.contains_key(&waiter_id) {
+                self.cancel_revocation(waiter_id);
+            }
+        }
     }
 }
-//#[async_trait]
+
 pub trait InventoryResourceRequest {
+    /// Lease a pool matching the request, waiting as the request says
+    /// when the matching pools are in use.
     fn request(
         &mut self,
         request: &ResourceRequest,
-        client: &Arc<tokio::sync::Mutex<InnerClient>>,
-        client_notify: &Arc<Notify>,
     ) -> impl std::future::Future<Output = Result<PoolLease, ResourceRequestError>> + Send;
 }
 
+/// The instant today (UTC) started, None when that is before the first instant there is
+fn start_of_day() -> Option<Instant> {
+    let now = Utc::now();
+    let midnight = now.date_naive().and_time(NaiveTime::MIN).and_utc();
+    Instant::now().checked_sub((now - midnight).to_std().unwrap_or_default())
+}
+
 fn matches(subset: &[String], superset: &[String]) -> bool {
     subset.iter().all(|x| superset.contains(x))
 }
 
+/// Checks the pool level constraints of a request: name, location and pool attributes
+fn pool_matches(request: &ResourceRequest, pool: &Pool) -> bool {
+    if let Some(wanted_pool_attributes) = &request.pool_attributes
+        && !matches(wanted_pool_attributes, &pool.attributes)
+    {
+        return false;
+    }
+    if let Some(wanted_location) = &request.location
+        && *wanted_location != pool.location
+    {
+        return false;
+    }
+    if let Some(requested_pool_name) = &request.by_name
+        && *requested_pool_name != pool.name
+    {
+        return false;
+    }
+    true
+}
+
+/// Returns None when the pool doesn't match, and the resource pairing if any was requested otherwise
+fn request_matches(request: &ResourceRequest, pool: &Pool) -> Option<Option<AttributeMatch>> {
+    if !pool_matches(request, pool) {
+        return None;
+    }
+    match &request.resource_attributes {
+        Some(requested_resources_spec) => {
+            solve_resource_matches(pool, requested_resources_spec).map(Some)
+        }
+        None => Some(None),
+    }
+}
+
 fn solve_resource_matches(
     pool: &Pool,
     requested_resources_spec: &Vec<AttributeSet>,
@@ -141,79 +1649,27 @@ fn solve_resource_matches(
     Some(matchlist)
 }
 
-//#[async_trait]
 impl InventoryResourceRequest for InventoryManager {
     async fn request(
         &mut self,
         request: &ResourceRequest,
-        client: &Arc<tokio::sync::Mutex<InnerClient>>,
-        client_notify: &Arc<Notify>,
     ) -> Result<PoolLease, ResourceRequestError> {
-        let mut inventory = self.0.lock().await;
-        let mut ultimate_failure: ResourceRequestError = ResourceRequestError::Impossible;
-
-        for potential_pool in &mut inventory.pools {
-            // skip if request.pool_attributes not a subset of potential_pool.attributes
-            if let Some(wanted_pool_attributes) = &request.pool_attributes
-                && !wanted_pool_attributes.iter().all(|requested_attribute| {
-                    potential_pool.attributes.contains(requested_attribute)
-                })
-            {
-                continue;
-            }
-            if let Some(wanted_location) = &request.location
-                && *wanted_location != potential_pool.location
-            {
-                continue;
-            }
-            if let Some(requested_resources_spec) = &request.resource_attributes {
-                if let Some(match_) =
-                    solve_resource_matches(potential_pool, requested_resources_spec)
-                {
-                    return Ok(PoolLease {
-                        leasetime: DEFAULT_LEASE_TIME,
-                        pool: potential_pool.clone(),
-                        pairing: Some(match_),
-                        notify: Arc::clone(client_notify),
-                    });
-                } else {
-                    continue;
-                }
-            }
-            if let Some(requested_pool_name) = &request.by_name
-                && *requested_pool_name != potential_pool.name
-            {
-                continue;
-            }
-            if potential_pool.user.upgrade().is_none() {
-                potential_pool.user = Arc::downgrade(client);
-                return Ok(PoolLease {
-                    leasetime: DEFAULT_LEASE_TIME,
-                    pool: potential_pool.clone(),
-                    pairing: None,
-                    notify: Arc::clone(client_notify),
-                });
-            } else {
-                ultimate_failure = ResourceRequestError::InUse;
-            }
-        }
-        Err(ultimate_failure)
+        self.request_for(request, "anonymous").await
     }
 }
 
 #[derive(Debug)]
-pub struct InnerClient {
+pub struct LocalRespoClient {
     pub name: String,
-    pub inventory_manager: InventoryManager, // needed to make a request
-    pub notify: Arc<Notify>,                 // needed to retry a request
+    groups: Vec<String>,
+    leasetime: Option<Duration>, // the leases last until released when None
+    inventory_manager: InventoryManager, // needed to make a request
 }
-#[derive(Debug)]
-pub struct LocalRespoClient(Arc<Mutex<InnerClient>>);
 
 pub trait ClientResourceRequest {
     /// request a resource to the server.
-    /// When a timeout is given, we offer our notification channel to the server
-    /// and sleep until the race between deadline or notification finishes.
+    /// Unless the request says not to wait, the server queues us for the pools matching it
+    /// until one is handed over or the wait is over.
     fn request(
         &mut self,
         request: &ResourceRequest,
@@ -225,64 +1681,49 @@ impl ClientResourceRequest for LocalRespoClient {
         &mut self,
         request: &ResourceRequest,
     ) -> Result<PoolLease, ResourceRequestError> {
-        let deadline = request.timeout.map(|timeout| Instant::now() + timeout);
-        println!("trying to claim {:?} until {:?}", &request, &deadline);
-        loop {
-            let mut client = self.0.lock().await;
-            let notify = client.notify.clone();
-            match client
-                .inventory_manager
-                .request(request, &self.0, &notify)
-                .await
-            {
-                Ok(lease) => return Ok(lease),
-                Err(ResourceRequestError::InUse) => {
-                    drop(client);
-                    if let Some(deadline) = &deadline {
-                        tokio::select! {
-                            _ = notify.notified() => {
-                                // fall through to retry
-                            },
-                            _ = sleep_until(*deadline) =>  {
-                                return Err(ResourceRequestError::TimeOut);
-                            },
-                        }
-                    } else {
-                        return Err(ResourceRequestError::InUse);
-                    }
-                }
-                Err(other) => {
-                    return Err(other);
-                }
-            }
-        }
+        debug!(?request, client = %self.name, "trying to claim");
+        let caller = Caller {
+            name: self.name.clone(),
+            groups: self.groups.clone(),
+        };
+        self.inventory_manager
+            .request_leased(request, &caller, self.leasetime)
+            .instrument(info_span!("request", client = %self.name))
+            .await
     }
 }
 
 // TODO: refactor to 'InventoryManager', which tracks limits (leasetime)
 pub struct LocalRespoClientFactory {
     inventory_manager: InventoryManager,
-    notify: Arc<Notify>,
 }
 
 impl LocalRespoClient {
-    fn new(inner: InnerClient) -> LocalRespoClient {
-        LocalRespoClient(Arc::new(Mutex::new(inner)))
+    fn new(caller: Caller, inventory_manager: InventoryManager) -> LocalRespoClient {
+        LocalRespoClient {
+            name: caller.name,
+            groups: caller.groups,
+            leasetime: None,
+            inventory_manager,
+        }
+    }
+
+    /// Leases that expire when not renewed within `leasetime`, see `InventoryManager::request_expiring`
+    pub fn with_leasetime(mut self, leasetime: Duration) -> LocalRespoClient {
+        self.leasetime = Some(leasetime);
+        self
     }
 }
 
 impl LocalRespoClientFactory {
     pub fn new(inventory_manager: InventoryManager) -> LocalRespoClientFactory {
-        Self {
-            inventory_manager: inventory_manager.clone(),
-            notify: Arc::new(Notify::new()),
-        }
+        Self { inventory_manager }
     }
     pub fn create(&self, name: String) -> LocalRespoClient {
-        LocalRespoClient::new(InnerClient {
-            name,
-            inventory_manager: self.inventory_manager.clone(),
-            notify: self.notify.clone(),
-        })
+        self.create_for(Caller::new(&name))
+    }
+    /// A client that is a member of groups, for pools restricted to those
+    pub fn create_for(&self, caller: Caller) -> LocalRespoClient {
+        LocalRespoClient::new(caller, self.inventory_manager.clone())
     }
 }
@@ -1,3 +1,13 @@
+pub mod auth;
 pub mod client;
 pub mod config;
+pub mod events;
+pub mod history;
 pub mod inventory;
+pub mod logging;
+pub mod metrics;
+pub mod preemption;
+pub mod query;
+pub mod quotas;
+pub mod server;
+pub mod tls;
@@ -0,0 +1,189 @@
+//! Prometheus metrics of the inventory and its HTTP front, served as `GET /metrics`.
+//!
+//! Counters and histograms are fed as things happen: by the inventory actor for leases and
+//! failed requests, by the server for HTTP requests. The state of the pools is read from the
+//! inventory status when the metrics are gathered.
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use tokio::time::Duration;
+
+use crate::inventory::{InventoryStatus, ResourceRequestError};
+
+/// Lease and wait times range from seconds to a working day
+const LONG_BUCKETS: &[f64] = &[
+    1.0, 10.0, 60.0, 300.0, 900.0, 1800.0, 3600.0, 7200.0, 14400.0, 28800.0, 86400.0,
+];
+
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    registry: Registry,
+    pool_state: IntGaugeVec,
+    queue_depth: IntGaugeVec,
+    leases: IntCounterVec,
+    preemptions: IntCounterVec,
+    lease_duration: HistogramVec,
+    wait_time: HistogramVec,
+    request_errors: IntCounterVec,
+    http_requests: HistogramVec,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        let pool_labels = &["pool", "location"];
+        let metrics = Metrics {
+            registry: Registry::new(),
+            pool_state: IntGaugeVec::new(
+                Opts::new(
+                    "respo_pool_state",
+                    "1 for the state the pool is in: leased, free or maintenance",
+                ),
+                &["pool", "location", "state"],
+            )
+            .unwrap(),
+            queue_depth: IntGaugeVec::new(
+                Opts::new("respo_pool_queue_depth", "Clients waiting for the pool"),
+                pool_labels,
+            )
+            .unwrap(),
+            leases: IntCounterVec::new(
+                Opts::new("respo_leases_total", "Leases granted"),
+                pool_labels,
+            )
+            .unwrap(),
+            preemptions: IntCounterVec::new(
+                Opts::new(
+                    "respo_preemptions_total",
+                    "Leases preempted for a request of higher priority",
+                ),
+                pool_labels,
+            )
+            .unwrap(),
+            lease_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "respo_lease_duration_seconds",
+                    "Time from granting a lease until it was released or expired",
+                )
+                .buckets(LONG_BUCKETS.into()),
+                pool_labels,
+            )
+            .unwrap(),
+            wait_time: HistogramVec::new(
+                HistogramOpts::new(
+                    "respo_wait_seconds",
+                    "Time from requesting until the lease was granted",
+                )
+                .buckets(LONG_BUCKETS.into()),
+                pool_labels,
+            )
+            .unwrap(),
+            request_errors: IntCounterVec::new(
+                Opts::new("respo_request_errors_total", "Requests that got no lease"),
+                &["error"],
+            )
+            .unwrap(),
+            http_requests: HistogramVec::new(
+                HistogramOpts::new(
+                    "respo_http_request_duration_seconds",
+                    "Time to answer HTTP requests, including the time spent queued",
+                ),
+                &["method", "path", "status"],
+            )
+            .unwrap(),
+        };
+        for collector in [
+            Box::new(metrics.pool_state.clone()) as Box<dyn prometheus::core::Collector>,
+            Box::new(metrics.queue_depth.clone()),
+            Box::new(metrics.leases.clone()),
+            Box::new(metrics.preemptions.clone()),
+            Box::new(metrics.lease_duration.clone()),
+            Box::new(metrics.wait_time.clone()),
+            Box::new(metrics.request_errors.clone()),
+            Box::new(metrics.http_requests.clone()),
+        ] {
+            metrics
+                .registry
+                .register(collector)
+                .expect("metric names are unique");
+        }
+        metrics
+    }
+
+    pub fn lease_granted(&self, pool: &str, location: &str, waited: Duration) {
+        self.leases.with_label_values(&[pool, location]).inc();
+        self.wait_time
+            .with_label_values(&[pool, location])
+            .observe(waited.as_secs_f64());
+    }
+
+    pub fn lease_preempted(&self, pool: &str, location: &str) {
+        self.preemptions.with_label_values(&[pool, location]).inc();
+    }
+
+    pub fn lease_ended(&self, pool: &str, location: &str, held: Duration) {
+        self.lease_duration
+            .with_label_values(&[pool, location])
+            .observe(held.as_secs_f64());
+    }
+
+    pub fn request_failed(&self, error: &ResourceRequestError) {
+        let error = match error {
+            ResourceRequestError::Impossible => "Impossible",
+            ResourceRequestError::InUse => "InUse",
+            ResourceRequestError::TimeOut => "TimeOut",
+            ResourceRequestError::NotHeld => "NotHeld",
+            ResourceRequestError::QuotaExceeded(_) => "QuotaExceeded",
+        };
+        self.request_errors.with_label_values(&[error]).inc();
+    }
+
+    pub fn http_request(&self, method: &str, path: &str, status: u16, elapsed: Duration) {
+        // clients pick the method, keep them from adding labels
+        let method = match method {
+            "GET" | "POST" | "PUT" | "DELETE" | "HEAD" | "OPTIONS" => method,
+            _ => "other",
+        };
+        self.http_requests
+            .with_label_values(&[method, path, &status.to_string()])
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// All metrics in the Prometheus text format, with the pool state taken from `status`
+    pub fn render(&self, status: &InventoryStatus) -> String {
+        // start over so pools removed from the inventory disappear
+        self.pool_state.reset();
+        self.queue_depth.reset();
+        for pool in &status.pools {
+            let state = if pool.maintenance {
+                "maintenance"
+            } else if pool.holder.is_some() {
+                "leased"
+            } else {
+                "free"
+            };
+            for candidate in ["leased", "free", "maintenance"] {
+                self.pool_state
+                    .with_label_values(&[&pool.name, &pool.location, candidate])
+                    .set((candidate == state) as i64);
+            }
+            self.queue_depth
+                .with_label_values(&[&pool.name, &pool.location])
+                .set(pool.queue_length as i64);
+        }
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("metrics encode to text");
+        String::from_utf8(buffer).expect("metrics are utf-8")
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        prometheus::TEXT_FORMAT
+    }
+}
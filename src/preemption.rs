@@ -0,0 +1,65 @@
+//! Reclaiming pools for urgent requests. A request with a priority of at least the one of the
+//! policy may revoke a lease that was marked preemptible when it was requested. The holder is
+//! warned and keeps the pool for the grace period, then the pool goes to the urgent request.
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::time::{Duration, Instant};
+
+/// The `preemption` section of the server config, without it nothing is ever preempted
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PreemptionPolicy {
+    /// requests with at least this priority may preempt
+    pub min_priority: u32,
+    /// time the holder has between the warning and the revocation
+    #[serde(default)]
+    pub grace_period_secs: u64,
+}
+
+impl PreemptionPolicy {
+    pub fn grace_period(&self) -> Duration {
+        Duration::from_secs(self.grace_period_secs)
+    }
+}
+
+/// The warning a holder gets when its lease is preempted
+#[derive(Debug, Clone, PartialEq)]
+pub struct Revocation {
+    /// the client the pool goes to
+    pub by: String,
+    /// when the lease is revoked, unless released before
+    pub at: Instant,
+}
+
+/// The answer of `/renew` to the remote holder of a lease
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct Renewal {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revocation: Option<RevocationNotice>,
+}
+
+/// A revocation as sent over the wire, with a wall clock time
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RevocationNotice {
+    pub by: String,
+    pub at: DateTime<Utc>,
+}
+
+impl From<&Revocation> for RevocationNotice {
+    fn from(revocation: &Revocation) -> RevocationNotice {
+        let left = revocation.at.saturating_duration_since(Instant::now());
+        RevocationNotice {
+            by: revocation.by.clone(),
+            at: Utc::now() + chrono::Duration::from_std(left).unwrap_or_default(),
+        }
+    }
+}
+
+impl From<RevocationNotice> for Revocation {
+    fn from(notice: RevocationNotice) -> Revocation {
+        let left = (notice.at - Utc::now()).to_std().unwrap_or_default();
+        Revocation {
+            by: notice.by,
+            at: Instant::now() + left,
+        }
+    }
+}
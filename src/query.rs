@@ -0,0 +1,115 @@
+//! The query string of `/lock`, written by the client and read by the server.
+//!
+//! Lists of attributes are joined with commas, one `resource_attributes` pair per resource.
+//! Attribute names can't contain commas or be empty, and an empty list of resources is sent
+//! as none. Everything else round-trips, values are percent encoded.
+//! Requests that don't fit, like attributes with commas, go as JSON body of a POST instead.
+//! `client_name` and `keep` are not part of the request, they stay in the query string then.
+use thiserror::Error;
+use url::form_urlencoded;
+
+use crate::inventory::{ResourceRequest, Wait};
+
+#[derive(Debug, Error, PartialEq)]
+pub enum QueryError {
+    #[error("No value specified")]
+    Empty,
+    #[error("key not recognised: {0:?}")]
+    UnknownKey(String),
+    #[error("parse error of {key}: {message}")]
+    Invalid { key: String, message: String },
+}
+
+/// A lease request, and the name of the client making it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LockQuery {
+    pub client_name: Option<String>,
+    /// whether the server keeps the lease once it answered, until released or no longer
+    /// renewed; otherwise the lease ends with the answer
+    pub keep: bool,
+    pub request: ResourceRequest,
+}
+
+impl LockQuery {
+    pub fn encode(&self) -> String {
+        let request = &self.request;
+        let mut query = form_urlencoded::Serializer::new(String::new());
+        if let Some(client_name) = &self.client_name {
+            query.append_pair("client_name", client_name);
+        }
+        if self.keep {
+            query.append_pair("keep", "true");
+        }
+        if let Some(location) = &request.location {
+            query.append_pair("location", location);
+        }
+        if let Some(attribute_list) = &request.pool_attributes {
+            query.append_pair("pool_attributes", &attribute_list.join(","));
+        }
+        for resource_attributes in request.resource_attributes.iter().flatten() {
+            query.append_pair("resource_attributes", &resource_attributes.join(","));
+        }
+        if let Some(pool_name) = &request.by_name {
+            query.append_pair("by_name", pool_name);
+        }
+        if request.wait != Wait::NoWait {
+            query.append_pair("wait", &request.wait.to_string());
+        }
+        if let Some(priority) = request.priority {
+            query.append_pair("priority", &priority.to_string());
+        }
+        if request.preemptible {
+            query.append_pair("preemptible", "true");
+        }
+        query.finish()
+    }
+
+    pub fn decode(query: &str) -> Result<LockQuery, QueryError> {
+        let mut pairs = form_urlencoded::parse(query.as_bytes()).peekable();
+        if pairs.peek().is_none() {
+            return Err(QueryError::Empty);
+        }
+        let mut decoded = LockQuery::default();
+        let request = &mut decoded.request;
+        for (key, value) in pairs {
+            let invalid = |message: String| QueryError::Invalid {
+                key: key.to_string(),
+                message,
+            };
+            match &*key {
+                "client_name" => decoded.client_name = Some(value.into()),
+                "keep" => {
+                    decoded.keep = value.parse::<bool>().map_err(|e| invalid(e.to_string()))?;
+                }
+                "location" => request.location = Some(value.into()),
+                "by_name" => request.by_name = Some(value.into()),
+                "pool_attributes" => request.pool_attributes = Some(split_attributes(&value)),
+                "resource_attributes" => request
+                    .resource_attributes
+                    .get_or_insert_with(Vec::new)
+                    .push(split_attributes(&value)),
+                // timeout is the former name, it took seconds only
+                "wait" | "timeout" => {
+                    request.wait = value.parse::<Wait>().map_err(|e| invalid(e.to_string()))?;
+                }
+                "priority" => {
+                    let priority = value.parse::<u32>().map_err(|e| invalid(e.to_string()))?;
+                    request.priority = Some(priority);
+                }
+                "preemptible" => {
+                    request.preemptible =
+                        value.parse::<bool>().map_err(|e| invalid(e.to_string()))?;
+                }
+                _ => return Err(QueryError::UnknownKey(key.into())),
+            }
+        }
+        Ok(decoded)
+    }
+}
+
+fn split_attributes(value: &str) -> Vec<String> {
+    if value.is_empty() {
+        return Vec::new();
+    }
+    value.split(',').map(String::from).collect()
+}
@@ -0,0 +1,52 @@
+//! Limits on the leases of users and groups, so one of them can't starve the others.
+//! A quota on a group counts the leases and waiters of all of its members together.
+use serde::{Deserialize, Serialize};
+
+use crate::inventory::Caller;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct Quota {
+    /// user or group name
+    pub subject: String,
+    /// leases held at the same time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_leases: Option<usize>,
+    /// hours the leases were held today (UTC), those held now included
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_hours_per_day: Option<f64>,
+    /// requests waiting in the queues at the same time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_queued: Option<usize>,
+}
+
+impl Quota {
+    pub fn applies_to(&self, caller: &Caller) -> bool {
+        self.subject == caller.name || caller.groups.contains(&self.subject)
+    }
+}
+
+/// How much of a quota is used
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct QuotaUsage {
+    #[serde(flatten)]
+    pub quota: Quota,
+    pub leases: usize,
+    pub hours_today: f64,
+    pub queued: usize,
+}
+
+impl QuotaUsage {
+    /// Whether another lease would go over the quota
+    pub fn lease_exceeds(&self) -> bool {
+        self.quota.max_leases.is_some_and(|max| self.leases >= max)
+            || self
+                .quota
+                .max_hours_per_day
+                .is_some_and(|max| self.hours_today >= max)
+    }
+
+    /// Whether another waiter would go over the quota
+    pub fn queue_exceeds(&self) -> bool {
+        self.quota.max_queued.is_some_and(|max| self.queued >= max)
+    }
+}
@@ -0,0 +1,197 @@
+//! Authentication of the callers of the server.
+//!
+//! The identity of an authenticated caller replaces the client name it declares.
+//! Authenticators are tried in order, the first one to recognise the credentials wins.
+//! Group memberships and the admin role come from the config, whichever authenticator was used.
+use std::collections::HashMap;
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::auth::Credentials;
+use crate::inventory::Caller;
+
+/// Who the caller is
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    pub name: String,
+    pub groups: Vec<String>,
+    /// may use the maintenance and force release endpoints
+    pub admin: bool,
+}
+
+impl Identity {
+    pub fn new(name: &str) -> Identity {
+        Identity {
+            name: name.into(),
+            groups: Vec::new(),
+            admin: false,
+        }
+    }
+
+    /// The caller as seen by the inventory, to check the pools it may use
+    pub fn caller(&self) -> Caller {
+        Caller {
+            name: self.name.clone(),
+            groups: self.groups.clone(),
+        }
+    }
+}
+
+pub trait Authenticator: Send + Sync {
+    /// The identity the credentials belong to, None when they are not valid here
+    fn authenticate(&self, credentials: &Credentials) -> Option<Identity>;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TokenConfig {
+    pub token: String,
+    pub user: String,
+}
+
+/// The `auth` section of the server config
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct AuthConfig {
+    #[serde(default)]
+    pub tokens: Vec<TokenConfig>,
+    /// file with `user:bcrypt-hash` lines, as written by `htpasswd -B`
+    #[serde(default)]
+    pub htpasswd: Option<PathBuf>,
+    /// members of each group
+    #[serde(default)]
+    pub groups: HashMap<String, Vec<String>>,
+    /// users and groups with the admin role
+    #[serde(default)]
+    pub admins: Vec<String>,
+}
+
+/// API tokens, each belonging to a user
+pub struct StaticTokens {
+    users: HashMap<String, String>, // token to user
+}
+
+impl StaticTokens {
+    pub fn new(tokens: &[TokenConfig]) -> StaticTokens {
+        StaticTokens {
+            users: tokens
+                .iter()
+                .map(|token| (token.token.clone(), token.user.clone()))
+                .collect(),
+        }
+    }
+}
+
+impl Authenticator for StaticTokens {
+    fn authenticate(&self, credentials: &Credentials) -> Option<Identity> {
+        let Credentials::Token { token } = credentials else {
+            return None;
+        };
+        self.users.get(token).map(|user| Identity::new(user))
+    }
+}
+
+/// User names and bcrypt password hashes
+pub struct Htpasswd {
+    hashes: HashMap<String, String>,
+}
+
+impl Htpasswd {
+    pub fn load(path: &Path) -> io::Result<Htpasswd> {
+        let mut hashes = HashMap::new();
+        for line in io::BufReader::new(std::fs::File::open(path)?).lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (user, hash) = line.split_once(':').ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("not a user:hash line in {:?}", path),
+                )
+            })?;
+            hashes.insert(user.into(), hash.into());
+        }
+        Ok(Htpasswd { hashes })
+    }
+}
+
+impl Authenticator for Htpasswd {
+    fn authenticate(&self, credentials: &Credentials) -> Option<Identity> {
+        let Credentials::Password { user, password } = credentials else {
+            return None;
+        };
+        let hash = self.hashes.get(user)?;
+        bcrypt::verify(password, hash)
+            .ok()?
+            .then(|| Identity::new(user))
+    }
+}
+
+pub struct Authentication {
+    authenticators: Vec<Box<dyn Authenticator>>,
+    groups: HashMap<String, Vec<String>>, // group to members
+    admins: Vec<String>,
+}
+
+impl Authentication {
+    pub fn new(authenticators: Vec<Box<dyn Authenticator>>) -> Authentication {
+        Authentication {
+            authenticators,
+            groups: HashMap::new(),
+            admins: Vec::new(),
+        }
+    }
+
+    /// Make users members of groups, and give users or groups the admin role
+    pub fn with_roles(
+        mut self,
+        groups: HashMap<String, Vec<String>>,
+        admins: Vec<String>,
+    ) -> Authentication {
+        self.groups = groups;
+        self.admins = admins;
+        self
+    }
+
+    pub fn from_config(config: &AuthConfig) -> io::Result<Authentication> {
+        let mut authenticators: Vec<Box<dyn Authenticator>> =
+            vec![Box::new(StaticTokens::new(&config.tokens))];
+        if let Some(path) = &config.htpasswd {
+            authenticators.push(Box::new(Htpasswd::load(path)?));
+        }
+        Ok(Authentication::new(authenticators)
+            .with_roles(config.groups.clone(), config.admins.clone()))
+    }
+
+    /// Check the value of an `Authorization` header
+    pub fn authenticate(&self, authorization: &str) -> Option<Identity> {
+        let credentials = Credentials::from_authorization(authorization)?;
+        let identity = self
+            .authenticators
+            .iter()
+            .find_map(|authenticator| authenticator.authenticate(&credentials))?;
+        Some(self.with_roles_of(identity))
+    }
+
+    /// The identity of a caller authenticated another way, like a client certificate
+    pub fn identify(&self, name: &str) -> Identity {
+        self.with_roles_of(Identity::new(name))
+    }
+
+    fn with_roles_of(&self, mut identity: Identity) -> Identity {
+        identity.groups = self
+            .groups
+            .iter()
+            .filter(|(_, members)| members.contains(&identity.name))
+            .map(|(group, _)| group.clone())
+            .collect();
+        identity.groups.sort();
+        identity.admin = self
+            .admins
+            .iter()
+            .any(|admin| *admin == identity.name || identity.groups.contains(admin));
+        identity
+    }
+}
@@ -1,27 +1,20 @@
-use std::convert::Infallible;
 use std::fs::File;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::{env, path::PathBuf};
 
-use http_body_util::Full;
-use hyper::body::Bytes;
-use hyper::server::conn::http1;
-use hyper::service::service_fn;
-use hyper::{Request, Response, StatusCode};
-use hyper_util::rt::TokioIo;
-
 use tokio::net::TcpListener;
-use tokio::time::Duration;
-use url::Url;
 
 use clap::{Parser, Subcommand};
 
-use rp::config::InventoryLoader;
-use rp::inventory::{
-    ClientResourceRequest, Inventory, InventoryManager, LocalRespoClient, LocalRespoClientFactory,
-    ResourceRequest,
-};
+use rp::config::{ServerConfig, ServerConfigLoader};
+use rp::history::LeaseHistory;
+use rp::inventory::InventoryManager;
+use rp::logging::{LogConfig, LogFormat, init_logging};
+use rp::server::RespoServer;
+use rp::server::auth::Authentication;
+use rp::server::tls;
+use rp::server::webhooks::start_webhooks;
 
 fn get_default_config_path() -> PathBuf {
     let mut path = env::current_dir().unwrap();
@@ -40,8 +33,14 @@ struct Cli {
     /// configuration file (default respod.yaml)
     config_path: PathBuf,
     #[arg(short, long)]
-    /// logfile path
-    log: Option<String>,
+    /// logfile path, logs go to stderr when not given
+    log: Option<PathBuf>,
+    #[arg(long)]
+    /// log filter, like `info` or `rp=debug`; defaults to $RUST_LOG, then `info`
+    log_level: Option<String>,
+    #[arg(long, default_value = "text")]
+    /// log format: text, pretty or json
+    log_format: LogFormat,
 }
 
 #[derive(Subcommand, Debug)]
@@ -50,127 +49,15 @@ enum Commands {
     Serve,
 }
 
-async fn handle_request(
-    client_factory: Arc<LocalRespoClientFactory>,
-    request: Request<hyper::body::Incoming>,
-) -> Result<Response<Full<Bytes>>, Infallible> {
-    let uri_string = request.uri().to_string();
-    let request_url = Url::parse(&uri_string).unwrap();
-    let params = request_url.query_pairs();
-    if params.count() == 0 {
-        let mut resp = Response::new(Full::new(Bytes::from("No value specified")));
-        *resp.status_mut() = StatusCode::BAD_REQUEST;
-        return Ok(resp);
-    }
-    let mut request = ResourceRequest::default();
-    let mut client_name: Option<String> = None;
-
-    for (key, value) in params {
-        match &*key {
-            "client_name" => client_name = Some(String::from(value)),
-            "location" => request.location = Some(String::from(value)),
-            "by_name" => request.by_name = Some(String::from(value)),
-            "pool_attributes" => {
-                let attribute_list: Vec<String> = value.split(",").map(String::from).collect();
-                request.pool_attributes = Some(attribute_list);
-            }
-            "resource_attributes" => {
-                let resource_attributes: Vec<String> = value.split(",").map(String::from).collect();
-                match &mut request.resource_attributes {
-                    None => {
-                        request.resource_attributes = Some(vec![resource_attributes]);
-                    }
-                    Some(existing_list) => {
-                        existing_list.push(resource_attributes);
-                    }
-                }
-            }
-            "timeout" => {
-                let value = value.parse::<u64>();
-                match value {
-                    Ok(value) => {
-                        let value = Duration::new(value, 0);
-                        request.timeout = Some(value);
-                    }
-                    Err(e) => {
-                        let mut resp =
-                            Response::new(Full::new(Bytes::from(format!("parse error: {:?}", e))));
-                        *resp.status_mut() = StatusCode::BAD_REQUEST;
-                        return Ok(resp);
-                    }
-                }
-            }
-            _ => {
-                let mut resp = Response::new(Full::new(Bytes::from(format!(
-                    "key not recognised: {:?}",
-                    key
-                ))));
-                *resp.status_mut() = StatusCode::BAD_REQUEST;
-                return Ok(resp);
-            }
-        }
-    }
-    dbg!(&request);
-
-    let mut client_a: LocalRespoClient =
-        client_factory.create(client_name.unwrap_or("no-name".into()));
-    let lease = client_a.request(&request).await;
-    match lease {
-        Ok(lease) => {
-            let json = serde_json::to_string_pretty(&lease);
-            match json {
-                Ok(json) => Ok(Response::new(Full::new(Bytes::from(json)))),
-                Err(x) => Ok(Response::new(Full::new(Bytes::from(format!(
-                    "got an error: {:?}",
-                    x
-                ))))),
-            }
-        }
-        Err(x) => Ok(Response::new(Full::new(Bytes::from(format!(
-            "got an error: {:?}",
-            x
-        ))))),
-    }
-}
-
-async fn http_serve(
-    client_factory: LocalRespoClientFactory,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3000)); // TODO: make configurable
-
-    let listener = TcpListener::bind(addr).await?;
-    let client_factory = Arc::new(client_factory);
-
-    loop {
-        let onion1 = client_factory.clone();
-        let (stream, _) = listener.accept().await?;
-
-        // Use an adapter to access something implementing `tokio::io` traits as if they implement
-        // `hyper::rt` IO traits.
-        let io = TokioIo::new(stream);
-
-        // Spawn a tokio task to serve multiple connections concurrently
-        tokio::task::spawn(async move {
-            // Finally, we bind the incoming connection to our `hello` service
-            if let Err(err) = http1::Builder::new()
-                // `service_fn` converts our function in a `Service`
-                .serve_connection(
-                    io,
-                    service_fn(move |req: Request<hyper::body::Incoming>| {
-                        let onion2 = onion1.clone();
-                        async move { handle_request(onion2, req).await }
-                    }),
-                )
-                .await
-            {
-                eprintln!("Error serving connection: {:?}", err);
-            }
-        });
-    }
-}
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let args = Cli::parse();
+    init_logging(&LogConfig {
+        level: args.log_level.clone(),
+        default_level: "info".into(),
+        format: args.log_format,
+        file: args.log.clone(),
+    })?;
 
     match args.command {
         Commands::Serve => {
@@ -178,10 +65,29 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
                 .try_exists()
                 .expect("Can't check existence of file or config does not exist");
             let f = File::open(args.config_path).unwrap();
-            let parsed: Inventory = InventoryLoader::load(f);
-            let manager = InventoryManager::new(parsed);
-            let client_factory = LocalRespoClientFactory::new(manager);
-            http_serve(client_factory).await
+            let config: ServerConfig = ServerConfigLoader::load(f);
+            // as update_inventory does for an update, pools and resources are addressed by name
+            config.inventory.validate()?;
+            let manager = match config.lease_history {
+                Some(path) => {
+                    InventoryManager::with_history(config.inventory, LeaseHistory::open(path))
+                }
+                None => InventoryManager::new(config.inventory),
+            };
+            manager.set_quotas(config.quotas).await;
+            manager.set_preemption(config.preemption).await;
+            let _webhooks = start_webhooks(config.webhooks, config.webhook_outbox, &manager)?;
+            let addr = SocketAddr::from(([127, 0, 0, 1], 3000)); // TODO: make configurable
+            let listener = TcpListener::bind(addr).await?;
+            tracing::info!(%addr, "serving");
+            let mut server = RespoServer::new(manager);
+            if let Some(auth) = &config.auth {
+                server = server.with_authentication(Authentication::from_config(auth)?);
+            }
+            if let Some(tls_config) = &config.tls {
+                server = server.with_tls(tls::acceptor(tls_config)?);
+            }
+            Arc::new(server).serve(listener).await
         }
     }
 }
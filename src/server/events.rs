@@ -0,0 +1,103 @@
+//! `GET /events`: the inventory events as a Server-Sent Events stream.
+//! Optional query parameters `pool` and `location` select the events about those pools.
+//! The events about pools the caller may not use are left out, as for `/status`.
+use std::convert::Infallible;
+
+use futures::stream::{self, StreamExt};
+use http_body_util::{BodyExt, StreamBody};
+use hyper::body::{Bytes, Frame};
+use hyper::header::{CACHE_CONTROL, CONTENT_TYPE};
+use hyper::{Request, Response, StatusCode};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::events::{EventFilter, InventoryEvent};
+use crate::inventory::{Caller, InventoryManager, Pool};
+use crate::server::auth::Identity;
+use crate::server::{ResponseBody, full, hidden_from, query_caller, query_pairs};
+
+/// One event in the SSE wire format
+pub fn format_event(event: &InventoryEvent) -> String {
+    let data = serde_json::to_string(event).expect("events serialize to json");
+    format!("event: {}\ndata: {}\n\n", event.name(), data)
+}
+
+/// The event as the caller may see it: without the pools hidden from it,
+/// None when it is only about those
+fn visible_to(caller: &Caller, pools: &[Pool], event: InventoryEvent) -> Option<InventoryEvent> {
+    match event {
+        InventoryEvent::Queued {
+            client,
+            pools: queued,
+        } => {
+            let queued: Vec<String> = queued
+                .into_iter()
+                .filter(|name| !hidden_from(caller, pools, name))
+                .collect();
+            (!queued.is_empty()).then_some(InventoryEvent::Queued {
+                client,
+                pools: queued,
+            })
+        }
+        event => {
+            let hidden = event
+                .pools()
+                .iter()
+                .any(|name| hidden_from(caller, pools, name));
+            (!hidden).then_some(event)
+        }
+    }
+}
+
+pub fn handle_events<B>(
+    inventory_manager: &InventoryManager,
+    request: &Request<B>,
+    identity: &Option<Identity>,
+) -> Response<ResponseBody> {
+    let mut filter = EventFilter::default();
+    let mut client_name: Option<String> = None;
+    for (key, value) in query_pairs(request) {
+        match &*key {
+            "pool" => filter.pool = Some(value),
+            "location" => filter.location = Some(value),
+            "client_name" => client_name = Some(value),
+            _ => {
+                let mut resp = Response::new(full(format!("key not recognised: {:?}", key)));
+                *resp.status_mut() = StatusCode::BAD_REQUEST;
+                return resp;
+            }
+        }
+    }
+
+    let caller = query_caller(identity, client_name);
+    let receiver = inventory_manager.subscribe();
+    let manager = inventory_manager.clone();
+    // hyper drops the stream, and with it the subscription, when the client goes away
+    let events = stream::unfold(receiver, move |mut receiver| {
+        let manager = manager.clone();
+        let filter = filter.clone();
+        let caller = caller.clone();
+        async move {
+            loop {
+                let chunk = match receiver.recv().await {
+                    Ok(event) => {
+                        let pools = manager.pools();
+                        match visible_to(&caller, &pools, event) {
+                            Some(event) if filter.matches(&event, &pools) => format_event(&event),
+                            _ => continue,
+                        }
+                    }
+                    Err(RecvError::Lagged(count)) => format!(": missed {count} events\n\n"),
+                    Err(RecvError::Closed) => return None,
+                };
+                return Some((chunk, receiver));
+            }
+        }
+    })
+    .map(|chunk| Ok::<_, Infallible>(Frame::data(Bytes::from(chunk))));
+
+    let mut resp = Response::new(BodyExt::boxed(StreamBody::new(events)));
+    let headers = resp.headers_mut();
+    headers.insert(CONTENT_TYPE, "text/event-stream".parse().unwrap());
+    headers.insert(CACHE_CONTROL, "no-cache".parse().unwrap());
+    resp
+}
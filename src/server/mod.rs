@@ -0,0 +1,657 @@
+pub mod auth;
+pub mod events;
+pub mod reports;
+pub mod tls;
+pub mod webhooks;
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::Bytes;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+use tokio_rustls::TlsAcceptor;
+use tracing::{Instrument, Span, debug, field, info_span, warn};
+
+use crate::inventory::{
+    Caller, ClientResourceRequest, DEFAULT_LEASE_TIME, InventoryError, InventoryManager,
+    InventoryStatus, LocalRespoClient, LocalRespoClientFactory, Pool, PoolLease, ResourceRequest,
+    ResourceRequestError,
+};
+use crate::preemption::{Renewal, RevocationNotice};
+use crate::query::{LockQuery, QueryError};
+use crate::server::auth::{Authentication, Identity};
+
+pub type ResponseBody = BoxBody<Bytes, Infallible>;
+
+pub fn full<T: Into<Bytes>>(chunk: T) -> ResponseBody {
+    Full::new(chunk.into()).boxed()
+}
+
+pub(crate) fn error_response(status: StatusCode, message: String) -> Response<ResponseBody> {
+    let mut resp = Response::new(full(message));
+    *resp.status_mut() = status;
+    resp
+}
+
+pub(crate) fn json_response<T: serde::Serialize>(value: &T) -> Response<ResponseBody> {
+    let json = serde_json::to_string_pretty(value).expect("the answer serializes to json");
+    let mut response = Response::new(full(json));
+    response.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("application/json"),
+    );
+    response
+}
+
+/// Query parameters of the request, percent decoded
+pub fn query_pairs<B>(request: &Request<B>) -> Vec<(String, String)> {
+    url::form_urlencoded::parse(request.uri().query().unwrap_or("").as_bytes())
+        .into_owned()
+        .collect()
+}
+
+/// Who is asking: the authenticated identity, or else the client named in the query
+pub(crate) fn query_caller(identity: &Option<Identity>, client_name: Option<String>) -> Caller {
+    match identity {
+        Some(identity) => identity.caller(),
+        None => Caller::new(&client_name.unwrap_or("no-name".into())),
+    }
+}
+
+/// Whether the pool named `name` is hidden from the caller, not being one it may use.
+/// Pools no longer in the inventory are not.
+pub(crate) fn hidden_from(caller: &Caller, pools: &[Pool], name: &str) -> bool {
+    pools
+        .iter()
+        .any(|pool| pool.name == name && !caller.may_use(pool))
+}
+
+/// The request of `/lock`: in the query string, or as JSON body with only
+/// the client name and keep left in the query string
+fn lock_request(query: &str, body: &[u8]) -> Result<LockQuery, String> {
+    if body.is_empty() {
+        return LockQuery::decode(query).map_err(|e| e.to_string());
+    }
+    let (client_name, keep) = match LockQuery::decode(query) {
+        Ok(LockQuery {
+            client_name,
+            keep,
+            request,
+        }) if request == ResourceRequest::default() => (client_name, keep),
+        Ok(_) => {
+            return Err(
+                "the request is in the body, the query only takes client_name and keep".into(),
+            );
+        }
+        Err(QueryError::Empty) => (None, false),
+        Err(e) => return Err(e.to_string()),
+    };
+    let request = serde_json::from_slice(body).map_err(|e| format!("invalid request body: {e}"))?;
+    Ok(LockQuery {
+        client_name,
+        keep,
+        request,
+    })
+}
+
+/// A lease the server holds for a remote client, asked for with `keep`
+struct KeptLease {
+    holder: String,
+    lease: Arc<PoolLease>, // renewed without holding on to the others
+}
+
+/// The HTTP front of an inventory
+pub struct RespoServer {
+    inventory_manager: InventoryManager,
+    client_factory: LocalRespoClientFactory,
+    kept: Mutex<HashMap<u64, KeptLease>>, // by lease id
+    next_request_id: AtomicU64,
+    authentication: Option<Arc<Authentication>>, // anybody may call when not set
+    tls: Option<TlsAcceptor>,
+}
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+/// A lease request is small, anything bigger is not one
+const MAX_REQUEST_BODY: usize = 64 * 1024;
+
+impl RespoServer {
+    pub fn new(inventory_manager: InventoryManager) -> RespoServer {
+        RespoServer {
+            client_factory: LocalRespoClientFactory::new(inventory_manager.clone()),
+            inventory_manager,
+            kept: Mutex::new(HashMap::new()),
+            next_request_id: AtomicU64::new(1),
+            authentication: None,
+            tls: None,
+        }
+    }
+
+    /// Only accept authenticated callers, their identity replaces the client name they give
+    pub fn with_authentication(mut self, authentication: Authentication) -> RespoServer {
+        self.authentication = Some(Arc::new(authentication));
+        self
+    }
+
+    /// Serve over TLS only, see `tls::acceptor`
+    pub fn with_tls(mut self, acceptor: TlsAcceptor) -> RespoServer {
+        self.tls = Some(acceptor);
+        self
+    }
+
+    pub async fn serve(
+        self: Arc<Self>,
+        listener: TcpListener,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let server = self.clone();
+
+            // Spawn a tokio task to serve multiple connections concurrently
+            tokio::task::spawn(async move {
+                match &server.tls {
+                    None => server.serve_connection(stream, None).await,
+                    Some(acceptor) => match acceptor.accept(stream).await {
+                        Ok(stream) => {
+                            let peer = tls::peer_name(stream.get_ref().1.peer_certificates());
+                            server.serve_connection(stream, peer).await
+                        }
+                        Err(err) => warn!("TLS handshake failed: {}", err),
+                    },
+                }
+            });
+        }
+    }
+
+    /// Serve the requests of one connection, `peer` is the name in the client certificate
+    async fn serve_connection<I>(self: Arc<Self>, stream: I, peer: Option<String>)
+    where
+        I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        // Use an adapter to access something implementing `tokio::io` traits as if they implement
+        // `hyper::rt` IO traits.
+        let io = TokioIo::new(stream);
+        if let Err(err) = http1::Builder::new()
+            // `service_fn` converts our function in a `Service`
+            .serve_connection(
+                io,
+                service_fn(move |req: Request<hyper::body::Incoming>| {
+                    let server = self.clone();
+                    let peer = peer.clone();
+                    let span = server.request_span(&req);
+                    async move { server.handle_request(req, peer).await }.instrument(span)
+                }),
+            )
+            .await
+        {
+            warn!("Error serving connection: {:?}", err);
+        }
+    }
+
+    /// The span of a request, identified by the `X-Request-Id` header of the caller or a number
+    fn request_span<B>(&self, request: &Request<B>) -> Span {
+        let request_id = match request
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+        {
+            Some(request_id) => request_id.to_string(),
+            None => self
+                .next_request_id
+                .fetch_add(1, Ordering::Relaxed)
+                .to_string(),
+        };
+        info_span!(
+            "http_request",
+            %request_id,
+            method = %request.method(),
+            path = request.uri().path(),
+            client = field::Empty,
+        )
+    }
+
+    async fn handle_request(
+        &self,
+        request: Request<hyper::body::Incoming>,
+        peer: Option<String>,
+    ) -> Result<Response<ResponseBody>, Infallible> {
+        let started = Instant::now();
+        let method = request.method().clone();
+        // the lock handler answers any other path, label by handler to keep the label set small
+        let path = match (request.method(), request.uri().path()) {
+            (&Method::GET, "/events") => "/events",
+            (&Method::GET, "/metrics") => "/metrics",
+            (&Method::GET, "/status") => "/status",
+            (&Method::GET, "/pool") => "/pool",
+            (&Method::GET, "/leases") => "/leases",
+            (&Method::GET, "/reports/usage") => "/reports/usage",
+            (&Method::POST, "/maintenance") => "/maintenance",
+            (&Method::POST, "/release") => "/release",
+            (&Method::POST, "/renew") => "/renew",
+            (&Method::POST, "/unlock") => "/unlock",
+            _ => "/lock",
+        };
+        let response = match self.authenticate(&request, peer).await {
+            Err(response) => response,
+            Ok(identity) => match path {
+                "/events" => events::handle_events(&self.inventory_manager, &request, &identity),
+                "/status" => self.handle_status(&request, &identity).await,
+                "/pool" => self.handle_pool(&request, &identity).await,
+                "/leases" => self.handle_leases(&request, &identity).await,
+                "/reports/usage" => {
+                    reports::handle_usage_report(&self.inventory_manager, &request, &identity).await
+                }
+                // the metrics are labelled with every pool, restricted or not
+                "/metrics" | "/maintenance" | "/release" if !self.is_admin(&identity) => {
+                    debug!("not an admin");
+                    error_response(StatusCode::FORBIDDEN, "this needs the admin role".into())
+                }
+                "/metrics" => self.handle_metrics().await,
+                "/maintenance" => self.handle_maintenance(&request).await,
+                "/release" => self.handle_force_release(&request).await,
+                "/renew" => self.handle_renew(&request, &identity).await,
+                "/unlock" => self.handle_unlock(&request, &identity).await,
+                _ => self.handle_lock(request, identity).await?,
+            },
+        };
+        debug!(status = response.status().as_u16(), elapsed = ?started.elapsed(), "answered");
+        // for /events this is the time until the stream started
+        self.inventory_manager.metrics().http_request(
+            method.as_str(),
+            path,
+            response.status().as_u16(),
+            started.elapsed(),
+        );
+        Ok(response)
+    }
+
+    /// The identity of the caller: the name in its client certificate, or else the owner
+    /// of its credentials. None when authentication is off and there is no client certificate.
+    /// Callers without valid credentials get a 401 response.
+    async fn authenticate<B>(
+        &self,
+        request: &Request<B>,
+        peer: Option<String>,
+    ) -> Result<Option<Identity>, Response<ResponseBody>> {
+        if let Some(peer) = peer {
+            Span::current().record("client", peer.as_str());
+            return Ok(Some(match &self.authentication {
+                Some(authentication) => authentication.identify(&peer),
+                None => Identity::new(&peer),
+            }));
+        }
+        let Some(authentication) = self.authentication.clone() else {
+            return Ok(None);
+        };
+        let authorization = request
+            .headers()
+            .get(hyper::header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from);
+        let identity = match authorization {
+            // bcrypt takes its time, keep it off the runtime threads
+            Some(authorization) => {
+                tokio::task::spawn_blocking(move || authentication.authenticate(&authorization))
+                    .await
+                    .unwrap_or(None)
+            }
+            None => None,
+        };
+        match identity {
+            Some(identity) => {
+                Span::current().record("client", identity.name.as_str());
+                Ok(Some(identity))
+            }
+            None => {
+                debug!("not authenticated");
+                let mut response = error_response(
+                    StatusCode::UNAUTHORIZED,
+                    "valid credentials are required".into(),
+                );
+                response.headers_mut().insert(
+                    hyper::header::WWW_AUTHENTICATE,
+                    hyper::header::HeaderValue::from_static("Basic realm=\"respo\""),
+                );
+                Err(response)
+            }
+        }
+    }
+
+    /// Admin endpoints are open when authentication is off, callers are trusted then
+    fn is_admin(&self, identity: &Option<Identity>) -> bool {
+        self.authentication.is_none() || identity.as_ref().is_some_and(|identity| identity.admin)
+    }
+
+    /// Whether the caller may renew or release a lease kept for `holder`
+    fn may_keep(&self, identity: &Option<Identity>, holder: &str) -> bool {
+        identity
+            .as_ref()
+            .is_none_or(|identity| identity.name == holder)
+            || self.is_admin(identity)
+    }
+
+    /// GET /metrics, in the Prometheus text format
+    async fn handle_metrics(&self) -> Response<ResponseBody> {
+        let status = self.inventory_manager.status().await;
+        let metrics = self.inventory_manager.metrics();
+        let mut response = Response::new(full(metrics.render(&status)));
+        response.headers_mut().insert(
+            hyper::header::CONTENT_TYPE,
+            hyper::header::HeaderValue::from_static(metrics.content_type()),
+        );
+        response
+    }
+
+    /// GET /status, the pools, the use of the quotas and the leases as JSON
+    async fn handle_status(
+        &self,
+        request: &Request<hyper::body::Incoming>,
+        identity: &Option<Identity>,
+    ) -> Response<ResponseBody> {
+        let client_name = query_pairs(request)
+            .into_iter()
+            .find_map(|(key, value)| (key == "client_name").then_some(value));
+        json_response(&self.status_for(&query_caller(identity, client_name)).await)
+    }
+
+    /// The status without the pools the caller may not use, nor the leases on them
+    async fn status_for(&self, caller: &Caller) -> InventoryStatus {
+        let mut status = self.inventory_manager.status().await;
+        let pools = self.inventory_manager.pools();
+        let visible = |name: &str| {
+            pools
+                .iter()
+                .any(|pool| pool.name == name && caller.may_use(pool))
+        };
+        status.pools.retain(|pool| visible(&pool.name));
+        status.leases.retain(|lease| visible(&lease.pool));
+        status
+    }
+
+    /// GET /pool?name=<name>, the pool with its resources and their properties as JSON.
+    /// A pool the caller may not use is not found, as for /lock.
+    async fn handle_pool(
+        &self,
+        request: &Request<hyper::body::Incoming>,
+        identity: &Option<Identity>,
+    ) -> Response<ResponseBody> {
+        let mut name: Option<String> = None;
+        let mut client_name: Option<String> = None;
+        for (key, value) in query_pairs(request) {
+            match &*key {
+                "name" => name = Some(value),
+                "client_name" => client_name = Some(value),
+                _ => {
+                    return error_response(
+                        StatusCode::BAD_REQUEST,
+                        format!("key not recognised: {:?}", key),
+                    );
+                }
+            }
+        }
+        let Some(name) = name else {
+            return error_response(StatusCode::BAD_REQUEST, "name has to be specified".into());
+        };
+        let caller = query_caller(identity, client_name);
+        match self.inventory_manager.pool(&name).await {
+            Some(details) if caller.may_use(&details.pool) => json_response(&details),
+            _ => error_response(
+                StatusCode::NOT_FOUND,
+                InventoryError::UnknownPool(name).to_string(),
+            ),
+        }
+    }
+
+    /// GET /leases?mine=<true|false>&client_name=<name>, the leases as JSON.
+    /// Only those of the caller when mine, named as for /lock.
+    async fn handle_leases(
+        &self,
+        request: &Request<hyper::body::Incoming>,
+        identity: &Option<Identity>,
+    ) -> Response<ResponseBody> {
+        let mut mine = false;
+        let mut client_name: Option<String> = None;
+        for (key, value) in query_pairs(request) {
+            match &*key {
+                "client_name" => client_name = Some(value),
+                "mine" => match value.parse::<bool>() {
+                    Ok(value) => mine = value,
+                    Err(e) => {
+                        return error_response(
+                            StatusCode::BAD_REQUEST,
+                            format!("parse error: {:?}", e),
+                        );
+                    }
+                },
+                _ => {
+                    return error_response(
+                        StatusCode::BAD_REQUEST,
+                        format!("key not recognised: {:?}", key),
+                    );
+                }
+            }
+        }
+        let caller = query_caller(identity, client_name);
+        let mut leases = self.status_for(&caller).await.leases;
+        if mine {
+            leases.retain(|lease| lease.client == caller.name);
+        }
+        json_response(&leases)
+    }
+
+    /// POST /maintenance?pool=<name>&enabled=<true|false>
+    async fn handle_maintenance(
+        &self,
+        request: &Request<hyper::body::Incoming>,
+    ) -> Response<ResponseBody> {
+        let mut pool: Option<String> = None;
+        let mut enabled: Option<bool> = None;
+        for (key, value) in query_pairs(request) {
+            match &*key {
+                "pool" => pool = Some(value),
+                "enabled" => match value.parse::<bool>() {
+                    Ok(value) => enabled = Some(value),
+                    Err(e) => {
+                        return error_response(
+                            StatusCode::BAD_REQUEST,
+                            format!("parse error: {:?}", e),
+                        );
+                    }
+                },
+                _ => {
+                    return error_response(
+                        StatusCode::BAD_REQUEST,
+                        format!("key not recognised: {:?}", key),
+                    );
+                }
+            }
+        }
+        let (Some(pool), Some(enabled)) = (pool, enabled) else {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                "pool and enabled have to be specified".into(),
+            );
+        };
+        match self.inventory_manager.set_maintenance(&pool, enabled).await {
+            Ok(()) => Response::new(full("ok")),
+            Err(e) => error_response(StatusCode::NOT_FOUND, e.to_string()),
+        }
+    }
+
+    /// POST /release?pool=<name>, ends the lease on the pool whoever holds it
+    async fn handle_force_release(
+        &self,
+        request: &Request<hyper::body::Incoming>,
+    ) -> Response<ResponseBody> {
+        let mut pool: Option<String> = None;
+        for (key, value) in query_pairs(request) {
+            match &*key {
+                "pool" => pool = Some(value),
+                _ => {
+                    return error_response(
+                        StatusCode::BAD_REQUEST,
+                        format!("key not recognised: {:?}", key),
+                    );
+                }
+            }
+        }
+        let Some(pool) = pool else {
+            return error_response(StatusCode::BAD_REQUEST, "pool has to be specified".into());
+        };
+        match self.inventory_manager.force_release(&pool).await {
+            Ok(()) => Response::new(full("ok")),
+            Err(e) => error_response(StatusCode::NOT_FOUND, e.to_string()),
+        }
+    }
+
+    /// POST /renew?lease=<id>, extends a kept lease by its lease time.
+    /// Answers with the revocation of the lease when it is preempted.
+    async fn handle_renew(
+        &self,
+        request: &Request<hyper::body::Incoming>,
+        identity: &Option<Identity>,
+    ) -> Response<ResponseBody> {
+        let lease_id = match kept_lease_id(request) {
+            Ok(lease_id) => lease_id,
+            Err(message) => return error_response(StatusCode::BAD_REQUEST, message),
+        };
+        let lease = match self.kept.lock().await.get(&lease_id) {
+            None => {
+                return error_response(StatusCode::GONE, ResourceRequestError::NotHeld.to_string());
+            }
+            Some(kept_lease) if !self.may_keep(identity, &kept_lease.holder) => {
+                return error_response(
+                    StatusCode::FORBIDDEN,
+                    "the lease is somebody else's".into(),
+                );
+            }
+            Some(kept_lease) => kept_lease.lease.clone(),
+        };
+        match lease.renew().await {
+            Ok(()) => json_response(&Renewal {
+                revocation: lease
+                    .pending_revocation()
+                    .as_ref()
+                    .map(RevocationNotice::from),
+            }),
+            Err(e) => {
+                self.kept.lock().await.remove(&lease_id);
+                let message = match lease.revocation().await {
+                    Some(revocation) => format!("The lease was revoked for {}", revocation.by),
+                    None => e.to_string(),
+                };
+                error_response(StatusCode::GONE, message)
+            }
+        }
+    }
+
+    /// POST /unlock?lease=<id>, releases a kept lease
+    async fn handle_unlock(
+        &self,
+        request: &Request<hyper::body::Incoming>,
+        identity: &Option<Identity>,
+    ) -> Response<ResponseBody> {
+        let lease_id = match kept_lease_id(request) {
+            Ok(lease_id) => lease_id,
+            Err(message) => return error_response(StatusCode::BAD_REQUEST, message),
+        };
+        let mut kept = self.kept.lock().await;
+        match kept.get(&lease_id) {
+            None => error_response(StatusCode::GONE, ResourceRequestError::NotHeld.to_string()),
+            Some(kept_lease) if !self.may_keep(identity, &kept_lease.holder) => {
+                error_response(StatusCode::FORBIDDEN, "the lease is somebody else's".into())
+            }
+            Some(_) => {
+                kept.remove(&lease_id);
+                Response::new(full("ok"))
+            }
+        }
+    }
+
+    async fn handle_lock(
+        &self,
+        request: Request<hyper::body::Incoming>,
+        identity: Option<Identity>,
+    ) -> Result<Response<ResponseBody>, Infallible> {
+        let query = request.uri().query().unwrap_or("").to_string();
+        let body = match Limited::new(request.into_body(), MAX_REQUEST_BODY)
+            .collect()
+            .await
+        {
+            Ok(body) => body.to_bytes(),
+            Err(e) => return Ok(error_response(StatusCode::BAD_REQUEST, e.to_string())),
+        };
+        let LockQuery {
+            client_name,
+            keep,
+            request,
+        } = match lock_request(&query, &body) {
+            Ok(query) => query,
+            Err(message) => return Ok(error_response(StatusCode::BAD_REQUEST, message)),
+        };
+        let caller = query_caller(&identity, client_name);
+        Span::current().record("client", caller.name.as_str());
+        debug!(?request, keep, "lock request");
+
+        let holder = caller.name.clone();
+        // a kept lease outlives the answer, it expires unless the client renews it
+        let mut client_a: LocalRespoClient = self
+            .client_factory
+            .create_for(caller)
+            .with_leasetime(DEFAULT_LEASE_TIME);
+        let lease = client_a.request(&request).await;
+        match lease {
+            Ok(lease) => {
+                let json = serde_json::to_string_pretty(&lease);
+                if keep {
+                    let mut kept = self.kept.lock().await;
+                    // the leases kept before on the pool are over, it was granted again
+                    kept.retain(|_, kept| kept.lease.pool().name != lease.pool().name);
+                    kept.insert(
+                        lease.id(),
+                        KeptLease {
+                            holder,
+                            lease: Arc::new(lease),
+                        },
+                    );
+                }
+                match json {
+                    Ok(json) => Ok(Response::new(full(json))),
+                    Err(x) => Ok(Response::new(full(format!("got an error: {:?}", x)))),
+                }
+            }
+            // the reason as JSON, for the client to tell
+            Err(x) => {
+                let mut response = json_response(&x);
+                *response.status_mut() = StatusCode::CONFLICT;
+                Ok(response)
+            }
+        }
+    }
+}
+
+/// The `lease` of the query string of /renew and /unlock
+fn kept_lease_id<B>(request: &Request<B>) -> Result<u64, String> {
+    let mut lease_id: Option<u64> = None;
+    for (key, value) in query_pairs(request) {
+        match &*key {
+            "lease" => match value.parse::<u64>() {
+                Ok(value) => lease_id = Some(value),
+                Err(e) => return Err(format!("parse error: {:?}", e)),
+            },
+            _ => return Err(format!("key not recognised: {:?}", key)),
+        }
+    }
+    lease_id.ok_or_else(|| "lease has to be specified".into())
+}
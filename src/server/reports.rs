@@ -0,0 +1,93 @@
+//! `GET /reports/usage`: utilisation aggregated from the lease history.
+//! Query parameters: `from` and `to`, as RFC 3339 times or dates (UTC), defaulting to the
+//! last 7 days, and `group_by`, a comma separated list of `pool`, `location`, `user` and `day`.
+//! The leases of pools the caller may not use are left out, as for `/status`.
+use chrono::{DateTime, Days, NaiveDate, Utc};
+use hyper::header::CONTENT_TYPE;
+use hyper::{Request, Response, StatusCode};
+
+use crate::history::{UsageGrouping, usage_report};
+use crate::inventory::InventoryManager;
+use crate::server::auth::Identity;
+use crate::server::{ResponseBody, error_response, full, hidden_from, query_caller, query_pairs};
+
+const DEFAULT_PERIOD: Days = Days::new(7);
+
+/// A time, or the start of a day
+fn parse_time(value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map(|day| day.and_hms_opt(0, 0, 0).expect("midnight exists").and_utc())
+        .map_err(|_| format!("not a date or RFC 3339 time: {:?}", value))
+}
+
+pub async fn handle_usage_report<B>(
+    inventory_manager: &InventoryManager,
+    request: &Request<B>,
+    identity: &Option<Identity>,
+) -> Response<ResponseBody> {
+    let Some(history) = inventory_manager.history() else {
+        return error_response(StatusCode::NOT_FOUND, "no lease history is kept".into());
+    };
+    let mut from: Option<DateTime<Utc>> = None;
+    let mut to: Option<DateTime<Utc>> = None;
+    let mut grouping = vec![UsageGrouping::Pool];
+    let mut client_name: Option<String> = None;
+    for (key, value) in query_pairs(request) {
+        let parsed = match &*key {
+            "client_name" => {
+                client_name = Some(value);
+                Ok(())
+            }
+            "from" => parse_time(&value).map(|time| from = Some(time)),
+            "to" => parse_time(&value).map(|time| to = Some(time)),
+            "group_by" => value
+                .split(',')
+                .map(str::parse)
+                .collect::<Result<Vec<UsageGrouping>, String>>()
+                .map(|parsed| grouping = parsed),
+            _ => Err(format!("key not recognised: {:?}", key)),
+        };
+        if let Err(e) = parsed {
+            return error_response(StatusCode::BAD_REQUEST, e);
+        }
+    }
+    let to = to.unwrap_or_else(Utc::now);
+    let from = match from.or_else(|| to.checked_sub_days(DEFAULT_PERIOD)) {
+        Some(from) => from,
+        None => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                "to is too early for the default period".into(),
+            );
+        }
+    };
+    // an empty or reversed period
+    if from >= to {
+        return error_response(StatusCode::BAD_REQUEST, "from has to be before to".into());
+    }
+
+    let mut entries = match history.read(from, to).await {
+        Ok(entries) => entries,
+        Err(e) => {
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("could not read the lease history: {}", e),
+            );
+        }
+    };
+    let caller = query_caller(identity, client_name);
+    let pools = inventory_manager.pools();
+    entries.retain(|entry| !hidden_from(&caller, &pools, &entry.pool));
+    let report = usage_report(&entries, from, to, &grouping);
+    let mut response = Response::new(full(
+        serde_json::to_string_pretty(&report).expect("reports serialize to json"),
+    ));
+    response.headers_mut().insert(
+        CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("application/json"),
+    );
+    response
+}
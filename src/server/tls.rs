@@ -0,0 +1,51 @@
+//! TLS for the server. With a client CA, callers have to present a certificate signed by it,
+//! and the common name of that certificate is their identity.
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use rustls::ServerConfig;
+use rustls::pki_types::CertificateDer;
+use rustls::server::WebPkiClientVerifier;
+use serde::{Deserialize, Serialize};
+use tokio_rustls::TlsAcceptor;
+
+use crate::tls::{TlsError, load_certificates, load_private_key, load_roots, provider};
+
+/// The `tls` section of the server config, PEM files
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TlsConfig {
+    pub certificate: PathBuf,
+    pub key: PathBuf,
+    /// certificate authority of the client certificates, they are required when given
+    #[serde(default)]
+    pub client_ca: Option<PathBuf>,
+}
+
+pub fn acceptor(config: &TlsConfig) -> Result<TlsAcceptor, TlsError> {
+    let builder =
+        ServerConfig::builder_with_provider(provider()).with_safe_default_protocol_versions()?;
+    let builder = match &config.client_ca {
+        Some(client_ca) => {
+            let verifier = WebPkiClientVerifier::builder_with_provider(
+                Arc::new(load_roots(client_ca)?),
+                provider(),
+            )
+            .build()
+            .map_err(|e| TlsError::InvalidCa(e.to_string()))?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let server_config = builder.with_single_cert(
+        load_certificates(&config.certificate)?,
+        load_private_key(&config.key)?,
+    )?;
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+/// The common name of the verified client certificate
+pub fn peer_name(certificates: Option<&[CertificateDer<'_>]>) -> Option<String> {
+    let (_, certificate) = x509_parser::parse_x509_certificate(certificates?.first()?).ok()?;
+    let common_name = certificate.subject().iter_common_name().next()?;
+    common_name.as_str().ok().map(String::from)
+}
@@ -0,0 +1,509 @@
+//! Outbound webhooks: inventory events are POSTed as JSON to the configured URLs,
+//! `https://` ones over TLS trusting the well known certificate authorities.
+//!
+//! Every hook has its own worker, delivering its events in order. A failed delivery is
+//! retried with exponential backoff. Deliveries are kept in the outbox until they succeed
+//! or run out of attempts; with an outbox file they are picked up again after a restart.
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use hmac::{Hmac, Mac};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper::{Request, StatusCode};
+use hyper_util::rt::TokioIo;
+use rustls::pki_types::ServerName;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use thiserror::Error;
+use tokio::net::TcpStream;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time::{Duration, sleep, timeout};
+use tokio_rustls::TlsConnector;
+use tracing::{error, warn};
+
+use crate::events::{EventFilter, InventoryEvent};
+use crate::inventory::InventoryManager;
+use crate::tls::{ClientTlsConfig, TlsError};
+
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+pub const SIGNATURE_HEADER: &str = "x-respo-signature";
+pub const EVENT_HEADER: &str = "x-respo-event";
+pub const DELIVERY_HEADER: &str = "x-respo-delivery";
+
+fn default_max_attempts() -> u32 {
+    10
+}
+fn default_initial_backoff_ms() -> u64 {
+    1_000
+}
+fn default_max_backoff_ms() -> u64 {
+    300_000
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// names of the events to deliver, all events when not given
+    #[serde(default)]
+    pub events: Option<Vec<String>>,
+    #[serde(default)]
+    pub filter: EventFilter,
+    /// when given, the body is signed with HMAC-SHA256 in the `X-Respo-Signature` header
+    #[serde(default)]
+    pub secret: Option<String>,
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    #[serde(default = "default_initial_backoff_ms")]
+    pub initial_backoff_ms: u64,
+    #[serde(default = "default_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+}
+
+impl WebhookConfig {
+    pub fn new(url: String) -> WebhookConfig {
+        WebhookConfig {
+            url,
+            events: None,
+            filter: EventFilter::default(),
+            secret: None,
+            max_attempts: default_max_attempts(),
+            initial_backoff_ms: default_initial_backoff_ms(),
+            max_backoff_ms: default_max_backoff_ms(),
+        }
+    }
+
+    /// The URL has to be a http or https one, with a host
+    fn check(&self) -> Result<(), WebhookError> {
+        let url = self.url.parse::<hyper::Uri>()?;
+        url.host().ok_or(WebhookError::InvalidHostError)?;
+        match url.scheme_str() {
+            Some("http" | "https") => Ok(()),
+            scheme => Err(WebhookError::UnsupportedScheme(
+                scheme.unwrap_or_default().into(),
+            )),
+        }
+    }
+
+    fn wants(&self, event: &InventoryEvent, manager: &InventoryManager) -> bool {
+        if let Some(events) = &self.events
+            && !events.iter().any(|name| name == event.name())
+        {
+            return false;
+        }
+        self.filter.matches(event, &manager.pools())
+    }
+
+    /// Delay before the given retry, doubling from the initial backoff up to the maximum
+    fn backoff(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .initial_backoff_ms
+            .saturating_mul(1u64 << attempt.saturating_sub(1).min(32));
+        Duration::from_millis(backoff.min(self.max_backoff_ms))
+    }
+}
+
+/// `sha256=<hex>` of the HMAC of the body, as sent in the signature header
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Delivery {
+    pub id: u64,
+    /// position of the hook in the configuration, hooks may share a URL
+    #[serde(default)]
+    pub hook: usize,
+    pub url: String,
+    pub event: InventoryEvent,
+}
+
+#[derive(Error, Debug)]
+pub enum WebhookError {
+    #[error("URL error: {0}")]
+    InvalidUriError(#[from] ::http::uri::InvalidUri),
+    #[error("No host specified")]
+    InvalidHostError,
+    #[error("Unsupported scheme {0:?}, webhook URLs are http or https")]
+    UnsupportedScheme(String),
+    #[error("TLS error: {0}")]
+    Tls(#[from] TlsError),
+    #[error("HTTP Communication error: {0}")]
+    HTTPCommunicationError(#[from] ::http::Error),
+    #[error("Hyper error: {0}")]
+    HyperError(#[from] hyper::Error),
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
+    #[error("No response within {0:?}")]
+    TimeOut(Duration),
+    #[error("Unexpected response status {0}")]
+    Status(StatusCode),
+}
+
+/// How many lines are appended to the outbox file before it is compacted,
+/// when more than half of what it holds is done
+const COMPACT_AFTER: usize = 1000;
+
+/// A line of the outbox file: a delivery to make, or the id of one that is done
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Record {
+    Done { done: u64 },
+    Delivery(Delivery),
+}
+
+/// The deliveries that are not done yet. With a file, the deliveries and the ids of those
+/// done are appended to it by a writer off the runtime threads, which compacts it now and then.
+#[derive(Debug)]
+pub struct Outbox {
+    path: Option<PathBuf>,
+    pending: BTreeMap<u64, Delivery>,
+    next_id: u64,
+    log: Option<mpsc::UnboundedSender<Record>>, // to the writer, once started
+}
+
+impl Outbox {
+    /// Opens the outbox, loading what was pending from the file. Lines that can't be read,
+    /// like one cut short by a crash, are skipped with a warning.
+    pub fn open(path: Option<PathBuf>) -> io::Result<Outbox> {
+        let mut pending = BTreeMap::new();
+        let mut next_id = 0;
+        if let Some(path) = &path
+            && path.exists()
+        {
+            for (index, line) in io::BufReader::new(fs::File::open(path)?)
+                .lines()
+                .enumerate()
+            {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let record = match serde_json::from_str(&line) {
+                    Ok(record) => record,
+                    Err(e) => {
+                        warn!(?path, line = index + 1, "skipping an outbox line: {}", e);
+                        continue;
+                    }
+                };
+                match record {
+                    Record::Delivery(delivery) => {
+                        next_id = next_id.max(delivery.id + 1);
+                        pending.insert(delivery.id, delivery);
+                    }
+                    Record::Done { done } => {
+                        next_id = next_id.max(done + 1);
+                        pending.remove(&done);
+                    }
+                }
+            }
+        }
+        Ok(Outbox {
+            path,
+            pending,
+            next_id,
+            log: None,
+        })
+    }
+
+    pub fn pending(&self) -> impl Iterator<Item = &Delivery> {
+        self.pending.values()
+    }
+
+    fn add(&mut self, hook: usize, url: &str, event: &InventoryEvent) -> Delivery {
+        let delivery = Delivery {
+            id: self.next_id,
+            hook,
+            url: url.into(),
+            event: event.clone(),
+        };
+        self.next_id += 1;
+        self.pending.insert(delivery.id, delivery.clone());
+        self.append(Record::Delivery(delivery.clone()));
+        delivery
+    }
+
+    fn remove(&mut self, id: u64) {
+        if self.pending.remove(&id).is_some() {
+            self.append(Record::Done { done: id });
+        }
+    }
+
+    fn append(&self, record: Record) {
+        if let Some(log) = &self.log {
+            // the writer only stops once the outbox is dropped
+            let _ = log.send(record);
+        }
+    }
+
+    /// Compact the file to the pending deliveries, then start the writer appending the changes
+    fn start_log(&mut self) -> io::Result<()> {
+        let Some(path) = self.path.clone() else {
+            return Ok(());
+        };
+        compact(&path, &self.pending)?;
+        let (log, records) = mpsc::unbounded_channel();
+        let pending = self.pending.clone();
+        tokio::task::spawn_blocking(move || write_log(&path, pending, records));
+        self.log = Some(log);
+        Ok(())
+    }
+}
+
+/// Rewrite the file with the pending deliveries, through a temporary file so a crash
+/// leaves either version intact
+fn compact(path: &Path, pending: &BTreeMap<u64, Delivery>) -> io::Result<()> {
+    let temporary = path.with_extension("tmp");
+    let mut file = io::BufWriter::new(fs::File::create(&temporary)?);
+    for delivery in pending.values() {
+        serde_json::to_writer(&mut file, delivery)?;
+        file.write_all(b"\n")?;
+    }
+    file.into_inner()?.sync_all()?;
+    fs::rename(temporary, path)
+}
+
+/// Append the records as they come, syncing once per batch. `pending` follows the records,
+/// the file is compacted to it once mostly done deliveries were appended.
+fn write_log(
+    path: &Path,
+    mut pending: BTreeMap<u64, Delivery>,
+    mut records: mpsc::UnboundedReceiver<Record>,
+) {
+    let mut file: Option<fs::File> = None;
+    let mut appended: usize = 0;
+    while let Some(record) = records.blocking_recv() {
+        let mut batch = vec![record];
+        while let Ok(record) = records.try_recv() {
+            batch.push(record);
+        }
+        let mut lines = Vec::new();
+        for record in &batch {
+            serde_json::to_writer(&mut lines, record).expect("records serialize to json");
+            lines.push(b'\n');
+            match record {
+                Record::Delivery(delivery) => {
+                    pending.insert(delivery.id, delivery.clone());
+                }
+                Record::Done { done } => {
+                    pending.remove(done);
+                }
+            }
+        }
+        appended = appended.saturating_add(batch.len());
+        let written = if appended >= COMPACT_AFTER.max(2 * pending.len()) {
+            file = None;
+            compact(path, &pending).map(|()| appended = 0)
+        } else {
+            append(path, &mut file, &lines)
+        };
+        if let Err(e) = written {
+            error!("could not update the webhook outbox: {}", e);
+            // the next batch rewrites the file from the pending deliveries
+            file = None;
+            appended = usize::MAX;
+        }
+    }
+}
+
+fn append(path: &Path, file: &mut Option<fs::File>, lines: &[u8]) -> io::Result<()> {
+    let file = match file {
+        Some(file) => file,
+        None => file.insert(
+            fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?,
+        ),
+    };
+    file.write_all(lines)?;
+    file.sync_data()
+}
+
+type SharedOutbox = Arc<Mutex<Outbox>>;
+
+fn lock(outbox: &SharedOutbox) -> std::sync::MutexGuard<'_, Outbox> {
+    outbox.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// POST the event to the hook, once. `tls` connects the `https://` hooks.
+pub async fn deliver(
+    hook: &WebhookConfig,
+    delivery: &Delivery,
+    tls: &TlsConnector,
+) -> Result<(), WebhookError> {
+    let body = serde_json::to_vec(&delivery.event).expect("events serialize to json");
+    let url = hook.url.parse::<hyper::Uri>()?;
+    let host = url.host().ok_or(WebhookError::InvalidHostError)?;
+    let https = match url.scheme_str() {
+        Some("https") => true,
+        Some("http") => false,
+        scheme => {
+            return Err(WebhookError::UnsupportedScheme(
+                scheme.unwrap_or_default().into(),
+            ));
+        }
+    };
+    let port = url.port_u16().unwrap_or(if https { 443 } else { 80 });
+    let authority = url
+        .authority()
+        .ok_or(WebhookError::InvalidHostError)?
+        .clone();
+
+    let mut request = Request::builder()
+        .method(hyper::Method::POST)
+        .uri(url.path_and_query().map_or("/", |path| path.as_str()))
+        .header(hyper::header::HOST, authority.as_str())
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .header(EVENT_HEADER, delivery.event.name())
+        .header(DELIVERY_HEADER, delivery.id.to_string());
+    if let Some(secret) = &hook.secret {
+        request = request.header(SIGNATURE_HEADER, sign(secret, &body));
+    }
+    let request = request.body(Full::new(Bytes::from(body)))?;
+
+    let exchange = async {
+        let stream = TcpStream::connect((host, port)).await?;
+        if !https {
+            return send(TokioIo::new(stream), request).await;
+        }
+        let server_name = ServerName::try_from(host.trim_matches(['[', ']']).to_string())
+            .map_err(|_| WebhookError::InvalidHostError)?;
+        let stream = tls.connect(server_name, stream).await?;
+        send(TokioIo::new(stream), request).await
+    };
+    let status = timeout(DELIVERY_TIMEOUT, exchange)
+        .await
+        .map_err(|_| WebhookError::TimeOut(DELIVERY_TIMEOUT))??;
+    if status.is_success() {
+        Ok(())
+    } else {
+        Err(WebhookError::Status(status))
+    }
+}
+
+/// Send the request over the connection, answering the response status
+async fn send<I>(io: I, request: Request<Full<Bytes>>) -> Result<StatusCode, WebhookError>
+where
+    I: hyper::rt::Read + hyper::rt::Write + Unpin + Send + 'static,
+{
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(io).await?;
+    tokio::task::spawn(conn);
+    let response = sender.send_request(request).await?;
+    let status = response.status();
+    // read the body so the server isn't cut off while answering
+    response.into_body().collect().await?;
+    Ok(status)
+}
+
+async fn run_worker(
+    hook: WebhookConfig,
+    outbox: SharedOutbox,
+    tls: TlsConnector,
+    mut deliveries: mpsc::UnboundedReceiver<Delivery>,
+) {
+    while let Some(delivery) = deliveries.recv().await {
+        let mut attempt = 1;
+        loop {
+            match deliver(&hook, &delivery, &tls).await {
+                Ok(()) => break,
+                Err(e) if attempt >= hook.max_attempts => {
+                    error!(
+                        delivery = delivery.id,
+                        url = %hook.url,
+                        attempt,
+                        "giving up on webhook delivery: {}",
+                        e
+                    );
+                    break;
+                }
+                Err(e) => {
+                    warn!(
+                        delivery = delivery.id,
+                        url = %hook.url,
+                        attempt,
+                        "webhook delivery failed, retrying: {}",
+                        e
+                    );
+                    sleep(hook.backoff(attempt)).await;
+                    attempt += 1;
+                }
+            }
+        }
+        lock(&outbox).remove(delivery.id);
+    }
+}
+
+/// Start delivering the events of the inventory to the hooks. Deliveries left in the
+/// outbox go first; those for hooks no longer configured, or now at another URL, are dropped.
+/// Aborting the returned task stops all deliveries. Hooks with an invalid URL are refused.
+pub fn start_webhooks(
+    hooks: Vec<WebhookConfig>,
+    outbox: Option<PathBuf>,
+    manager: &InventoryManager,
+) -> Result<JoinHandle<()>, WebhookError> {
+    for hook in &hooks {
+        hook.check()?;
+    }
+    let tls = ClientTlsConfig::default().connector()?;
+    let outbox = Arc::new(Mutex::new(Outbox::open(outbox)?));
+    let mut events = manager.subscribe();
+    let manager = manager.clone();
+
+    let mut workers = JoinSet::new();
+    // by hook index, the secret and the filter go with the hook rather than its URL
+    let mut queues: Vec<mpsc::UnboundedSender<Delivery>> = Vec::new();
+    for hook in &hooks {
+        let (queue, deliveries) = mpsc::unbounded_channel();
+        queues.push(queue);
+        workers.spawn(run_worker(
+            hook.clone(),
+            outbox.clone(),
+            tls.clone(),
+            deliveries,
+        ));
+    }
+    {
+        // the workers wait for the lock, the log is started before they remove anything
+        let mut outbox = lock(&outbox);
+        let pending: Vec<Delivery> = outbox.pending().cloned().collect();
+        for delivery in pending {
+            match hooks.get(delivery.hook) {
+                Some(hook) if hook.url == delivery.url => {
+                    let _ = queues[delivery.hook].send(delivery);
+                }
+                _ => outbox.remove(delivery.id),
+            }
+        }
+        outbox.start_log()?;
+    }
+
+    Ok(tokio::spawn(async move {
+        let _workers = workers; // aborted along with this task
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(count)) => {
+                    warn!("webhooks missed {count} events");
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+            for (index, hook) in hooks.iter().enumerate() {
+                if !hook.wants(&event, &manager) {
+                    continue;
+                }
+                let delivery = lock(&outbox).add(index, &hook.url, &event);
+                let _ = queues[index].send(delivery);
+            }
+        }
+    }))
+}
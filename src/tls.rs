@@ -0,0 +1,98 @@
+//! TLS settings of the client, and the PEM loading shared with the server.
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::{ClientConfig, RootCertStore};
+use thiserror::Error;
+use tokio_rustls::TlsConnector;
+
+pub const CA_CERT_ENV: &str = "RP_CA_CERT";
+pub const CLIENT_CERT_ENV: &str = "RP_CLIENT_CERT";
+pub const CLIENT_KEY_ENV: &str = "RP_CLIENT_KEY";
+
+#[derive(Error, Debug)]
+pub enum TlsError {
+    #[error("Cannot read {0:?}: {1}")]
+    Unreadable(PathBuf, rustls::pki_types::pem::Error),
+    #[error("No certificate in {0:?}")]
+    NoCertificate(PathBuf),
+    #[error("A client certificate needs a key, and a key a certificate")]
+    Incomplete,
+    #[error("Invalid certificate authority: {0}")]
+    InvalidCa(String),
+    #[error("TLS error: {0}")]
+    Rustls(#[from] rustls::Error),
+}
+
+pub(crate) fn provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+pub(crate) fn load_certificates(path: &Path) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+    let certificates = CertificateDer::pem_file_iter(path)
+        .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| TlsError::Unreadable(path.into(), e))?;
+    if certificates.is_empty() {
+        return Err(TlsError::NoCertificate(path.into()));
+    }
+    Ok(certificates)
+}
+
+pub(crate) fn load_private_key(path: &Path) -> Result<PrivateKeyDer<'static>, TlsError> {
+    PrivateKeyDer::from_pem_file(path).map_err(|e| TlsError::Unreadable(path.into(), e))
+}
+
+pub(crate) fn load_roots(path: &Path) -> Result<RootCertStore, TlsError> {
+    let mut roots = RootCertStore::empty();
+    for certificate in load_certificates(path)? {
+        roots
+            .add(certificate)
+            .map_err(|e| TlsError::InvalidCa(e.to_string()))?;
+    }
+    Ok(roots)
+}
+
+/// How the client connects to `https://` servers.
+/// Without a CA, the server certificate has to be signed by one of the well known authorities.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClientTlsConfig {
+    /// PEM file with the certificate authorities to trust
+    pub ca: Option<PathBuf>,
+    /// PEM files with the client certificate and its key, for servers asking for one
+    pub certificate: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+}
+
+impl ClientTlsConfig {
+    /// Read from `$RP_CA_CERT`, `$RP_CLIENT_CERT` and `$RP_CLIENT_KEY`
+    pub fn from_env() -> ClientTlsConfig {
+        let path = |name| std::env::var_os(name).map(PathBuf::from);
+        ClientTlsConfig {
+            ca: path(CA_CERT_ENV),
+            certificate: path(CLIENT_CERT_ENV),
+            key: path(CLIENT_KEY_ENV),
+        }
+    }
+
+    pub fn connector(&self) -> Result<TlsConnector, TlsError> {
+        let roots = match &self.ca {
+            Some(ca) => load_roots(ca)?,
+            None => RootCertStore {
+                roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+            },
+        };
+        let builder = ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(roots);
+        let config = match (&self.certificate, &self.key) {
+            (Some(certificate), Some(key)) => builder
+                .with_client_auth_cert(load_certificates(certificate)?, load_private_key(key)?)?,
+            (None, None) => builder.with_no_client_auth(),
+            _ => return Err(TlsError::Incomplete),
+        };
+        Ok(TlsConnector::from(Arc::new(config)))
+    }
+}
@@ -0,0 +1,318 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{
+        body_string, build_pool, by_name, next_event, send, serve, serve_with, start_server,
+    };
+    use http_body_util::Empty;
+    use hyper::body::Bytes;
+    use hyper::{Method, Request, StatusCode};
+    use hyper_util::rt::TokioIo;
+    use rp::auth::Credentials;
+    use rp::config::ServerConfigLoader;
+    use rp::events::InventoryEvent;
+    use rp::history::{LeaseHistory, LeaseHistoryEntry, UsageReport};
+    use rp::inventory::{
+        Caller, Inventory, InventoryManager, InventoryStatus, Pool, ResourceRequest,
+        ResourceRequestError, Wait,
+    };
+    use rp::server::RespoServer;
+    use rp::server::auth::{AuthConfig, Authentication, TokenConfig};
+    use std::collections::HashMap;
+    use std::net::SocketAddr;
+    use tokio::net::TcpStream;
+    use tokio::time::Duration;
+
+    fn restricted_pool(name: &str, allowed: &[&str]) -> Pool {
+        Pool {
+            allowed: allowed.iter().map(|allowed| allowed.to_string()).collect(),
+            ..build_pool(name, "lab1")
+        }
+    }
+    fn member(name: &str, groups: &[&str]) -> Caller {
+        Caller {
+            name: name.into(),
+            groups: groups.iter().map(|group| group.to_string()).collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_restricted_pool_is_invisible_to_outsiders() {
+        let manager = InventoryManager::new(Inventory {
+            pools: vec![restricted_pool("prototype", &["team-a", "carol"])],
+        });
+        let waiting = ResourceRequest {
+            wait: Wait::For(Duration::from_secs(60)),
+            ..by_name("prototype")
+        };
+
+        // not queued either: for an outsider the pool doesn't exist
+        assert!(matches!(
+            manager
+                .request_as(&waiting, &member("mallory", &["team-b"]))
+                .await,
+            Err(ResourceRequestError::Impossible)
+        ));
+        assert!(matches!(
+            manager.request_for(&waiting, "mallory").await,
+            Err(ResourceRequestError::Impossible)
+        ));
+
+        let lease = manager
+            .request_as(&waiting, &member("alice", &["team-a"]))
+            .await
+            .unwrap();
+        drop(lease);
+        let lease = manager.request_for(&waiting, "carol").await.unwrap();
+        drop(lease);
+    }
+
+    #[tokio::test]
+    async fn test_restricted_pool_is_left_out_of_queries() {
+        let (address, manager) = start_server(vec![
+            restricted_pool("prototype", &["carol"]),
+            build_pool("pool1", "lab1"),
+        ])
+        .await;
+        let _lease = manager
+            .request_for(&by_name("prototype"), "carol")
+            .await
+            .unwrap();
+        let get = |path: &'static str| send(address, Method::GET, path);
+
+        let status = |body: String| serde_json::from_str::<InventoryStatus>(&body).unwrap();
+        let mallory = status(body_string(get("/status?client_name=mallory").await).await);
+        assert_eq!(mallory.pools.len(), 1);
+        assert_eq!(mallory.pools[0].name, "pool1");
+        assert!(mallory.leases.is_empty());
+        let carol = status(body_string(get("/status?client_name=carol").await).await);
+        assert_eq!(carol.pools.len(), 2);
+        assert_eq!(carol.leases.len(), 1);
+
+        let response = get("/pool?name=prototype&client_name=mallory").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = get("/pool?name=prototype&client_name=carol").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = get("/leases?client_name=mallory").await;
+        assert_eq!(body_string(response).await, "[]");
+    }
+
+    #[tokio::test]
+    async fn test_restricted_pool_is_left_out_of_events() {
+        let (address, manager) = start_server(vec![
+            restricted_pool("prototype", &["carol"]),
+            build_pool("pool1", "lab1"),
+        ])
+        .await;
+        let response = send(address, Method::GET, "/events?client_name=mallory").await;
+        let mut body = response.into_body();
+        let mut buffer = String::new();
+
+        let _prototype = manager
+            .request_for(&by_name("prototype"), "carol")
+            .await
+            .unwrap();
+        let _pool1 = manager.request_for(&by_name("pool1"), "alice").await;
+        let waiting = ResourceRequest {
+            wait: Wait::For(Duration::from_secs(60)),
+            ..Default::default()
+        };
+        let waiter = {
+            let manager = manager.clone();
+            tokio::spawn(async move { manager.request_for(&waiting, "carol").await })
+        };
+
+        assert!(matches!(
+            next_event(&mut body, &mut buffer).await,
+            InventoryEvent::LeaseGranted { pool, .. } if pool == "pool1"
+        ));
+        // queued on both pools, only the one mallory may use is told
+        assert_eq!(
+            next_event(&mut body, &mut buffer).await,
+            InventoryEvent::Queued {
+                client: "carol".into(),
+                pools: vec!["pool1".into()],
+            }
+        );
+        waiter.abort();
+    }
+
+    #[tokio::test]
+    async fn test_restricted_pool_is_left_out_of_reports() {
+        let path =
+            std::env::temp_dir().join(format!("respo-history-access-{}.jsonl", std::process::id()));
+        let granted_at = chrono::Utc::now() - chrono::Duration::hours(2);
+        let entry = |client: &str, pool: &str| LeaseHistoryEntry {
+            lease_id: 1,
+            client: client.into(),
+            pool: pool.into(),
+            location: "lab1".into(),
+            requested_at: granted_at,
+            granted_at,
+            ended_at: granted_at + chrono::Duration::hours(1),
+            waited_seconds: 0.0,
+            held_seconds: 3600.0,
+            expired: false,
+        };
+        let lines: String = [entry("carol", "prototype"), entry("alice", "pool1")]
+            .iter()
+            .map(|entry| serde_json::to_string(entry).unwrap() + "\n")
+            .collect();
+        std::fs::write(&path, lines).unwrap();
+        let manager = InventoryManager::with_history(
+            Inventory {
+                pools: vec![
+                    restricted_pool("prototype", &["carol"]),
+                    build_pool("pool1", "lab1"),
+                ],
+            },
+            LeaseHistory::open(path.clone()),
+        );
+        let address = serve(manager).await;
+
+        let pools = |client: &'static str| async move {
+            let path = format!("/reports/usage?group_by=pool&client_name={client}");
+            let response = send(address, Method::GET, &path).await;
+            let report: UsageReport = serde_json::from_str(&body_string(response).await).unwrap();
+            report
+                .rows
+                .into_iter()
+                .filter_map(|row| row.pool)
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(pools("mallory").await, vec!["pool1"]);
+        assert_eq!(pools("carol").await.len(), 2);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_force_release_hands_over() {
+        let manager = InventoryManager::new(Inventory {
+            pools: vec![build_pool("pool1", "lab1")],
+        });
+        let _stuck = manager
+            .request_for(&by_name("pool1"), "client_a")
+            .await
+            .unwrap();
+        let waiting = ResourceRequest {
+            wait: Wait::For(Duration::from_secs(60)),
+            ..by_name("pool1")
+        };
+        let waiter = {
+            let manager = manager.clone();
+            tokio::spawn(async move { manager.request_for(&waiting, "client_b").await })
+        };
+        while manager.status().await.pools[0].queue_length == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        manager.force_release("pool1").await.unwrap();
+        let _lease = waiter.await.unwrap().unwrap();
+        assert_eq!(
+            manager.status().await.pools[0].holder.as_deref(),
+            Some("client_b")
+        );
+        assert!(manager.force_release("nonexistent").await.is_err());
+    }
+
+    async fn call(address: SocketAddr, method: Method, path: &str, token: &str) -> StatusCode {
+        let stream = TcpStream::connect(address).await.unwrap();
+        let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .unwrap();
+        tokio::spawn(conn);
+        let credentials = Credentials::Token {
+            token: token.into(),
+        };
+        let request = Request::builder()
+            .method(method)
+            .uri(path)
+            .header(hyper::header::HOST, address.to_string())
+            .header(hyper::header::AUTHORIZATION, credentials.authorization())
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        let response = sender.send_request(request).await.unwrap();
+        let status = response.status();
+        body_string(response).await;
+        status
+    }
+
+    #[tokio::test]
+    async fn test_admin_endpoints_need_admin_role() {
+        let token = |token: &str, user: &str| TokenConfig {
+            token: token.into(),
+            user: user.into(),
+        };
+        let config = AuthConfig {
+            tokens: vec![token("user-token", "alice"), token("ops-token", "olivia")],
+            groups: HashMap::from([("ops".into(), vec!["olivia".into()])]),
+            admins: vec!["ops".into()],
+            ..Default::default()
+        };
+        let manager = InventoryManager::new(Inventory {
+            pools: vec![build_pool("pool1", "lab1")],
+        });
+        let server = RespoServer::new(manager.clone())
+            .with_authentication(Authentication::from_config(&config).unwrap());
+        let address = serve_with(server).await;
+
+        for path in [
+            "/maintenance?pool=pool1&enabled=true",
+            "/release?pool=pool1",
+        ] {
+            assert_eq!(
+                call(address, Method::POST, path, "user-token").await,
+                StatusCode::FORBIDDEN
+            );
+            assert_eq!(
+                call(address, Method::POST, path, "ops-token").await,
+                StatusCode::OK
+            );
+        }
+        assert!(manager.status().await.pools[0].maintenance);
+        // the metrics name every pool
+        assert_eq!(
+            call(address, Method::GET, "/metrics", "user-token").await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            call(address, Method::GET, "/metrics", "ops-token").await,
+            StatusCode::OK
+        );
+    }
+
+    #[test]
+    fn test_config_with_roles_and_acls() {
+        let config = ServerConfigLoader::load(
+            r#"
+pools:
+  - name: prototype
+    attributes: []
+    location: lab1
+    resources: []
+    allowed: [team-a]
+auth:
+  tokens:
+    - token: abc
+      user: alice
+  groups:
+    team-a: [alice]
+  admins: [bob]
+"#
+            .as_bytes(),
+        );
+        assert_eq!(config.inventory.pools[0].allowed, vec!["team-a"]);
+        let auth = config.auth.unwrap();
+        assert_eq!(auth.groups["team-a"], vec!["alice"]);
+        assert_eq!(auth.admins, vec!["bob"]);
+
+        let identity = Authentication::from_config(&auth)
+            .unwrap()
+            .authenticate("Bearer abc")
+            .unwrap();
+        assert_eq!(identity.groups, vec!["team-a"]);
+        assert!(!identity.admin);
+        assert!(identity.caller().may_use(&config.inventory.pools[0]));
+    }
+}
@@ -0,0 +1,354 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{build_pool, by_name};
+    use rp::events::InventoryEvent;
+    use rp::inventory::{
+        Caller, DEFAULT_LEASE_TIME, Inventory, InventoryError, InventoryManager, PoolLease,
+        ResourceRequest, ResourceRequestError, Wait,
+    };
+    use tokio::sync::broadcast::Receiver;
+    use tokio::time::{Duration, Instant, sleep};
+
+    fn build_manager(names: &[&str]) -> InventoryManager {
+        InventoryManager::new(Inventory {
+            pools: names
+                .iter()
+                .map(|name| build_pool(name, "location1"))
+                .collect(),
+        })
+    }
+    async fn expiring(manager: &InventoryManager, pool: &str, client: &str) -> PoolLease {
+        manager
+            .request_expiring(&by_name(pool), &Caller::new(client), DEFAULT_LEASE_TIME)
+            .await
+            .unwrap()
+    }
+    fn drain(events: &mut Receiver<InventoryEvent>) -> Vec<InventoryEvent> {
+        std::iter::from_fn(|| events.try_recv().ok()).collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_lease_expires() {
+        let manager = build_manager(&["pool1"]);
+        let mut events = manager.subscribe();
+        let lease = expiring(&manager, "pool1", "client_a").await;
+        assert_eq!(lease.leasetime(), Some(DEFAULT_LEASE_TIME));
+
+        sleep(DEFAULT_LEASE_TIME - Duration::from_secs(1)).await;
+        assert!(manager.is_leased("pool1").await);
+        sleep(Duration::from_secs(2)).await;
+        assert!(!manager.is_leased("pool1").await);
+        assert!(matches!(
+            lease.renew().await,
+            Err(ResourceRequestError::NotHeld)
+        ));
+
+        let events = drain(&mut events);
+        assert!(matches!(
+            events.as_slice(),
+            [
+                InventoryEvent::LeaseGranted { .. },
+                InventoryEvent::LeaseExpired { pool, client, .. }
+            ] if pool == "pool1" && client == "client_a"
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_local_lease_lasts_until_released() {
+        let manager = build_manager(&["pool1"]);
+        let lease = manager
+            .request_for(&by_name("pool1"), "client_a")
+            .await
+            .unwrap();
+        assert_eq!(lease.leasetime(), None);
+
+        sleep(DEFAULT_LEASE_TIME * 10).await;
+        assert!(manager.is_leased("pool1").await);
+        assert!(lease.renew().await.is_ok());
+        assert_eq!(manager.status().await.leases[0].expires_at, None);
+        drop(lease);
+        assert!(!manager.is_leased("pool1").await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_renew_extends_lease() {
+        let manager = build_manager(&["pool1"]);
+        let lease = expiring(&manager, "pool1", "client_a").await;
+
+        sleep(DEFAULT_LEASE_TIME - Duration::from_secs(10)).await;
+        assert!(lease.renew().await.is_ok());
+        sleep(Duration::from_secs(20)).await;
+        assert!(manager.is_leased("pool1").await);
+        drop(lease);
+        assert!(!manager.is_leased("pool1").await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_expired_lease_goes_to_waiter() {
+        let manager = build_manager(&["pool1"]);
+        let _lease = expiring(&manager, "pool1", "client_a").await;
+        let patient = ResourceRequest {
+            wait: Wait::For(DEFAULT_LEASE_TIME * 2),
+            ..by_name("pool1")
+        };
+
+        let start = Instant::now();
+        assert!(manager.request_for(&patient, "client_b").await.is_ok());
+        assert_eq!(Instant::now() - start, DEFAULT_LEASE_TIME);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_waiter_times_out_at_deadline() {
+        let manager = build_manager(&["pool1"]);
+        let _lease = manager
+            .request_for(&by_name("pool1"), "client_a")
+            .await
+            .unwrap();
+        let impatient = ResourceRequest {
+            wait: Wait::For(Duration::from_secs(10)),
+            ..by_name("pool1")
+        };
+
+        let start = Instant::now();
+        assert!(matches!(
+            manager.request_for(&impatient, "client_b").await,
+            Err(ResourceRequestError::TimeOut)
+        ));
+        assert_eq!(Instant::now() - start, Duration::from_secs(10));
+        assert_eq!(manager.status().await.pools[0].queue_length, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_events_in_order() {
+        let manager = build_manager(&["pool1"]);
+        let mut events = manager.subscribe();
+        let lease = manager
+            .request_for(&by_name("pool1"), "client_a")
+            .await
+            .unwrap();
+        let waiting = ResourceRequest {
+            wait: Wait::For(Duration::from_secs(10)),
+            ..by_name("pool1")
+        };
+
+        let (lease_b, _) = tokio::join!(manager.request_for(&waiting, "client_b"), async {
+            sleep(Duration::from_secs(1)).await;
+            drop(lease);
+        });
+        assert!(lease_b.is_ok());
+
+        let kinds: Vec<(&str, String)> = drain(&mut events)
+            .into_iter()
+            .map(|event| match event {
+                InventoryEvent::LeaseGranted { client, .. } => ("granted", client),
+                InventoryEvent::Queued { client, .. } => ("queued", client),
+                InventoryEvent::LeaseReleased { client, .. } => ("released", client),
+                other => panic!("unexpected event {other:?}"),
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ("granted", "client_a".into()),
+                ("queued", "client_b".into()),
+                ("released", "client_a".into()),
+                ("granted", "client_b".into()),
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_status_reports_holder_and_queue() {
+        let manager = build_manager(&["pool1", "pool2"]);
+        let _lease = manager
+            .request_for(&by_name("pool1"), "client_a")
+            .await
+            .unwrap();
+        let waiting = ResourceRequest {
+            wait: Wait::For(Duration::from_secs(10)),
+            ..by_name("pool1")
+        };
+
+        tokio::select! {
+            _ = manager.request_for(&waiting, "client_b") => panic!("pool1 is held"),
+            status = async {
+                sleep(Duration::from_secs(1)).await;
+                manager.status().await
+            } => {
+                assert_eq!(status.pools[0].holder.as_deref(), Some("client_a"));
+                assert_eq!(status.pools[0].queue_length, 1);
+                assert_eq!(status.pools[1].holder, None);
+                assert_eq!(status.pools[1].queue_length, 0);
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_update_inventory_keeps_leases_and_requeues() {
+        let manager = build_manager(&["pool1"]);
+        let _lease = manager
+            .request_for(&by_name("pool1"), "client_a")
+            .await
+            .unwrap();
+        let any_pool = ResourceRequest {
+            pool_attributes: Some(vec!["attr1".into()]),
+            wait: Wait::For(Duration::from_secs(10)),
+            ..Default::default()
+        };
+
+        let (lease_b, _) = tokio::join!(manager.request_for(&any_pool, "client_b"), async {
+            sleep(Duration::from_secs(1)).await;
+            let inventory = Inventory {
+                pools: vec![
+                    build_pool("pool1", "location1"),
+                    build_pool("pool2", "location1"),
+                ],
+            };
+            manager.update_inventory(inventory).await.unwrap();
+        });
+        assert!(lease_b.is_ok());
+        assert!(manager.is_leased("pool1").await);
+        assert!(manager.is_leased("pool2").await);
+        assert_eq!(manager.pools().len(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_update_inventory_removing_pool_fails_waiters() {
+        let manager = build_manager(&["pool1", "pool2"]);
+        let lease = manager
+            .request_for(&by_name("pool1"), "client_a")
+            .await
+            .unwrap();
+        let waiting = ResourceRequest {
+            wait: Wait::For(Duration::from_secs(10)),
+            ..by_name("pool1")
+        };
+
+        let (result, _) = tokio::join!(manager.request_for(&waiting, "client_b"), async {
+            sleep(Duration::from_secs(1)).await;
+            let inventory = Inventory {
+                pools: vec![build_pool("pool2", "location1")],
+            };
+            manager.update_inventory(inventory).await.unwrap();
+        });
+        assert!(matches!(result, Err(ResourceRequestError::Impossible)));
+        // the lease on the removed pool can still be dropped
+        drop(lease);
+        assert!(!manager.is_leased("pool2").await);
+    }
+
+    #[tokio::test]
+    async fn test_lease_on_removed_pool_ends_with_an_event() {
+        let manager = build_manager(&["pool1", "pool2"]);
+        let lease = manager
+            .request_for(&by_name("pool1"), "client_a")
+            .await
+            .unwrap();
+        let inventory = Inventory {
+            pools: vec![build_pool("pool2", "location1")],
+        };
+        manager.update_inventory(inventory).await.unwrap();
+        let mut events = manager.subscribe();
+
+        let lease_id = lease.id();
+        drop(lease);
+        manager.status().await; // the release is handled before
+        assert_eq!(
+            drain(&mut events),
+            vec![InventoryEvent::LeaseReleased {
+                lease_id,
+                pool: "pool1".into(),
+                client: "client_a".into(),
+            }]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_wait_forever() {
+        let manager = build_manager(&["pool1"]);
+        manager.set_maintenance("pool1", true).await.unwrap();
+        let forever = ResourceRequest {
+            wait: Wait::Forever,
+            ..by_name("pool1")
+        };
+
+        let start = Instant::now();
+        let (granted, _) = tokio::join!(
+            async {
+                let lease = manager.request_for(&forever, "client_b").await;
+                (lease, Instant::now())
+            },
+            async {
+                sleep(Duration::from_secs(24 * 3600)).await;
+                manager.set_maintenance("pool1", false).await.unwrap();
+            }
+        );
+        assert!(granted.0.is_ok());
+        assert_eq!(granted.1 - start, Duration::from_secs(24 * 3600));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_abandoned_waiter_is_forgotten() {
+        let manager = build_manager(&["pool1"]);
+        let _lease = manager
+            .request_for(&by_name("pool1"), "client_a")
+            .await
+            .unwrap();
+        let forever = ResourceRequest {
+            wait: Wait::Forever,
+            ..by_name("pool1")
+        };
+        let abandoned = {
+            let manager = manager.clone();
+            tokio::spawn(async move { manager.request_for(&forever, "client_b").await })
+        };
+        while manager.status().await.pools[0].queue_length < 1 {
+            tokio::task::yield_now().await;
+        }
+
+        abandoned.abort();
+        sleep(Duration::from_secs(60)).await;
+        assert_eq!(manager.status().await.pools[0].queue_length, 0);
+    }
+
+    #[test]
+    fn test_wait_parsing() {
+        for (text, wait) in [
+            ("nowait", Wait::NoWait),
+            ("forever", Wait::Forever),
+            ("90", Wait::For(Duration::from_secs(90))),
+            ("90s", Wait::For(Duration::from_secs(90))),
+            ("2h", Wait::For(Duration::from_secs(7200))),
+            ("1h 30m", Wait::For(Duration::from_secs(5400))),
+        ] {
+            assert_eq!(text.parse::<Wait>().unwrap(), wait, "{text}");
+        }
+        assert!("soon".parse::<Wait>().is_err());
+        assert!("-5s".parse::<Wait>().is_err());
+        // what is displayed parses back
+        let wait = Wait::For(Duration::from_secs(5400));
+        assert_eq!(wait.to_string(), "1h 30m");
+        assert_eq!(wait.to_string().parse::<Wait>().unwrap(), wait);
+        assert_eq!(
+            serde_json::to_string(&Wait::Forever).unwrap(),
+            "\"forever\""
+        );
+    }
+
+    #[tokio::test]
+    async fn test_update_inventory_rejects_duplicate_names() {
+        let manager = build_manager(&["pool1"]);
+        let inventory = Inventory {
+            pools: vec![
+                build_pool("pool1", "location1"),
+                build_pool("pool1", "location1"),
+            ],
+        };
+        assert!(matches!(
+            manager.update_inventory(inventory).await,
+            Err(InventoryError::DuplicatePoolName(name)) if name == "pool1"
+        ));
+    }
+}
@@ -0,0 +1,171 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{body_string, build_pool, serve_with};
+    use http_body_util::Empty;
+    use hyper::body::{Bytes, Incoming};
+    use hyper::{Request, Response, StatusCode};
+    use hyper_util::rt::TokioIo;
+    use rp::auth::Credentials;
+    use rp::client::{ClientResourceRequestError, RemoteRespoClientFactory};
+    use rp::events::InventoryEvent;
+    use rp::inventory::{Inventory, InventoryManager, ResourceRequest};
+    use rp::server::RespoServer;
+    use rp::server::auth::{AuthConfig, Authentication, TokenConfig};
+    use std::net::SocketAddr;
+    use std::path::PathBuf;
+    use tokio::net::TcpStream;
+
+    fn token(token: &str) -> Credentials {
+        Credentials::Token {
+            token: token.into(),
+        }
+    }
+    fn password(user: &str, password: &str) -> Credentials {
+        Credentials::Password {
+            user: user.into(),
+            password: password.into(),
+        }
+    }
+    fn temporary_file(name: &str, content: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("respo-auth-{}-{}", name, std::process::id()));
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    /// A server with a token for ci-bot and a password for alice
+    async fn start_authenticating_server(test: &str) -> (SocketAddr, InventoryManager) {
+        let hash = bcrypt::hash("wonderland", 4).unwrap();
+        let htpasswd = temporary_file(test, &format!("# users\nalice:{}\n", hash));
+        let config = AuthConfig {
+            tokens: vec![TokenConfig {
+                token: "s3cret".into(),
+                user: "ci-bot".into(),
+            }],
+            htpasswd: Some(htpasswd.clone()),
+            ..Default::default()
+        };
+        let authentication = Authentication::from_config(&config).unwrap();
+        std::fs::remove_file(htpasswd).unwrap();
+        let manager = InventoryManager::new(Inventory {
+            pools: vec![build_pool("pool1", "lab1")],
+        });
+        let server = RespoServer::new(manager.clone()).with_authentication(authentication);
+        (serve_with(server).await, manager)
+    }
+
+    async fn lock(address: SocketAddr, credentials: Option<&Credentials>) -> Response<Incoming> {
+        let stream = TcpStream::connect(address).await.unwrap();
+        let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .unwrap();
+        tokio::spawn(conn);
+        let mut request = Request::builder()
+            .uri(format!(
+                "http://{address}/lock?client_name=mallory&by_name=pool1"
+            ))
+            .header(hyper::header::HOST, address.to_string());
+        if let Some(credentials) = credentials {
+            request = request.header(hyper::header::AUTHORIZATION, credentials.authorization());
+        }
+        sender
+            .send_request(request.body(Empty::<Bytes>::new()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn test_authorization_header_round_trip() {
+        for credentials in [token("s3cret"), password("alice", "a:b c")] {
+            assert_eq!(
+                Credentials::from_authorization(&credentials.authorization()),
+                Some(credentials)
+            );
+        }
+        assert_eq!(Credentials::from_authorization("Digest whatever"), None);
+        assert_eq!(Credentials::from_authorization("Basic not-base64!"), None);
+    }
+
+    #[test]
+    fn test_credentials_file() {
+        let path = temporary_file("token.yaml", "token: s3cret\n");
+        assert_eq!(
+            Credentials::from_file(path.clone()).unwrap(),
+            token("s3cret")
+        );
+        std::fs::write(&path, "user: alice\npassword: wonderland\n").unwrap();
+        assert_eq!(
+            Credentials::from_file(path.clone()).unwrap(),
+            password("alice", "wonderland")
+        );
+        std::fs::remove_file(path).unwrap();
+        assert!(!format!("{:?}", password("alice", "wonderland")).contains("wonderland"));
+    }
+
+    #[tokio::test]
+    async fn test_unauthenticated_requests_are_refused() {
+        let (address, manager) = start_authenticating_server("refused").await;
+        for credentials in [
+            None,
+            Some(token("guess")),
+            Some(password("alice", "guess")),
+            Some(password("bob", "wonderland")),
+        ] {
+            let response = lock(address, credentials.as_ref()).await;
+            assert_eq!(
+                response.status(),
+                StatusCode::UNAUTHORIZED,
+                "{credentials:?}"
+            );
+            assert!(
+                response
+                    .headers()
+                    .contains_key(hyper::header::WWW_AUTHENTICATE)
+            );
+        }
+        assert!(!manager.is_leased("pool1").await);
+    }
+
+    #[tokio::test]
+    async fn test_identity_replaces_client_name() {
+        let (address, manager) = start_authenticating_server("identity").await;
+        let mut events = manager.subscribe();
+        for (credentials, identity) in [
+            (token("s3cret"), "ci-bot"),
+            (password("alice", "wonderland"), "alice"),
+        ] {
+            let response = lock(address, Some(&credentials)).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            body_string(response).await;
+            loop {
+                if let InventoryEvent::LeaseGranted { client, .. } = events.recv().await.unwrap() {
+                    assert_eq!(client, identity);
+                    break;
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_remote_client_sends_credentials() {
+        let (address, _manager) = start_authenticating_server("client").await;
+        let url = format!("http://{address}/lock");
+        let request = ResourceRequest {
+            by_name: Some("pool1".into()),
+            ..Default::default()
+        };
+
+        let mut factory = RemoteRespoClientFactory::new(url.clone());
+        let mut client = factory.create("anyone".into());
+        assert!(matches!(
+            client.request(&request).await,
+            Err(ClientResourceRequestError::Unauthorized)
+        ));
+
+        let mut factory =
+            RemoteRespoClientFactory::new(url).with_credentials(Some(token("s3cret")));
+        let mut client = factory.create("anyone".into());
+        assert!(client.request(&request).await.is_ok());
+    }
+}
@@ -0,0 +1,373 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{body_string, build_pool, by_name, send, serve, start_server};
+    use hyper::{Method, StatusCode};
+    use rp::client::http::Timeouts;
+    use rp::client::lease::LeaseLost;
+    use rp::client::{ClientResourceRequestError, RemoteRespoClientFactory};
+    use rp::inventory::{
+        Inventory, InventoryManager, Pool, ResourceRequest, ResourceRequestError, Wait,
+    };
+    use rp::preemption::PreemptionPolicy;
+    use rp::server::RespoServer;
+    use std::collections::HashMap;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::time::{Duration, Instant, sleep, timeout};
+
+    fn timeouts(response: Duration, retry: Duration) -> Timeouts {
+        Timeouts {
+            response,
+            retry,
+            ..Default::default()
+        }
+    }
+    async fn free_address() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap()
+    }
+    fn build_manager() -> InventoryManager {
+        InventoryManager::new(Inventory {
+            pools: vec![build_pool("pool1", "lab1")],
+        })
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_server_is_an_error() {
+        let address = free_address().await;
+        let mut factory = RemoteRespoClientFactory::new(format!("http://{address}/lock"))
+            .with_timeouts(timeouts(Duration::from_secs(1), Duration::from_millis(500)));
+        let mut client = factory.create("client_a".into());
+
+        let start = Instant::now();
+        let error = client.request(&by_name("pool1")).await.unwrap_err();
+        assert!(error.is_connection_failure(), "{error}");
+        assert!(start.elapsed() >= Duration::from_millis(300));
+    }
+
+    #[tokio::test]
+    async fn test_retries_until_the_server_is_up() {
+        let address = free_address().await;
+        let mut factory = RemoteRespoClientFactory::new(format!("http://{address}/lock"));
+        let mut client = factory.create("client_a".into());
+
+        tokio::spawn(async move {
+            sleep(Duration::from_millis(500)).await;
+            let listener = TcpListener::bind(address).await.unwrap();
+            Arc::new(RespoServer::new(build_manager()))
+                .serve(listener)
+                .await
+        });
+        assert!(client.request(&by_name("pool1")).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_lost_connection_is_not_retried() {
+        // reads the request, hangs up without answering
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+        {
+            let connections = connections.clone();
+            tokio::spawn(async move {
+                while let Ok((mut stream, _)) = listener.accept().await {
+                    connections.fetch_add(1, Ordering::SeqCst);
+                    let mut request = [0; 4096];
+                    let _ = stream.read(&mut request).await;
+                }
+            });
+        }
+        let mut factory = RemoteRespoClientFactory::new(format!("http://{address}/lock"));
+        let mut client = factory.create("client_a".into());
+
+        let error = client.request(&by_name("pool1")).await.unwrap_err();
+        assert!(!error.is_connection_failure(), "{error}");
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_response_timeout() {
+        // accepts connections, never answers
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut connections = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                connections.push(stream);
+            }
+        });
+        let mut factory = RemoteRespoClientFactory::new(format!("http://{address}/lock"))
+            .with_timeouts(timeouts(Duration::from_millis(200), Duration::ZERO));
+        let mut client = factory.create("client_a".into());
+
+        assert!(matches!(
+            client.request(&by_name("pool1")).await,
+            Err(ClientResourceRequestError::TimedOut)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_clients_of_a_factory_share_connections() {
+        let server = serve(build_manager()).await;
+        // counts the connections on their way to the server
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+        {
+            let connections = connections.clone();
+            tokio::spawn(async move {
+                while let Ok((mut inbound, _)) = listener.accept().await {
+                    connections.fetch_add(1, Ordering::SeqCst);
+                    tokio::spawn(async move {
+                        let mut outbound = TcpStream::connect(server).await.unwrap();
+                        let _ = tokio::io::copy_bidirectional(&mut inbound, &mut outbound).await;
+                    });
+                }
+            });
+        }
+
+        let mut factory = RemoteRespoClientFactory::new(format!("http://{address}/lock"));
+        for name in ["client_a", "client_b", "client_c"] {
+            let mut client = factory.create(name.into());
+            let lease = client.request(&by_name("pool1")).await.unwrap();
+            lease.release().await.unwrap();
+        }
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+
+    async fn until_released(manager: &InventoryManager, pool: &str) {
+        timeout(Duration::from_secs(5), async {
+            while manager.is_leased(pool).await {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the pool is released");
+    }
+
+    #[tokio::test]
+    async fn test_remote_lease_is_kept_until_released() {
+        let manager = build_manager();
+        let address = serve(manager.clone()).await;
+        let mut factory = RemoteRespoClientFactory::new(format!("http://{address}/lock"))
+            .with_heartbeat(Duration::from_millis(50));
+        let mut client = factory.create("client_a".into());
+
+        let lease = client.request(&by_name("pool1")).await.unwrap();
+        assert_eq!(lease.pool().name, "pool1");
+        assert_eq!(lease.resources()[0].attributes, vec!["RA1".to_string()]);
+        sleep(Duration::from_millis(200)).await;
+        assert!(manager.is_leased("pool1").await);
+        lease.release().await.unwrap();
+        assert!(!manager.is_leased("pool1").await);
+
+        let lease = client.request(&by_name("pool1")).await.unwrap();
+        drop(lease);
+        until_released(&manager, "pool1").await;
+    }
+
+    #[tokio::test]
+    async fn test_remote_lease_lost() {
+        let manager = build_manager();
+        let address = serve(manager.clone()).await;
+        let mut factory = RemoteRespoClientFactory::new(format!("http://{address}/lock"))
+            .with_heartbeat(Duration::from_millis(50));
+        let mut client = factory.create("client_a".into());
+
+        let lease = client.request(&by_name("pool1")).await.unwrap();
+        manager.force_release("pool1").await.unwrap();
+        let lost = timeout(Duration::from_secs(5), lease.lost()).await.unwrap();
+        assert!(matches!(lost, LeaseLost::Ended(_)), "{lost}");
+        assert!(matches!(
+            lease.release().await,
+            Err(ClientResourceRequestError::NotHeld(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_remote_lease_revocation() {
+        let manager = build_manager();
+        manager
+            .set_preemption(Some(PreemptionPolicy {
+                min_priority: 100,
+                grace_period_secs: 60,
+            }))
+            .await;
+        let address = serve(manager.clone()).await;
+        let mut factory = RemoteRespoClientFactory::new(format!("http://{address}/lock"))
+            .with_heartbeat(Duration::from_millis(50));
+        let mut client = factory.create("nightly".into());
+        let soak_test = ResourceRequest {
+            preemptible: true,
+            ..by_name("pool1")
+        };
+        let lease = client.request(&soak_test).await.unwrap();
+        assert_eq!(lease.grace_period(), Some(Duration::from_secs(60)));
+
+        let hotfix = {
+            let manager = manager.clone();
+            let urgent = ResourceRequest {
+                wait: Wait::For(Duration::from_secs(3600)),
+                priority: Some(100),
+                ..by_name("pool1")
+            };
+            tokio::spawn(async move { manager.request_for(&urgent, "hotfix").await })
+        };
+        let revocation = timeout(Duration::from_secs(5), lease.revocation())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(revocation.by, "hotfix");
+        let left = revocation.at - Instant::now();
+        assert!(left > Duration::from_secs(55) && left <= Duration::from_secs(60));
+
+        lease.release().await.unwrap();
+        assert!(hotfix.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_kept_leases_only() {
+        let (address, manager) = start_server(vec![build_pool("pool1", "lab1")]).await;
+        // the lease of a plain request ends with the answer
+        let response = send(address, Method::GET, "/lock?by_name=pool1").await;
+        let lease: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+        until_released(&manager, "pool1").await;
+        let path = format!("/renew?lease={}", lease["id"]);
+        assert_eq!(
+            send(address, Method::POST, &path).await.status(),
+            StatusCode::GONE
+        );
+
+        let response = send(address, Method::GET, "/lock?by_name=pool1&keep=true").await;
+        let lease: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+        let id = &lease["id"];
+        assert!(manager.is_leased("pool1").await);
+        for (path, status) in [
+            (format!("/renew?lease={id}"), StatusCode::OK),
+            ("/renew".into(), StatusCode::BAD_REQUEST),
+            (format!("/unlock?lease={id}"), StatusCode::OK),
+            (format!("/unlock?lease={id}"), StatusCode::GONE),
+        ] {
+            assert_eq!(
+                send(address, Method::POST, &path).await.status(),
+                status,
+                "{path}"
+            );
+        }
+        assert!(!manager.is_leased("pool1").await);
+    }
+
+    #[tokio::test]
+    async fn test_huge_wait_waits_forever() {
+        let (address, manager) = start_server(vec![build_pool("pool1", "lab1")]).await;
+        let held = manager
+            .request_for(&by_name("pool1"), "client_a")
+            .await
+            .unwrap();
+        let waiting = tokio::spawn(async move {
+            let path = format!("/lock?client_name=b&by_name=pool1&wait={}", u64::MAX);
+            send(address, Method::GET, &path).await.status()
+        });
+        while manager.status().await.pools[0].queue_length < 1 {
+            tokio::task::yield_now().await;
+        }
+        drop(held);
+        assert_eq!(waiting.await.unwrap(), StatusCode::OK);
+        until_released(&manager, "pool1").await;
+
+        // and from the client
+        let mut factory = RemoteRespoClientFactory::new(format!("http://{address}/lock"));
+        let mut client = factory.create("client_a".into());
+        let request = ResourceRequest {
+            wait: Wait::For(Duration::from_secs(u64::MAX)),
+            ..by_name("pool1")
+        };
+        assert!(client.request(&request).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_queries() {
+        let pool = Pool {
+            properties: HashMap::from([("rack".to_string(), 4.into())]),
+            ..build_pool("pool1", "lab1")
+        };
+        let (address, _manager) = start_server(vec![pool, build_pool("pool2", "lab2")]).await;
+        let mut factory = RemoteRespoClientFactory::new(format!("http://{address}/lock"));
+        let mut client_a = factory.create("client_a".into());
+        let client_b = factory.create("client_b".into());
+
+        let lease = client_a.request(&by_name("pool1")).await.unwrap();
+        let status = client_b.status().await.unwrap();
+        assert_eq!(status.pools[0].holder.as_deref(), Some("client_a"));
+        assert_eq!(status.pools[1].holder, None);
+
+        let details = client_b.pool("pool1").await.unwrap();
+        assert_eq!(details.pool.properties["rack"], 4);
+        assert_eq!(details.pool.resources[0].name, "0");
+        assert_eq!(details.lease.unwrap().lease_id, lease.id());
+        assert!(matches!(
+            client_b.pool("pool3").await,
+            Err(ClientResourceRequestError::Refused { status, .. }) if status == StatusCode::NOT_FOUND
+        ));
+
+        let leases = client_a.leases().await.unwrap();
+        assert_eq!(leases.len(), 1);
+        assert_eq!(
+            (leases[0].pool.as_str(), leases[0].client.as_str()),
+            ("pool1", "client_a")
+        );
+        assert!(client_b.leases().await.unwrap().is_empty());
+
+        // the server url will do as well
+        let mut factory = RemoteRespoClientFactory::new(format!("http://{address}"));
+        let client = factory.create("client_c".into());
+        assert_eq!(client.status().await.unwrap().leases.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_refusal_reason() {
+        let (address, _manager) = start_server(vec![build_pool("pool1", "lab1")]).await;
+        let mut factory = RemoteRespoClientFactory::new(format!("http://{address}/lock"));
+        let mut client = factory.create("client_a".into());
+
+        let _lease = client.request(&by_name("pool1")).await.unwrap();
+        assert!(matches!(
+            client.request(&by_name("pool1")).await,
+            Err(ClientResourceRequestError::InventoryError(
+                ResourceRequestError::InUse
+            ))
+        ));
+        let response = send(address, Method::GET, "/lock?by_name=pool2").await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(body_string(response).await, r#""Impossible""#);
+    }
+
+    #[tokio::test]
+    async fn test_other_failures_are_refusals() {
+        // answers like a proxy in front of a server that is down
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = [0; 4096];
+                let _ = stream.read(&mut request).await;
+                let _ = stream
+                    .write_all(b"HTTP/1.1 502 Bad Gateway\r\ncontent-length: 7\r\n\r\nno pool")
+                    .await;
+            }
+        });
+        let mut factory = RemoteRespoClientFactory::new(format!("http://{address}/lock"));
+        let mut client = factory.create("client_a".into());
+
+        assert!(matches!(
+            client.request(&by_name("pool1")).await,
+            Err(ClientResourceRequestError::Refused { status, message })
+                if status == StatusCode::BAD_GATEWAY && message == "no pool"
+        ));
+    }
+}
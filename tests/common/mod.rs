@@ -0,0 +1,104 @@
+//! Helpers for tests that talk to an in-process server
+#![allow(dead_code)]
+
+use http_body_util::{BodyExt, Full};
+use hyper::body::{Bytes, Incoming};
+use hyper::{Method, Request, Response};
+use hyper_util::rt::TokioIo;
+use rp::events::InventoryEvent;
+use rp::inventory::{Inventory, InventoryManager, Pool, Resource, ResourceRequest};
+use rp::server::RespoServer;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::{TcpListener, TcpStream};
+
+pub fn build_pool(name: &str, location: &str) -> Pool {
+    Pool {
+        name: name.into(),
+        attributes: vec!["attr1".into()],
+        location: location.into(),
+        resources: vec![Resource {
+            name: "0".into(),
+            attributes: vec!["RA1".into()],
+            properties: HashMap::new(),
+        }],
+        allowed: vec![],
+        properties: HashMap::new(),
+    }
+}
+
+/// A request for the pool named `name`
+pub fn by_name(name: &str) -> ResourceRequest {
+    ResourceRequest {
+        by_name: Some(name.into()),
+        ..Default::default()
+    }
+}
+
+/// Serve the pools on an ephemeral port
+pub async fn start_server(pools: Vec<Pool>) -> (SocketAddr, InventoryManager) {
+    let manager = InventoryManager::new(Inventory { pools });
+    (serve(manager.clone()).await, manager)
+}
+
+/// Serve the inventory of the manager on an ephemeral port
+pub async fn serve(manager: InventoryManager) -> SocketAddr {
+    serve_with(RespoServer::new(manager)).await
+}
+
+pub async fn serve_with(server: RespoServer) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(Arc::new(server).serve(listener));
+    address
+}
+
+/// Send a request on a new connection and return the response once its headers arrived
+pub async fn send(address: SocketAddr, method: Method, path_and_query: &str) -> Response<Incoming> {
+    send_body(address, method, path_and_query, "").await
+}
+
+/// Like `send`, with a body
+pub async fn send_body(
+    address: SocketAddr,
+    method: Method,
+    path_and_query: &str,
+    body: &str,
+) -> Response<Incoming> {
+    let stream = TcpStream::connect(address).await.unwrap();
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+        .await
+        .unwrap();
+    tokio::spawn(conn);
+    let request = Request::builder()
+        .method(method)
+        .uri(path_and_query)
+        .header(hyper::header::HOST, address.to_string())
+        .body(Full::new(Bytes::from(body.to_string())))
+        .unwrap();
+    sender.send_request(request).await.unwrap()
+}
+
+pub async fn body_string(response: Response<Incoming>) -> String {
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    String::from_utf8(body.to_vec()).unwrap()
+}
+
+/// Read an SSE body until the next event and decode its data
+pub async fn next_event(body: &mut Incoming, buffer: &mut String) -> InventoryEvent {
+    loop {
+        if let Some(end) = buffer.find("\n\n") {
+            let message: String = buffer.drain(..end + 2).collect();
+            let data = message
+                .lines()
+                .find_map(|line| line.strip_prefix("data: "))
+                .expect("event has data");
+            return serde_json::from_str(data).unwrap();
+        }
+        let frame = body.frame().await.expect("stream open").unwrap();
+        if let Ok(data) = frame.into_data() {
+            buffer.push_str(std::str::from_utf8(&data).unwrap());
+        }
+    }
+}
@@ -1,11 +1,17 @@
 #[cfg(test)]
 mod tests {
-    use rp::config::InventoryLoader;
-    use rp::inventory::{Inventory, Pool, Resource};
+    use rp::config::{InventoryLoader, ServerConfig};
+    use rp::events::EventFilter;
+    use rp::inventory::{
+        Inventory, InventoryError, InventoryManager, InventoryResourceRequest, Pool, PoolLease,
+        Resource, ResourceRequest,
+    };
+    use serde::Deserialize;
+    use serde_json::json;
     use serde_saphyr::from_str;
     use std::collections::HashMap;
     use std::fs::File;
-    use std::sync::Weak;
+    use std::path::PathBuf;
 
     fn build_simple_inventory() -> Inventory {
         Inventory {
@@ -15,15 +21,18 @@ mod tests {
                 location: "location1".into(),
                 resources: vec![
                     Resource {
+                        name: "0".into(),
                         attributes: vec!["RA1".into(), "RA2".into()],
                         properties: HashMap::new(),
                     },
                     Resource {
+                        name: "1".into(),
                         attributes: vec!["RB1".into(), "RB2".into()],
                         properties: HashMap::new(),
                     },
                 ],
-                user: Weak::new(),
+                allowed: vec![],
+                properties: HashMap::new(),
             }],
         }
     }
@@ -54,4 +63,187 @@ pools:
         let parsed: Inventory = InventoryLoader::load(f);
         assert_eq!(expected, parsed);
     }
+    #[test]
+    fn test_server_config_with_webhooks() {
+        let yaml_input = r#"
+pools:
+  - name: pool1
+    attributes: [attr1, attr2]
+    location: location1
+    resources:
+      - attributes: [RA1, RA2]
+        properties: {}
+      - attributes: [RB1, RB2]
+        properties: {}
+webhooks:
+  - url: http://127.0.0.1:8080/hook
+    events: [LeaseGranted, LeaseReleased]
+    filter:
+      location: location1
+    secret: s3cret
+  - url: http://127.0.0.1:8080/all
+webhook_outbox: /var/lib/respod/outbox.jsonl
+"#;
+        let parsed: ServerConfig = from_str(yaml_input).unwrap();
+        assert_eq!(parsed.inventory, build_simple_inventory());
+        assert_eq!(parsed.webhooks.len(), 2);
+        let hook = &parsed.webhooks[0];
+        assert_eq!(
+            hook.events,
+            Some(vec!["LeaseGranted".into(), "LeaseReleased".into()])
+        );
+        assert_eq!(
+            hook.filter,
+            EventFilter {
+                pool: None,
+                location: Some("location1".into())
+            }
+        );
+        assert_eq!(hook.secret.as_deref(), Some("s3cret"));
+        assert_eq!(hook.max_attempts, 10);
+        assert_eq!(parsed.webhooks[1].events, None);
+        assert_eq!(
+            parsed.webhook_outbox,
+            Some(PathBuf::from("/var/lib/respod/outbox.jsonl"))
+        );
+    }
+    #[test]
+    fn test_inventory_is_a_server_config() {
+        let yaml_input = "pools: []\n";
+        let parsed: ServerConfig = from_str(yaml_input).unwrap();
+        assert!(parsed.webhooks.is_empty());
+        assert_eq!(parsed.webhook_outbox, None);
+    }
+    #[tokio::test]
+    async fn test_structured_properties() {
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct Network {
+            address: String,
+            vlan: u16,
+        }
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct Bench {
+            serial_ports: Vec<String>,
+            baudrate: u32,
+            network: Network,
+        }
+        let yaml_input = r#"
+pools:
+  - name: pool1
+    attributes: [attr1]
+    location: location1
+    resources:
+      - attributes: [RA1]
+        properties:
+          serial_ports: [/dev/ttyUSB0, /dev/ttyUSB1]
+          baudrate: 115200
+          network:
+            address: 10.0.0.2
+            vlan: 12
+"#;
+        let inventory = InventoryLoader::load(yaml_input.as_bytes());
+        let properties = inventory.pools[0].resources[0].properties.clone();
+        assert_eq!(properties["baudrate"], json!(115200));
+        assert_eq!(properties["serial_ports"][1], "/dev/ttyUSB1");
+        assert_eq!(properties["network"]["vlan"], 12);
+
+        // on to the client in the lease JSON, the way the server sends it
+        let mut manager = InventoryManager::new(inventory);
+        let lease = manager.request(&ResourceRequest::default()).await.unwrap();
+        let json = serde_json::to_string_pretty(&lease).unwrap();
+        let received: PoolLease = serde_json::from_str(&json).unwrap();
+        assert_eq!(received.resources()[0].properties, properties);
+        assert_eq!(
+            received.resources()[0].properties_as::<Bench>().unwrap(),
+            Bench {
+                serial_ports: vec!["/dev/ttyUSB0".into(), "/dev/ttyUSB1".into()],
+                baudrate: 115200,
+                network: Network {
+                    address: "10.0.0.2".into(),
+                    vlan: 12
+                },
+            }
+        );
+    }
+    #[tokio::test]
+    async fn test_map_keyed_inventory() {
+        let yaml_input = r#"
+pools:
+  software-update-bench:
+    attributes: [a, b]
+    location: office1
+    properties:
+      rack: 4
+    entities:
+      programmer:
+        attributes: [c, d]
+        properties:
+          socket: 127.0.0.1:1234
+      dut:
+        attributes: [e, f]
+        properties:
+          socket: 127.0.0.1:4567
+  spare-bench:
+    attributes: [a]
+    location: office1
+    resources:
+      - attributes: [c]
+        properties: {}
+      - name: dut
+        attributes: [e]
+        properties: {}
+"#;
+        let inventory = InventoryLoader::load(yaml_input.as_bytes());
+        inventory.validate().unwrap();
+        let names = |pool: &Pool| -> Vec<String> {
+            pool.resources.iter().map(|r| r.name.clone()).collect()
+        };
+        let bench = &inventory.pools[0];
+        assert_eq!(bench.name, "software-update-bench");
+        assert_eq!(bench.properties["rack"], 4);
+        assert_eq!(names(bench), ["programmer", "dut"]);
+        assert_eq!(inventory.pools[1].name, "spare-bench");
+        assert_eq!(names(&inventory.pools[1]), ["0", "dut"]);
+
+        // the lease names the resources it pairs
+        let mut manager = InventoryManager::new(inventory);
+        let request = ResourceRequest {
+            resource_attributes: Some(vec![vec!["e".into()]]),
+            ..Default::default()
+        };
+        let lease = manager.request(&request).await.unwrap();
+        let json: serde_json::Value = serde_json::to_value(&lease).unwrap();
+        assert_eq!(json["pairing"][0][1]["name"], "dut");
+        assert_eq!(json["pool"]["properties"]["rack"], 4);
+        assert_eq!(lease.resource("programmer").unwrap().attributes, ["c", "d"]);
+
+        let duplicate = r#"
+pools:
+  bench:
+    attributes: []
+    location: office1
+    resources:
+      - {name: dut, attributes: [], properties: {}}
+      - {name: dut, attributes: [], properties: {}}
+"#;
+        assert!(matches!(
+            InventoryLoader::load(duplicate.as_bytes()).validate(),
+            Err(InventoryError::DuplicateResourceName { .. })
+        ));
+
+        let unnamed = r#"
+pools:
+  - name: bench
+    attributes: []
+    location: office1
+    resources: []
+  - attributes: []
+    location: office1
+    resources: []
+"#;
+        assert!(matches!(
+            InventoryLoader::load(unnamed.as_bytes()).validate(),
+            Err(InventoryError::UnnamedPool(1))
+        ));
+    }
 }
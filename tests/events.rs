@@ -0,0 +1,123 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{body_string, build_pool, by_name, next_event, send, start_server};
+    use hyper::{Method, StatusCode};
+    use rp::events::{EventFilter, InventoryEvent};
+
+    #[tokio::test]
+    async fn test_events_filtered_by_pool() {
+        let (address, manager) = start_server(vec![
+            build_pool("pool1", "location1"),
+            build_pool("pool2", "location1"),
+        ])
+        .await;
+        let response = send(address, Method::GET, "/events?pool=pool1").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "text/event-stream");
+        let mut body = response.into_body();
+        let mut buffer = String::new();
+
+        let lease2 = manager.request_for(&by_name("pool2"), "client_a").await;
+        let lease1 = manager.request_for(&by_name("pool1"), "client_b").await;
+        drop(lease2);
+        drop(lease1);
+        let response = send(
+            address,
+            Method::POST,
+            "/maintenance?pool=pool1&enabled=true",
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        assert!(matches!(
+            next_event(&mut body, &mut buffer).await,
+            InventoryEvent::LeaseGranted { pool, client, .. } if pool == "pool1" && client == "client_b"
+        ));
+        assert!(matches!(
+            next_event(&mut body, &mut buffer).await,
+            InventoryEvent::LeaseReleased { pool, .. } if pool == "pool1"
+        ));
+        assert_eq!(
+            next_event(&mut body, &mut buffer).await,
+            InventoryEvent::MaintenanceChanged {
+                pool: "pool1".into(),
+                maintenance: true
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_events_unknown_filter_rejected() {
+        let (address, _manager) = start_server(vec![build_pool("pool1", "location1")]).await;
+        let response = send(address, Method::GET, "/events?colour=blue").await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_unknown_pool() {
+        let (address, _manager) = start_server(vec![build_pool("pool1", "location1")]).await;
+        let response = send(
+            address,
+            Method::POST,
+            "/maintenance?pool=pool9&enabled=true",
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert!(body_string(response).await.contains("pool9"));
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_holds_back_pool() {
+        let (address, manager) = start_server(vec![build_pool("pool1", "location1")]).await;
+        send(
+            address,
+            Method::POST,
+            "/maintenance?pool=pool1&enabled=true",
+        )
+        .await;
+        assert!(matches!(
+            manager.request_for(&by_name("pool1"), "client_a").await,
+            Err(rp::inventory::ResourceRequestError::InUse)
+        ));
+        assert!(manager.status().await.pools[0].maintenance);
+        send(
+            address,
+            Method::POST,
+            "/maintenance?pool=pool1&enabled=false",
+        )
+        .await;
+        assert!(
+            manager
+                .request_for(&by_name("pool1"), "client_a")
+                .await
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_filter_by_location() {
+        let pools = vec![
+            build_pool("pool1", "location1"),
+            build_pool("pool2", "location2"),
+        ];
+        let filter = EventFilter {
+            location: Some("location2".into()),
+            ..Default::default()
+        };
+        let granted = |pool: &str| InventoryEvent::LeaseGranted {
+            lease_id: 1,
+            pool: pool.into(),
+            client: "client_a".into(),
+        };
+        assert!(!filter.matches(&granted("pool1"), &pools));
+        assert!(filter.matches(&granted("pool2"), &pools));
+        assert!(filter.matches(&InventoryEvent::InventoryUpdated, &pools));
+        let queued = InventoryEvent::Queued {
+            client: "client_a".into(),
+            pools: vec!["pool1".into(), "pool2".into()],
+        };
+        assert!(filter.matches(&queued, &pools));
+    }
+}
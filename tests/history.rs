@@ -0,0 +1,307 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{body_string, build_pool, by_name, send, serve};
+    use chrono::{DateTime, NaiveDate, Utc};
+    use hyper::{Method, StatusCode};
+    use rp::history::{
+        LeaseHistory, LeaseHistoryEntry, UsageGrouping, UsageReport, UsageRow, usage_report,
+    };
+    use rp::inventory::{Inventory, InventoryManager};
+    use std::path::PathBuf;
+    use tokio::time::{Duration, sleep, timeout};
+
+    fn history_path(test: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "respo-history-{}-{}.jsonl",
+            test,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+    fn time(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value).unwrap().into()
+    }
+    fn entry(client: &str, pool: &str, granted_at: &str, ended_at: &str) -> LeaseHistoryEntry {
+        let granted_at = time(granted_at);
+        let ended_at = time(ended_at);
+        LeaseHistoryEntry {
+            lease_id: 1,
+            client: client.into(),
+            pool: pool.into(),
+            location: "lab1".into(),
+            requested_at: granted_at - chrono::Duration::seconds(60),
+            granted_at,
+            ended_at,
+            waited_seconds: 60.0,
+            held_seconds: (ended_at - granted_at).num_seconds() as f64,
+            expired: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ended_leases_are_appended_to_history() {
+        let path = history_path("append");
+        let manager = InventoryManager::with_history(
+            Inventory {
+                pools: vec![build_pool("pool1", "lab1"), build_pool("pool2", "lab2")],
+            },
+            LeaseHistory::open(path.clone()),
+        );
+        let lease1 = manager
+            .request_for(&by_name("pool1"), "client_a")
+            .await
+            .unwrap();
+        let lease2 = manager
+            .request_for(&by_name("pool2"), "client_b")
+            .await
+            .unwrap();
+        drop(lease2);
+        drop(lease1);
+
+        let history = manager.history().unwrap();
+        let (from, to) = (
+            Utc::now() - chrono::Duration::hours(1),
+            Utc::now() + chrono::Duration::hours(1),
+        );
+        let entries = timeout(Duration::from_secs(5), async {
+            loop {
+                let entries = history.read(from, to).await.unwrap();
+                if entries.len() == 2 {
+                    return entries;
+                }
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("history was not written");
+        assert_eq!(
+            entries
+                .iter()
+                .map(|entry| (
+                    entry.client.as_str(),
+                    entry.pool.as_str(),
+                    entry.location.as_str()
+                ))
+                .collect::<Vec<_>>(),
+            vec![("client_b", "pool2", "lab2"), ("client_a", "pool1", "lab1")]
+        );
+        for entry in &entries {
+            assert!(!entry.expired);
+            assert!(entry.requested_at <= entry.granted_at);
+            assert!(entry.granted_at <= entry.ended_at);
+        }
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_lines_are_skipped() {
+        let path = history_path("corrupt");
+        let line = |entry: &LeaseHistoryEntry| serde_json::to_string(entry).unwrap();
+        let first = entry(
+            "client_a",
+            "pool1",
+            "2026-10-01T08:00:00Z",
+            "2026-10-01T09:00:00Z",
+        );
+        let second = entry(
+            "client_b",
+            "pool2",
+            "2026-10-01T10:00:00Z",
+            "2026-10-01T11:00:00Z",
+        );
+        let truncated = &line(&second)[..40];
+        std::fs::write(
+            &path,
+            format!(
+                "{}\nnot json\n{}\n{}\n",
+                line(&first),
+                truncated,
+                line(&second)
+            ),
+        )
+        .unwrap();
+
+        let history = LeaseHistory::open(path.clone());
+        let entries = history
+            .read(time("2026-10-01T00:00:00Z"), time("2026-10-02T00:00:00Z"))
+            .await
+            .unwrap();
+        assert_eq!(entries, vec![first, second]);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_usage_report_splits_leases_over_days() {
+        let entries = vec![
+            entry(
+                "alice",
+                "pool1",
+                "2026-10-01T18:00:00Z",
+                "2026-10-02T06:00:00Z",
+            ),
+            entry(
+                "bob",
+                "pool1",
+                "2026-10-02T12:00:00Z",
+                "2026-10-02T18:00:00Z",
+            ),
+        ];
+        let report = usage_report(
+            &entries,
+            time("2026-10-01T00:00:00Z"),
+            time("2026-10-03T00:00:00Z"),
+            &[UsageGrouping::Pool, UsageGrouping::Day],
+        );
+        let day = |day: u32| Some(NaiveDate::from_ymd_opt(2026, 10, day).unwrap());
+        assert_eq!(
+            report.rows,
+            vec![
+                UsageRow {
+                    pool: Some("pool1".into()),
+                    day: day(1),
+                    leases: 1,
+                    held_seconds: 6.0 * 3600.0,
+                    waited_seconds: 60.0,
+                    utilisation: 0.25,
+                    ..Default::default()
+                },
+                UsageRow {
+                    pool: Some("pool1".into()),
+                    day: day(2),
+                    leases: 1,
+                    held_seconds: 12.0 * 3600.0,
+                    waited_seconds: 60.0,
+                    utilisation: 0.5,
+                    ..Default::default()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_usage_report_clips_to_period_and_groups_by_user() {
+        let entries = vec![
+            entry(
+                "alice",
+                "pool1",
+                "2026-10-01T10:00:00Z",
+                "2026-10-01T14:00:00Z",
+            ),
+            entry(
+                "alice",
+                "pool2",
+                "2026-10-01T12:00:00Z",
+                "2026-10-01T13:00:00Z",
+            ),
+            entry(
+                "bob",
+                "pool1",
+                "2026-10-01T15:00:00Z",
+                "2026-10-01T16:00:00Z",
+            ),
+        ];
+        let report = usage_report(
+            &entries,
+            time("2026-10-01T12:00:00Z"),
+            time("2026-10-01T16:00:00Z"),
+            &[UsageGrouping::User],
+        );
+        assert_eq!(
+            report.rows,
+            vec![
+                UsageRow {
+                    user: Some("alice".into()),
+                    // the lease of pool1 was granted before the period
+                    leases: 1,
+                    held_seconds: 3.0 * 3600.0,
+                    waited_seconds: 60.0,
+                    utilisation: 0.75,
+                    ..Default::default()
+                },
+                UsageRow {
+                    user: Some("bob".into()),
+                    leases: 1,
+                    held_seconds: 3600.0,
+                    waited_seconds: 60.0,
+                    utilisation: 0.25,
+                    ..Default::default()
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_usage_report_endpoint() {
+        let path = history_path("endpoint");
+        let lines: String = [
+            entry(
+                "alice",
+                "pool1",
+                "2026-10-01T00:00:00Z",
+                "2026-10-01T12:00:00Z",
+            ),
+            entry(
+                "bob",
+                "pool1",
+                "2026-10-05T00:00:00Z",
+                "2026-10-05T12:00:00Z",
+            ),
+        ]
+        .iter()
+        .map(|entry| serde_json::to_string(entry).unwrap() + "\n")
+        .collect();
+        std::fs::write(&path, lines).unwrap();
+        let manager = InventoryManager::with_history(
+            Inventory {
+                pools: vec![build_pool("pool1", "lab1")],
+            },
+            LeaseHistory::open(path.clone()),
+        );
+        let address = serve(manager).await;
+
+        let response = send(
+            address,
+            Method::GET,
+            "/reports/usage?from=2026-10-01&to=2026-10-02&group_by=location,user",
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let report: UsageReport = serde_json::from_str(&body_string(response).await).unwrap();
+        assert_eq!(
+            report.rows,
+            vec![UsageRow {
+                location: Some("lab1".into()),
+                user: Some("alice".into()),
+                leases: 1,
+                held_seconds: 12.0 * 3600.0,
+                waited_seconds: 60.0,
+                utilisation: 0.5,
+                ..Default::default()
+            }]
+        );
+
+        for query in [
+            "group_by=team",
+            "from=2026-10-02&to=2026-10-01",
+            // no room for the default period before it
+            "to=-262143-01-02",
+        ] {
+            let response = send(address, Method::GET, &format!("/reports/usage?{query}")).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{query}");
+        }
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_usage_report_needs_history() {
+        let manager = InventoryManager::new(Inventory {
+            pools: vec![build_pool("pool1", "lab1")],
+        });
+        let address = serve(manager).await;
+        let response = send(address, Method::GET, "/reports/usage").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -2,10 +2,9 @@
 mod tests {
     use rp::inventory::{
         ClientResourceRequest, Inventory, InventoryManager, LocalRespoClient,
-        LocalRespoClientFactory, Pool, Resource, ResourceRequest, ResourceRequestError,
+        LocalRespoClientFactory, Pool, Resource, ResourceRequest, ResourceRequestError, Wait,
     };
     use std::collections::HashMap;
-    use std::sync::Weak;
     use tokio::join;
     use tokio::time::{Duration, sleep};
 
@@ -17,15 +16,18 @@ mod tests {
                 location: "location1".into(),
                 resources: vec![
                     Resource {
+                        name: "0".into(),
                         attributes: vec!["RA1".into(), "RA2".into()],
                         properties: HashMap::new(),
                     },
                     Resource {
+                        name: "1".into(),
                         attributes: vec!["RB1".into(), "RB2".into()],
                         properties: HashMap::new(),
                     },
                 ],
-                user: Weak::new(),
+                allowed: vec![],
+                properties: HashMap::new(),
             }],
         })
     }
@@ -135,7 +137,8 @@ mod tests {
 
         join!(
             async {
-                assert!(client_a.request(&ok_request.clone()).await.is_ok());
+                let lease = client_a.request(&ok_request.clone()).await;
+                assert!(lease.is_ok());
                 sleep(Duration::from_secs(1)).await;
             },
             async {
@@ -152,7 +155,7 @@ mod tests {
         let clientfactory = build_simple_clientfactory();
         let ok_request = build_ok_request();
         let ok_with_timeout = ResourceRequest {
-            timeout: Some(Duration::from_millis(500)),
+            wait: Wait::For(Duration::from_millis(500)),
             ..ok_request.clone()
         };
 
@@ -161,7 +164,8 @@ mod tests {
 
         join!(
             async move {
-                assert!(client_a.request(&ok_request).await.is_ok());
+                let lease = client_a.request(&ok_request).await;
+                assert!(lease.is_ok());
                 sleep(Duration::from_secs(1)).await;
             },
             async move {
@@ -180,7 +184,7 @@ mod tests {
         let clientfactory = build_simple_clientfactory();
         let ok_request = build_ok_request();
         let ok_with_timeout = ResourceRequest {
-            timeout: Some(Duration::from_millis(1000)),
+            wait: Wait::For(Duration::from_millis(1000)),
             ..ok_request.clone()
         };
 
@@ -0,0 +1,331 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{build_pool, by_name};
+    use rp::client::export::{TemplateError, environment, render};
+    use rp::inventory::{
+        ClientResourceRequest, Inventory, InventoryManager, LocalRespoClientFactory, Pool,
+        Resource, ResourceRequest, ResourceRequestError, Wait,
+    };
+    use std::collections::HashMap;
+    use tokio::join;
+    use tokio::time::{Duration, sleep};
+
+    fn build_two_pool_manager() -> InventoryManager {
+        InventoryManager::new(Inventory {
+            pools: vec![
+                build_pool("pool1", "location1"),
+                build_pool("pool2", "location1"),
+            ],
+        })
+    }
+
+    #[tokio::test]
+    async fn test_drop_lease_keeps_client() {
+        let manager = build_two_pool_manager();
+        let factory = LocalRespoClientFactory::new(manager.clone());
+        let mut client_a = factory.create("client_a".into());
+        let mut client_b = factory.create("client_b".into());
+
+        let lease = client_a.request(&by_name("pool1")).await.unwrap();
+        assert!(manager.is_leased("pool1").await);
+        drop(lease);
+        assert!(!manager.is_leased("pool1").await);
+
+        // client_a is still alive, yet the pool is available again
+        assert!(client_b.request(&by_name("pool1")).await.is_ok());
+        drop(client_a);
+    }
+
+    #[tokio::test]
+    async fn test_drop_client_keeps_lease() {
+        let manager = build_two_pool_manager();
+        let factory = LocalRespoClientFactory::new(manager.clone());
+        let mut client_a = factory.create("client_a".into());
+        let mut client_b = factory.create("client_b".into());
+
+        let lease = client_a.request(&by_name("pool1")).await.unwrap();
+        drop(client_a);
+        assert!(manager.is_leased("pool1").await);
+        assert!(matches!(
+            client_b.request(&by_name("pool1")).await,
+            Err(ResourceRequestError::InUse)
+        ));
+
+        drop(lease);
+        assert!(!manager.is_leased("pool1").await);
+        assert!(client_b.request(&by_name("pool1")).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_drop_factory_and_manager_keeps_lease() {
+        let manager = build_two_pool_manager();
+        let observer = manager.clone();
+        let factory = LocalRespoClientFactory::new(manager);
+        let mut client_a = factory.create("client_a".into());
+
+        let lease = client_a.request(&by_name("pool1")).await.unwrap();
+        drop(factory);
+        drop(client_a);
+        assert!(observer.is_leased("pool1").await);
+        drop(lease);
+        assert!(!observer.is_leased("pool1").await);
+    }
+
+    #[tokio::test]
+    async fn test_lease_releases_only_its_pool() {
+        let manager = build_two_pool_manager();
+        let factory = LocalRespoClientFactory::new(manager.clone());
+        let mut client_a = factory.create("client_a".into());
+
+        let lease1 = client_a.request(&by_name("pool1")).await.unwrap();
+        let lease2 = client_a.request(&by_name("pool2")).await.unwrap();
+        lease1.release();
+        assert!(!manager.is_leased("pool1").await);
+        assert!(manager.is_leased("pool2").await);
+        drop(lease2);
+        assert!(!manager.is_leased("pool2").await);
+    }
+
+    #[tokio::test]
+    async fn test_resource_attribute_request_holds_pool() {
+        let manager = build_two_pool_manager();
+        let factory = LocalRespoClientFactory::new(manager.clone());
+        let mut client_a = factory.create("client_a".into());
+        let request = ResourceRequest {
+            resource_attributes: Some(vec![vec!["RA1".into()]]),
+            ..by_name("pool1")
+        };
+
+        let lease = client_a.request(&request).await.unwrap();
+        assert!(manager.is_leased("pool1").await);
+        assert!(matches!(
+            client_a.request(&request).await,
+            Err(ResourceRequestError::InUse)
+        ));
+        drop(lease);
+        assert!(client_a.request(&request).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_release_wakes_waiter_of_that_pool() {
+        let manager = build_two_pool_manager();
+        let factory = LocalRespoClientFactory::new(manager);
+        let mut holder = factory.create("holder".into());
+        let mut client_b = factory.create("client_b".into());
+        let mut client_c = factory.create("client_c".into());
+
+        let lease1 = holder.request(&by_name("pool1")).await.unwrap();
+        let lease2 = holder.request(&by_name("pool2")).await.unwrap();
+        let wait_pool1 = ResourceRequest {
+            wait: Wait::For(Duration::from_millis(1000)),
+            ..by_name("pool1")
+        };
+        let wait_pool2 = ResourceRequest {
+            wait: Wait::For(Duration::from_millis(300)),
+            ..by_name("pool2")
+        };
+
+        join!(
+            async move {
+                sleep(Duration::from_millis(100)).await;
+                drop(lease1);
+                sleep(Duration::from_millis(500)).await;
+                drop(lease2);
+            },
+            async move {
+                let result = client_b.request(&wait_pool1).await;
+                assert!(
+                    result.is_ok(),
+                    "Unexpected error: {:?}",
+                    result.unwrap_err()
+                );
+            },
+            async move {
+                assert!(matches!(
+                    client_c.request(&wait_pool2).await,
+                    Err(ResourceRequestError::TimeOut)
+                ));
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_release_hands_over_in_queue_order() {
+        let manager = build_two_pool_manager();
+        let factory = LocalRespoClientFactory::new(manager);
+        let mut holder = factory.create("holder".into());
+        let mut client_b = factory.create("client_b".into());
+        let mut client_c = factory.create("client_c".into());
+        let wait_pool1 = ResourceRequest {
+            wait: Wait::For(Duration::from_millis(1000)),
+            ..by_name("pool1")
+        };
+        let wait_pool1_c = wait_pool1.clone();
+
+        let lease = holder.request(&by_name("pool1")).await.unwrap();
+        let order = std::sync::Mutex::new(Vec::new());
+        join!(
+            async {
+                sleep(Duration::from_millis(100)).await;
+                drop(lease);
+            },
+            async {
+                let lease = client_b.request(&wait_pool1).await.unwrap();
+                order.lock().unwrap().push("client_b");
+                sleep(Duration::from_millis(100)).await;
+                drop(lease);
+            },
+            async {
+                // queue up after client_b
+                sleep(Duration::from_millis(50)).await;
+                let _lease = client_c.request(&wait_pool1_c).await.unwrap();
+                order.lock().unwrap().push("client_c");
+            }
+        );
+        assert_eq!(*order.lock().unwrap(), vec!["client_b", "client_c"]);
+    }
+
+    #[tokio::test]
+    async fn test_release_skips_waiter_that_gave_up() {
+        let manager = build_two_pool_manager();
+        let factory = LocalRespoClientFactory::new(manager);
+        let mut holder = factory.create("holder".into());
+        let mut client_b = factory.create("client_b".into());
+        let mut client_c = factory.create("client_c".into());
+        let impatient = ResourceRequest {
+            wait: Wait::For(Duration::from_millis(50)),
+            ..by_name("pool1")
+        };
+        let patient = ResourceRequest {
+            wait: Wait::For(Duration::from_millis(1000)),
+            ..by_name("pool1")
+        };
+
+        let lease = holder.request(&by_name("pool1")).await.unwrap();
+        join!(
+            async move {
+                sleep(Duration::from_millis(200)).await;
+                drop(lease);
+            },
+            async move {
+                assert!(matches!(
+                    client_b.request(&impatient).await,
+                    Err(ResourceRequestError::TimeOut)
+                ));
+            },
+            async move {
+                sleep(Duration::from_millis(10)).await;
+                assert!(client_c.request(&patient).await.is_ok());
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_typed_lease_accessors() {
+        #[derive(Debug, serde::Deserialize, PartialEq)]
+        struct Programmer {
+            socket: String,
+        }
+        let resource = |attributes: &[&str], socket: &str| Resource {
+            name: attributes[0].into(),
+            attributes: attributes.iter().map(|a| a.to_string()).collect(),
+            properties: HashMap::from([("socket".to_string(), socket.into())]),
+        };
+        let pool = Pool {
+            resources: vec![
+                resource(&["dut", "arm"], "127.0.0.1:4567"),
+                resource(&["programmer", "jtag"], "127.0.0.1:1234"),
+            ],
+            ..build_pool("bench", "location1")
+        };
+        let manager = InventoryManager::new(Inventory { pools: vec![pool] });
+        let factory = LocalRespoClientFactory::new(manager);
+        let mut client = factory.create("client_a".into());
+        let request = ResourceRequest {
+            resource_attributes: Some(vec![vec!["arm".into(), "dut".into()], vec!["jtag".into()]]),
+            ..Default::default()
+        };
+
+        let lease = client.request(&request).await.unwrap();
+        assert_eq!(lease.name(), "bench");
+        assert_eq!(lease.location(), "location1");
+        assert_eq!(lease.attributes(), ["attr1"]);
+        assert_eq!(lease.pairing().count(), 2);
+        let dut = lease.resource_for(["dut", "arm"]).unwrap();
+        assert_eq!(dut.properties["socket"], "127.0.0.1:4567");
+        assert_eq!(lease.resource("dut"), Some(dut));
+        // not requested as such, found among the resources of the pool
+        let programmer = lease.resource_for(["programmer"]).unwrap();
+        assert_eq!(
+            programmer.properties_as::<Programmer>().unwrap(),
+            Programmer {
+                socket: "127.0.0.1:1234".into()
+            }
+        );
+        assert!(lease.resource_for(["scope"]).is_none());
+        assert!(lease.resources()[0].properties_as::<Vec<String>>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_exported_lease() {
+        let properties = serde_json::json!({
+            "socket": "127.0.0.1:1234",
+            "usb-port": 3,
+            "network": {"address": "10.0.0.2", "ports": [22, 80]},
+            "serial": null,
+        });
+        let pool = Pool {
+            resources: vec![Resource {
+                name: "programmer".into(),
+                attributes: vec!["jtag".into()],
+                properties: serde_json::from_value(properties).unwrap(),
+            }],
+            properties: HashMap::from([("rack".to_string(), true.into())]),
+            ..build_pool("bench", "location1")
+        };
+        let manager = InventoryManager::new(Inventory { pools: vec![pool] });
+        let factory = LocalRespoClientFactory::new(manager);
+        let mut client = factory.create("client_a".into());
+        let lease = client.request(&by_name("bench")).await.unwrap();
+
+        let variables = environment(&lease);
+        for (name, value) in [
+            ("RP_LEASE_ID", lease.id().to_string().as_str()),
+            ("RP_POOL_NAME", "bench"),
+            ("RP_LOCATION", "location1"),
+            ("RP_POOL_ATTRIBUTES", "attr1"),
+            ("RP_RESOURCES", "programmer"),
+            ("RP_PROP_RACK", "true"),
+            ("RP_RES_PROGRAMMER_SOCKET", "127.0.0.1:1234"),
+            ("RP_RES_PROGRAMMER_USB_PORT", "3"),
+            ("RP_RES_PROGRAMMER_NETWORK_ADDRESS", "10.0.0.2"),
+            ("RP_RES_PROGRAMMER_NETWORK_PORTS_1", "80"),
+            ("RP_RES_PROGRAMMER_SERIAL", ""),
+        ] {
+            assert_eq!(
+                variables.get(name).map(String::as_str),
+                Some(value),
+                "{name}"
+            );
+        }
+        assert_eq!(variables.len(), 12);
+
+        assert_eq!(
+            render(
+                "flash {{RP_POOL_NAME}} at {{ RP_RES_PROGRAMMER_SOCKET }}",
+                &variables
+            ),
+            Ok("flash bench at 127.0.0.1:1234".into())
+        );
+        assert_eq!(
+            render("{{RP_SCOPE}}", &variables),
+            Err(TemplateError::UnknownVariable("RP_SCOPE".into()))
+        );
+        assert_eq!(
+            render("at {{RP_LOCATION", &variables),
+            Err(TemplateError::Unclosed(3))
+        );
+    }
+}
@@ -0,0 +1,103 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{body_string, build_pool, start_server};
+    use http_body_util::Empty;
+    use hyper::Request;
+    use hyper::body::Bytes;
+    use hyper_util::rt::TokioIo;
+    use rp::logging::LogFormat;
+    use rp::server::REQUEST_ID_HEADER;
+    use serde_json::Value;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+    use tokio::net::TcpStream;
+    use tokio::time::{Duration, sleep};
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Buffer {
+        fn lines(&self) -> Vec<Value> {
+            String::from_utf8(self.0.lock().unwrap().clone())
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_log_format_parse() {
+        assert_eq!("json".parse::<LogFormat>(), Ok(LogFormat::Json));
+        assert_eq!("pretty".parse::<LogFormat>(), Ok(LogFormat::Pretty));
+        assert_eq!("text".parse::<LogFormat>(), Ok(LogFormat::Text));
+        assert!("xml".parse::<LogFormat>().is_err());
+    }
+
+    // the current thread runtime runs the server and the actor on this thread,
+    // so the default subscriber sees all of their events
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_lease_is_logged_in_request_span() {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .json()
+            .with_span_list(true)
+            .with_max_level(tracing::Level::DEBUG)
+            .with_writer(move || writer.clone())
+            .finish();
+        let _default = tracing::subscriber::set_default(subscriber);
+
+        let (address, manager) = start_server(vec![build_pool("pool1", "lab1")]).await;
+        let stream = TcpStream::connect(address).await.unwrap();
+        let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .unwrap();
+        tokio::spawn(conn);
+        let request = Request::builder()
+            .uri(format!(
+                "http://{address}/lock?client_name=client_a&by_name=pool1"
+            ))
+            .header(hyper::header::HOST, address.to_string())
+            .header(REQUEST_ID_HEADER, "build-42")
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        let response = sender.send_request(request).await.unwrap();
+        body_string(response).await;
+        // the lease was dropped at the end of the handler
+        while manager.is_leased("pool1").await {
+            sleep(Duration::from_millis(1)).await;
+        }
+
+        let lines = buffer.lines();
+        let find = |message: &str| {
+            lines
+                .iter()
+                .find(|line| line["fields"]["message"] == message)
+                .unwrap_or_else(|| panic!("no {message:?} in {lines:#?}"))
+        };
+        for message in ["granted", "lease ended"] {
+            let spans = find(message)["spans"].as_array().unwrap();
+            assert_eq!(spans[0]["name"], "http_request");
+            assert_eq!(spans[0]["request_id"], "build-42");
+            assert_eq!(spans[0]["client"], "client_a");
+            assert_eq!(spans[0]["path"], "/lock");
+            let lease = spans.iter().find(|span| span["name"] == "lease").unwrap();
+            assert_eq!(lease["client"], "client_a");
+            assert_eq!(lease["pool"], "pool1");
+            assert!(lease["lease_id"].is_u64());
+        }
+    }
+}
@@ -0,0 +1,194 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{body_string, build_pool, by_name, send, start_server};
+    use hyper::{Method, StatusCode};
+    use rp::inventory::{Inventory, InventoryManager, ResourceRequest, ResourceRequestError, Wait};
+    use tokio::time::{Duration, advance, sleep};
+
+    /// The value of the sample with exactly this name and labels
+    fn sample(metrics: &str, series: &str) -> Option<f64> {
+        metrics.lines().find_map(|line| {
+            line.strip_prefix(series)
+                .and_then(|value| value.strip_prefix(' '))
+                .map(|value| value.parse().unwrap())
+        })
+    }
+
+    #[tokio::test]
+    async fn test_metrics_endpoint_reports_pool_state() {
+        let (address, manager) = start_server(vec![
+            build_pool("pool1", "lab1"),
+            build_pool("pool2", "lab2"),
+        ])
+        .await;
+        let _lease = manager.request_for(&by_name("pool1"), "client_a").await;
+        manager.set_maintenance("pool2", true).await.unwrap();
+
+        let response = send(address, Method::GET, "/metrics").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(
+            response.headers()[hyper::header::CONTENT_TYPE]
+                .to_str()
+                .unwrap()
+                .starts_with("text/plain")
+        );
+        let metrics = body_string(response).await;
+        let state = |pool: &str, location: &str, state: &str| {
+            sample(
+                &metrics,
+                &format!(
+                    "respo_pool_state{{location=\"{location}\",pool=\"{pool}\",state=\"{state}\"}}"
+                ),
+            )
+        };
+        assert_eq!(state("pool1", "lab1", "leased"), Some(1.0));
+        assert_eq!(state("pool1", "lab1", "free"), Some(0.0));
+        assert_eq!(state("pool2", "lab2", "maintenance"), Some(1.0));
+        assert_eq!(state("pool2", "lab2", "free"), Some(0.0));
+        assert_eq!(
+            sample(
+                &metrics,
+                "respo_leases_total{location=\"lab1\",pool=\"pool1\"}"
+            ),
+            Some(1.0)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_metrics_count_http_requests() {
+        let (address, _manager) = start_server(vec![build_pool("pool1", "lab1")]).await;
+        let response = send(
+            address,
+            Method::POST,
+            "/maintenance?pool=pool1&enabled=true",
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = send(address, Method::POST, "/maintenance?pool=nope&enabled=true").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let metrics = body_string(send(address, Method::GET, "/metrics").await).await;
+        let count = |status: &str| {
+            sample(
+                &metrics,
+                &format!(
+                    "respo_http_request_duration_seconds_count{{method=\"POST\",path=\"/maintenance\",status=\"{status}\"}}"
+                ),
+            )
+        };
+        assert_eq!(count("200"), Some(1.0));
+        assert_eq!(count("404"), Some(1.0));
+    }
+
+    #[tokio::test]
+    async fn test_metrics_label_unknown_methods_as_other() {
+        let (address, _manager) = start_server(vec![build_pool("pool1", "lab1")]).await;
+        for method in [&b"BREW"[..], b"WHEN"] {
+            send(address, Method::from_bytes(method).unwrap(), "/status").await;
+        }
+
+        let metrics = body_string(send(address, Method::GET, "/metrics").await).await;
+        assert!(!metrics.contains("BREW"));
+        assert!(!metrics.contains("WHEN"));
+        assert!(metrics.contains("method=\"other\""));
+    }
+
+    #[tokio::test]
+    async fn test_metrics_count_request_errors() {
+        let manager = InventoryManager::new(Inventory {
+            pools: vec![build_pool("pool1", "lab1")],
+        });
+        let _lease = manager.request_for(&by_name("pool1"), "client_a").await;
+        assert!(matches!(
+            manager.request_for(&by_name("pool1"), "client_b").await,
+            Err(ResourceRequestError::InUse)
+        ));
+        assert!(matches!(
+            manager.request_for(&by_name("nope"), "client_b").await,
+            Err(ResourceRequestError::Impossible)
+        ));
+        let waiting = ResourceRequest {
+            wait: Wait::For(Duration::from_millis(10)),
+            ..by_name("pool1")
+        };
+        assert!(matches!(
+            manager.request_for(&waiting, "client_b").await,
+            Err(ResourceRequestError::TimeOut)
+        ));
+
+        let metrics = manager.metrics().render(&manager.status().await);
+        for error in ["InUse", "Impossible", "TimeOut"] {
+            assert_eq!(
+                sample(
+                    &metrics,
+                    &format!("respo_request_errors_total{{error=\"{error}\"}}")
+                ),
+                Some(1.0),
+                "{error}"
+            );
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_metrics_measure_wait_and_lease_time() {
+        let manager = InventoryManager::new(Inventory {
+            pools: vec![build_pool("pool1", "lab1")],
+        });
+        let lease = manager
+            .request_for(&by_name("pool1"), "client_a")
+            .await
+            .unwrap();
+        let waiting = ResourceRequest {
+            wait: Wait::For(Duration::from_secs(3600)),
+            ..by_name("pool1")
+        };
+        let waiter = {
+            let manager = manager.clone();
+            tokio::spawn(async move { manager.request_for(&waiting, "client_b").await })
+        };
+        sleep(Duration::from_secs(1)).await;
+        let metrics = manager.metrics().render(&manager.status().await);
+        assert_eq!(
+            sample(
+                &metrics,
+                "respo_pool_queue_depth{location=\"lab1\",pool=\"pool1\"}"
+            ),
+            Some(1.0)
+        );
+
+        advance(Duration::from_secs(119)).await;
+        drop(lease);
+        let _second = waiter.await.unwrap().unwrap();
+
+        let metrics = manager.metrics().render(&manager.status().await);
+        let labels = "{location=\"lab1\",pool=\"pool1\"}";
+        assert_eq!(
+            sample(
+                &metrics,
+                &format!("respo_lease_duration_seconds_count{labels}")
+            ),
+            Some(1.0)
+        );
+        assert_eq!(
+            sample(
+                &metrics,
+                &format!("respo_lease_duration_seconds_sum{labels}")
+            ),
+            Some(120.0)
+        );
+        assert_eq!(
+            sample(&metrics, &format!("respo_wait_seconds_count{labels}")),
+            Some(2.0)
+        );
+        assert_eq!(
+            sample(&metrics, &format!("respo_wait_seconds_sum{labels}")),
+            Some(120.0)
+        );
+        assert_eq!(
+            sample(&metrics, &format!("respo_pool_queue_depth{labels}")),
+            Some(0.0)
+        );
+    }
+}
@@ -0,0 +1,238 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::build_pool;
+    use rp::config::ServerConfigLoader;
+    use rp::events::InventoryEvent;
+    use rp::inventory::{Inventory, InventoryManager, ResourceRequest, ResourceRequestError, Wait};
+    use rp::preemption::PreemptionPolicy;
+    use tokio::sync::broadcast;
+    use tokio::time::{Duration, Instant, sleep};
+
+    async fn build_manager(policy: Option<PreemptionPolicy>) -> InventoryManager {
+        let manager = InventoryManager::new(Inventory {
+            pools: vec![build_pool("pool1", "lab1")],
+        });
+        manager.set_preemption(policy).await;
+        manager
+    }
+    fn policy() -> Option<PreemptionPolicy> {
+        Some(PreemptionPolicy {
+            min_priority: 100,
+            grace_period_secs: 60,
+        })
+    }
+    fn soak_test() -> ResourceRequest {
+        ResourceRequest {
+            preemptible: true,
+            ..Default::default()
+        }
+    }
+    fn waiting(priority: Option<u32>) -> ResourceRequest {
+        ResourceRequest {
+            wait: Wait::For(Duration::from_secs(3600)),
+            priority,
+            ..Default::default()
+        }
+    }
+    async fn next_event(events: &mut broadcast::Receiver<InventoryEvent>) -> InventoryEvent {
+        loop {
+            match events.recv().await.unwrap() {
+                InventoryEvent::Queued { .. } => continue,
+                event => return event,
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_urgent_request_revokes_preemptible_lease() {
+        let manager = build_manager(policy()).await;
+        let nightly = manager.request_for(&soak_test(), "nightly").await.unwrap();
+        let mut events = manager.subscribe();
+
+        let queued = {
+            let manager = manager.clone();
+            tokio::spawn(async move { manager.request_for(&waiting(None), "bob").await })
+        };
+        while manager.status().await.pools[0].queue_length < 1 {
+            tokio::task::yield_now().await;
+        }
+        let requested = Instant::now();
+        let hotfix = {
+            let manager = manager.clone();
+            tokio::spawn(async move { manager.request_for(&waiting(Some(100)), "hotfix").await })
+        };
+
+        let revocation = nightly.revocation().await.unwrap();
+        assert_eq!(revocation.by, "hotfix");
+        assert_eq!(revocation.at, requested + Duration::from_secs(60));
+        assert!(matches!(
+            next_event(&mut events).await,
+            InventoryEvent::LeasePreempted { client, by, grace_seconds: 60, .. }
+                if client == "nightly" && by == "hotfix"
+        ));
+
+        // the holder keeps the pool for the grace period, the urgent request goes first after
+        let _hotfix_lease = hotfix.await.unwrap().unwrap();
+        assert_eq!(requested.elapsed(), Duration::from_secs(60));
+        assert!(matches!(
+            next_event(&mut events).await,
+            InventoryEvent::LeaseRevoked { client, .. } if client == "nightly"
+        ));
+        assert!(matches!(
+            nightly.renew().await,
+            Err(ResourceRequestError::NotHeld)
+        ));
+        assert!(!queued.is_finished());
+        queued.abort();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_release_during_grace_period() {
+        let manager = build_manager(policy()).await;
+        let nightly = manager.request_for(&soak_test(), "nightly").await.unwrap();
+        let mut events = manager.subscribe();
+        let hotfix = {
+            let manager = manager.clone();
+            tokio::spawn(async move { manager.request_for(&waiting(Some(200)), "hotfix").await })
+        };
+
+        nightly.revocation().await.unwrap();
+        sleep(Duration::from_secs(10)).await;
+        nightly.release();
+        let _hotfix_lease = hotfix.await.unwrap().unwrap();
+
+        sleep(Duration::from_secs(60)).await;
+        let mut ended = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            ended.push(event.name());
+        }
+        assert_eq!(
+            ended,
+            ["Queued", "LeasePreempted", "LeaseReleased", "LeaseGranted"]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_urgent_request_timing_out_cancels_revocation() {
+        let manager = build_manager(policy()).await;
+        let nightly = manager.request_for(&soak_test(), "nightly").await.unwrap();
+        let hotfix = ResourceRequest {
+            wait: Wait::For(Duration::from_secs(30)),
+            priority: Some(100),
+            ..Default::default()
+        };
+        let hotfix = {
+            let manager = manager.clone();
+            tokio::spawn(async move { manager.request_for(&hotfix, "hotfix").await })
+        };
+        nightly.revocation().await.unwrap();
+
+        assert!(matches!(
+            hotfix.await.unwrap(),
+            Err(ResourceRequestError::TimeOut)
+        ));
+        assert_eq!(manager.status().await.leases[0].revoked_at, None);
+        sleep(Duration::from_secs(60)).await;
+        assert!(nightly.renew().await.is_ok());
+        assert_eq!(
+            manager.status().await.pools[0].holder.as_deref(),
+            Some("nightly")
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_what_is_not_preempted() {
+        let short = |priority| ResourceRequest {
+            wait: Wait::For(Duration::from_secs(120)),
+            priority,
+            ..Default::default()
+        };
+        let timed_out = |result| matches!(result, Err(ResourceRequestError::TimeOut));
+
+        // a lease that wasn't marked preemptible
+        let manager = build_manager(policy()).await;
+        let _held = manager
+            .request_for(&ResourceRequest::default(), "release")
+            .await
+            .unwrap();
+        assert!(timed_out(
+            manager.request_for(&short(Some(500)), "hotfix").await
+        ));
+
+        // a priority below the one of the policy
+        let manager = build_manager(policy()).await;
+        let _held = manager.request_for(&soak_test(), "nightly").await.unwrap();
+        assert!(timed_out(
+            manager.request_for(&short(Some(99)), "hotfix").await
+        ));
+
+        // a priority that isn't above the one of the lease
+        let manager = build_manager(policy()).await;
+        let urgent_soak = ResourceRequest {
+            priority: Some(100),
+            ..soak_test()
+        };
+        let _held = manager.request_for(&urgent_soak, "nightly").await.unwrap();
+        assert!(timed_out(
+            manager.request_for(&short(Some(100)), "hotfix").await
+        ));
+
+        // without a policy
+        let manager = build_manager(None).await;
+        let _held = manager.request_for(&soak_test(), "nightly").await.unwrap();
+        assert!(timed_out(
+            manager.request_for(&short(Some(500)), "hotfix").await
+        ));
+
+        // an urgent request that doesn't wait
+        let manager = build_manager(policy()).await;
+        let _held = manager.request_for(&soak_test(), "nightly").await.unwrap();
+        assert!(matches!(
+            manager
+                .request_for(
+                    &ResourceRequest {
+                        priority: Some(500),
+                        ..Default::default()
+                    },
+                    "hotfix"
+                )
+                .await,
+            Err(ResourceRequestError::InUse)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_no_revocation_without_guard() {
+        let manager = build_manager(policy()).await;
+        let lease = manager.request_for(&soak_test(), "nightly").await.unwrap();
+        let remote: rp::inventory::PoolLease =
+            serde_json::from_str(&serde_json::to_string(&lease).unwrap()).unwrap();
+        assert_eq!(remote.revocation().await, None);
+    }
+
+    #[test]
+    fn test_preemption_config() {
+        let config = ServerConfigLoader::load(
+            r#"
+pools: []
+preemption:
+  min_priority: 50
+  grace_period_secs: 300
+"#
+            .as_bytes(),
+        );
+        assert_eq!(
+            config.preemption,
+            Some(PreemptionPolicy {
+                min_priority: 50,
+                grace_period_secs: 300,
+            })
+        );
+        assert_eq!(
+            ServerConfigLoader::load("pools: []".as_bytes()).preemption,
+            None
+        );
+    }
+}
@@ -0,0 +1,203 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{body_string, build_pool, send, send_body, start_server};
+    use hyper::{Method, StatusCode};
+    use proptest::prelude::*;
+    use rp::client::RemoteRespoClientFactory;
+    use rp::events::InventoryEvent;
+    use rp::inventory::{Pool, ResourceRequest, Wait};
+    use rp::query::{LockQuery, QueryError};
+    use tokio::time::Duration;
+
+    fn attributes() -> impl Strategy<Value = Vec<String>> {
+        prop::collection::vec("[^,]+", 0..4)
+    }
+
+    fn wait() -> impl Strategy<Value = Wait> {
+        prop_oneof![
+            Just(Wait::NoWait),
+            Just(Wait::Forever),
+            (0..1_000_000u64, 0..1_000_000_000u32)
+                .prop_map(|(seconds, nanos)| Wait::For(Duration::new(seconds, nanos))),
+        ]
+    }
+
+    prop_compose! {
+        fn lock_query()(
+            client_name in prop::option::of(".*"),
+            keep in any::<bool>(),
+            location in prop::option::of(".*"),
+            pool_attributes in prop::option::of(attributes()),
+            resource_attributes in prop::option::of(prop::collection::vec(attributes(), 1..4)),
+            wait in wait(),
+            by_name in prop::option::of(".*"),
+            priority in prop::option::of(any::<u32>()),
+            preemptible in any::<bool>(),
+        ) -> LockQuery {
+            LockQuery {
+                client_name,
+                keep,
+                request: ResourceRequest {
+                    location,
+                    pool_attributes,
+                    resource_attributes,
+                    wait,
+                    by_name,
+                    priority,
+                    preemptible,
+                },
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_query_round_trip(query in lock_query()) {
+            let encoded = query.encode();
+            if encoded.is_empty() {
+                prop_assert_eq!(LockQuery::decode(&encoded), Err(QueryError::Empty));
+            } else {
+                prop_assert_eq!(LockQuery::decode(&encoded), Ok(query));
+            }
+        }
+
+        #[test]
+        fn test_json_round_trip(query in lock_query()) {
+            let json = serde_json::to_string(&query.request).unwrap();
+            prop_assert_eq!(serde_json::from_str::<ResourceRequest>(&json).unwrap(), query.request);
+        }
+
+        #[test]
+        fn test_wait_round_trip(wait in wait()) {
+            prop_assert_eq!(wait.to_string().parse::<Wait>().unwrap(), wait);
+        }
+    }
+
+    #[test]
+    fn test_query_errors() {
+        assert_eq!(LockQuery::decode(""), Err(QueryError::Empty));
+        assert_eq!(
+            LockQuery::decode("by_name=pool1&colour=red"),
+            Err(QueryError::UnknownKey("colour".into()))
+        );
+        assert!(matches!(
+            LockQuery::decode("priority=high"),
+            Err(QueryError::Invalid { key, .. }) if key == "priority"
+        ));
+        // the former seconds only timeout
+        assert_eq!(
+            LockQuery::decode("timeout=30").unwrap().request.wait,
+            Wait::For(Duration::from_secs(30))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_server_reads_relative_uri() {
+        let (address, _manager) = start_server(vec![build_pool("pool 1&2", "lab1")]).await;
+        let query = LockQuery {
+            client_name: Some("alice".into()),
+            request: ResourceRequest {
+                by_name: Some("pool 1&2".into()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let response = send(address, Method::GET, &format!("/lock?{}", query.encode())).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(body_string(response).await.contains("pool 1&2"));
+
+        let response = send(address, Method::GET, "/lock?colour=red").await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_lock_json_body() {
+        let (address, manager) = start_server(vec![build_pool("pool1", "lab1")]).await;
+        let mut events = manager.subscribe();
+        let response = send_body(
+            address,
+            Method::POST,
+            "/lock?client_name=alice",
+            r#"{"by_name": "pool1", "resource_attributes": [["RA1"]], "wait": "90s"}"#,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(body_string(response).await.contains("RA1"));
+        match events.recv().await.unwrap() {
+            InventoryEvent::LeaseGranted { client, .. } => assert_eq!(client, "alice"),
+            event => panic!("unexpected {event:?}"),
+        }
+
+        for (query, body) in [
+            ("", r#"{"by_name": "pool1", "colour": "red"}"#),
+            ("", r#"{"wait": "soon"}"#),
+            ("", "by_name=pool1"),
+            ("?by_name=pool1", r#"{"by_name": "pool1"}"#),
+        ] {
+            let response = send_body(address, Method::POST, &format!("/lock{query}"), body).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{query} {body}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_client_sends_every_field() {
+        let pool = Pool {
+            attributes: vec!["usb,hub".into(), "wifi".into()],
+            ..build_pool("bench", "lab 1")
+        };
+        let (address, manager) = start_server(vec![pool]).await;
+        let mut events = manager.subscribe();
+        let mut factory = RemoteRespoClientFactory::new(format!("http://{address}/lock"));
+        let mut client = factory.create("ci & co".into());
+
+        let request = ResourceRequest {
+            location: Some("lab 1".into()),
+            pool_attributes: Some(vec!["usb,hub".into(), "wifi".into()]),
+            resource_attributes: Some(vec![vec!["RA1".into()]]),
+            ..Default::default()
+        };
+        assert!(client.request(&request).await.is_ok());
+        match events.recv().await.unwrap() {
+            InventoryEvent::LeaseGranted { client, .. } => assert_eq!(client, "ci & co"),
+            event => panic!("unexpected {event:?}"),
+        }
+
+        // attributes the pool doesn't have are not lost on the way
+        let request = ResourceRequest {
+            pool_attributes: Some(vec!["bluetooth".into()]),
+            ..Default::default()
+        };
+        assert!(client.request(&request).await.is_err());
+    }
+
+    #[test]
+    fn test_spec_file() {
+        let yaml = "
+location: lab 1
+pool_attributes: [usb]
+resource_attributes:
+  - [programmer, jtag]
+  - [dut]
+wait: 1h 30m
+priority: 10
+";
+        let request: ResourceRequest = serde_saphyr::from_str(yaml).unwrap();
+        assert_eq!(
+            request,
+            ResourceRequest {
+                location: Some("lab 1".into()),
+                pool_attributes: Some(vec!["usb".into()]),
+                resource_attributes: Some(vec![
+                    vec!["programmer".into(), "jtag".into()],
+                    vec!["dut".into()]
+                ]),
+                wait: Wait::For(Duration::from_secs(5400)),
+                priority: Some(10),
+                ..Default::default()
+            }
+        );
+        assert!(serde_saphyr::from_str::<ResourceRequest>("name: pool1").is_err());
+    }
+}
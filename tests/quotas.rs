@@ -0,0 +1,266 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{body_string, build_pool, send, start_server};
+    use hyper::Method;
+    use rp::config::ServerConfigLoader;
+    use rp::inventory::{
+        Caller, Inventory, InventoryManager, InventoryStatus, ResourceRequest,
+        ResourceRequestError, Wait,
+    };
+    use rp::quotas::Quota;
+    use tokio::time::{Duration, sleep};
+
+    fn build_manager(names: &[&str]) -> InventoryManager {
+        InventoryManager::new(Inventory {
+            pools: names.iter().map(|name| build_pool(name, "lab1")).collect(),
+        })
+    }
+    fn any_pool() -> ResourceRequest {
+        ResourceRequest::default()
+    }
+    fn waiting() -> ResourceRequest {
+        ResourceRequest {
+            wait: Wait::For(Duration::from_secs(3600)),
+            ..Default::default()
+        }
+    }
+    fn member(name: &str, group: &str) -> Caller {
+        Caller {
+            name: name.into(),
+            groups: vec![group.into()],
+        }
+    }
+    fn quota_exceeded(
+        result: Result<rp::inventory::PoolLease, ResourceRequestError>,
+        subject: &str,
+    ) -> bool {
+        matches!(result, Err(ResourceRequestError::QuotaExceeded(s)) if s == subject)
+    }
+
+    #[tokio::test]
+    async fn test_group_lease_limit() {
+        let manager = build_manager(&["pool1", "pool2", "pool3"]);
+        manager
+            .set_quotas(vec![Quota {
+                subject: "team-ci".into(),
+                max_leases: Some(2),
+                ..Default::default()
+            }])
+            .await;
+
+        let first = manager
+            .request_as(&any_pool(), &member("job1", "team-ci"))
+            .await
+            .unwrap();
+        let _second = manager
+            .request_as(&any_pool(), &member("job2", "team-ci"))
+            .await
+            .unwrap();
+        assert!(quota_exceeded(
+            manager
+                .request_as(&any_pool(), &member("job3", "team-ci"))
+                .await,
+            "team-ci"
+        ));
+        // others are not held back by the team
+        let _other = manager.request_for(&any_pool(), "alice").await.unwrap();
+
+        drop(first);
+        assert!(
+            manager
+                .request_as(&any_pool(), &member("job3", "team-ci"))
+                .await
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_queue_limit() {
+        let manager = build_manager(&["pool1"]);
+        manager
+            .set_quotas(vec![Quota {
+                subject: "alice".into(),
+                max_queued: Some(1),
+                ..Default::default()
+            }])
+            .await;
+        let _held = manager.request_for(&any_pool(), "bob").await.unwrap();
+
+        let queued = {
+            let manager = manager.clone();
+            tokio::spawn(async move { manager.request_for(&waiting(), "alice").await })
+        };
+        while manager.status().await.quotas[0].queued == 0 {
+            tokio::task::yield_now().await;
+        }
+        assert!(quota_exceeded(
+            manager.request_for(&waiting(), "alice").await,
+            "alice"
+        ));
+        // an immediate request doesn't queue, the queue limit doesn't apply
+        assert!(matches!(
+            manager.request_for(&any_pool(), "alice").await,
+            Err(ResourceRequestError::InUse)
+        ));
+        queued.abort();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_daily_hours() {
+        let manager = build_manager(&["pool1", "pool2"]);
+        manager
+            .set_quotas(vec![Quota {
+                subject: "alice".into(),
+                max_hours_per_day: Some(0.5),
+                ..Default::default()
+            }])
+            .await;
+
+        let lease = manager.request_for(&any_pool(), "alice").await.unwrap();
+        sleep(Duration::from_secs(15 * 60)).await;
+        let status = manager.status().await;
+        assert_eq!(status.quotas[0].leases, 1);
+        assert!((status.quotas[0].hours_today - 0.25).abs() < 0.01);
+        drop(lease);
+
+        let lease = manager.request_for(&any_pool(), "alice").await.unwrap();
+        sleep(Duration::from_secs(16 * 60)).await;
+        // the lease held now counts too
+        assert!(quota_exceeded(
+            manager.request_for(&any_pool(), "alice").await,
+            "alice"
+        ));
+        drop(lease);
+        assert!(quota_exceeded(
+            manager.request_for(&any_pool(), "alice").await,
+            "alice"
+        ));
+        assert!(manager.request_for(&any_pool(), "bob").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_waiter_at_lease_limit_is_queued() {
+        let manager = build_manager(&["pool1", "pool2"]);
+        manager
+            .set_quotas(vec![Quota {
+                subject: "alice".into(),
+                max_leases: Some(1),
+                ..Default::default()
+            }])
+            .await;
+        let held = manager.request_for(&any_pool(), "alice").await.unwrap();
+        let queued = {
+            let manager = manager.clone();
+            tokio::spawn(async move { manager.request_for(&waiting(), "alice").await })
+        };
+        while manager.status().await.quotas[0].queued == 0 {
+            tokio::task::yield_now().await;
+        }
+        // the free pool stays free for the others meanwhile
+        assert_eq!(manager.status().await.quotas[0].leases, 1);
+        assert!(!queued.is_finished());
+
+        drop(held);
+        assert!(queued.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_waiter_over_quota_keeps_its_place() {
+        let manager = build_manager(&["pool1", "pool2"]);
+        manager
+            .set_quotas(vec![Quota {
+                subject: "alice".into(),
+                max_leases: Some(1),
+                ..Default::default()
+            }])
+            .await;
+        let pool1 = ResourceRequest {
+            by_name: Some("pool1".into()),
+            ..waiting()
+        };
+        let held = manager.request_for(&pool1, "bob").await.unwrap();
+        let alice_waiting = {
+            let manager = manager.clone();
+            let pool1 = pool1.clone();
+            tokio::spawn(async move { manager.request_for(&pool1, "alice").await })
+        };
+        let carol_waiting = {
+            let manager = manager.clone();
+            tokio::spawn(async move {
+                while manager.status().await.pools[0].queue_length < 1 {
+                    tokio::task::yield_now().await;
+                }
+                manager.request_for(&pool1, "carol").await
+            })
+        };
+        while manager.status().await.pools[0].queue_length < 2 {
+            tokio::task::yield_now().await;
+        }
+        // alice got another pool while waiting for pool1
+        let other = manager.request_for(&any_pool(), "alice").await.unwrap();
+
+        drop(held);
+        let carol = carol_waiting.await.unwrap().unwrap();
+        assert!(!alice_waiting.is_finished());
+        assert_eq!(manager.status().await.quotas[0].queued, 1);
+
+        drop(other);
+        drop(carol);
+        assert!(alice_waiting.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_status_endpoint_shows_usage() {
+        let (address, manager) = start_server(vec![build_pool("pool1", "lab1")]).await;
+        manager
+            .set_quotas(vec![Quota {
+                subject: "alice".into(),
+                max_leases: Some(3),
+                ..Default::default()
+            }])
+            .await;
+        let _lease = manager.request_for(&any_pool(), "alice").await.unwrap();
+
+        let response = send(address, Method::GET, "/status").await;
+        let status: InventoryStatus = serde_json::from_str(&body_string(response).await).unwrap();
+        assert_eq!(status.pools[0].holder.as_deref(), Some("alice"));
+        assert_eq!(status.quotas[0].quota.subject, "alice");
+        assert_eq!(status.quotas[0].quota.max_leases, Some(3));
+        assert_eq!(status.quotas[0].leases, 1);
+    }
+
+    #[test]
+    fn test_quota_config() {
+        let config = ServerConfigLoader::load(
+            r#"
+pools: []
+quotas:
+  - subject: team-ci
+    max_leases: 10
+    max_hours_per_day: 100
+    max_queued: 20
+  - subject: alice
+    max_leases: 2
+"#
+            .as_bytes(),
+        );
+        assert_eq!(
+            config.quotas,
+            vec![
+                Quota {
+                    subject: "team-ci".into(),
+                    max_leases: Some(10),
+                    max_hours_per_day: Some(100.0),
+                    max_queued: Some(20),
+                },
+                Quota {
+                    subject: "alice".into(),
+                    max_leases: Some(2),
+                    ..Default::default()
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,159 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{build_pool, by_name};
+    use rcgen::{
+        BasicConstraints, CertificateParams, CertifiedIssuer, DnType, IsCa, KeyPair,
+        KeyUsagePurpose,
+    };
+    use rp::client::RemoteRespoClientFactory;
+    use rp::config::ServerConfigLoader;
+    use rp::events::InventoryEvent;
+    use rp::inventory::{Inventory, InventoryManager};
+    use rp::server::RespoServer;
+    use rp::server::tls::{self, TlsConfig};
+    use rp::tls::ClientTlsConfig;
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+
+    /// A CA, a certificate of the server signed by it, and one of a client named ci-bot
+    struct Certificates {
+        directory: PathBuf,
+    }
+
+    impl Certificates {
+        fn generate(test: &str) -> Certificates {
+            let directory =
+                std::env::temp_dir().join(format!("respo-tls-{}-{}", test, std::process::id()));
+            std::fs::create_dir_all(&directory).unwrap();
+            let write =
+                |name: &str, pem: String| std::fs::write(directory.join(name), pem).unwrap();
+
+            let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+            ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            ca_params
+                .distinguished_name
+                .push(DnType::CommonName, "respo test CA");
+            ca_params.key_usages = vec![KeyUsagePurpose::KeyCertSign];
+            let ca = CertifiedIssuer::self_signed(ca_params, KeyPair::generate().unwrap()).unwrap();
+            write("ca.pem", ca.pem());
+
+            for (name, subject_alt_names, common_name) in [
+                ("server", vec!["127.0.0.1".to_string()], "respod"),
+                ("client", vec![], "ci-bot"),
+            ] {
+                let key = KeyPair::generate().unwrap();
+                let mut params = CertificateParams::new(subject_alt_names).unwrap();
+                params
+                    .distinguished_name
+                    .push(DnType::CommonName, common_name);
+                let certificate = params.signed_by(&key, &ca).unwrap();
+                write(&format!("{name}.pem"), certificate.pem());
+                write(&format!("{name}.key"), key.serialize_pem());
+            }
+            Certificates { directory }
+        }
+
+        fn path(&self, name: &str) -> PathBuf {
+            self.directory.join(name)
+        }
+
+        fn server_config(&self, client_ca: bool) -> TlsConfig {
+            TlsConfig {
+                certificate: self.path("server.pem"),
+                key: self.path("server.key"),
+                client_ca: client_ca.then(|| self.path("ca.pem")),
+            }
+        }
+
+        fn client_config(&self, client_certificate: bool) -> ClientTlsConfig {
+            ClientTlsConfig {
+                ca: Some(self.path("ca.pem")),
+                certificate: client_certificate.then(|| self.path("client.pem")),
+                key: client_certificate.then(|| self.path("client.key")),
+            }
+        }
+    }
+
+    impl Drop for Certificates {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.directory);
+        }
+    }
+
+    async fn serve_tls(config: &TlsConfig) -> (String, InventoryManager) {
+        let manager = InventoryManager::new(Inventory {
+            pools: vec![build_pool("pool1", "lab1")],
+        });
+        let server = RespoServer::new(manager.clone()).with_tls(tls::acceptor(config).unwrap());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("https://{}/lock", listener.local_addr().unwrap());
+        tokio::spawn(Arc::new(server).serve(listener));
+        (url, manager)
+    }
+
+    #[tokio::test]
+    async fn test_lease_over_https() {
+        let certificates = Certificates::generate("https");
+        let (url, _manager) = serve_tls(&certificates.server_config(false)).await;
+
+        let connector = certificates.client_config(false).connector().unwrap();
+        let mut factory = RemoteRespoClientFactory::new(url.clone()).with_tls(connector);
+        let mut client = factory.create("client_a".into());
+        assert!(client.request(&by_name("pool1")).await.is_ok());
+
+        // the test CA is not one of the well known ones
+        let mut factory = RemoteRespoClientFactory::new(url);
+        let mut client = factory.create("client_a".into());
+        assert!(client.request(&by_name("pool1")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_client_certificate_is_identity() {
+        let certificates = Certificates::generate("mtls");
+        let (url, manager) = serve_tls(&certificates.server_config(true)).await;
+        let mut events = manager.subscribe();
+
+        let connector = certificates.client_config(false).connector().unwrap();
+        let mut factory = RemoteRespoClientFactory::new(url.clone()).with_tls(connector);
+        let mut client = factory.create("anyone".into());
+        assert!(client.request(&by_name("pool1")).await.is_err());
+
+        let connector = certificates.client_config(true).connector().unwrap();
+        let mut factory = RemoteRespoClientFactory::new(url).with_tls(connector);
+        let mut client = factory.create("anyone".into());
+        assert!(client.request(&by_name("pool1")).await.is_ok());
+        match events.recv().await.unwrap() {
+            InventoryEvent::LeaseGranted { client, .. } => assert_eq!(client, "ci-bot"),
+            event => panic!("unexpected {event:?}"),
+        }
+    }
+
+    #[test]
+    fn test_tls_config() {
+        let config = ServerConfigLoader::load(
+            r#"
+pools: []
+tls:
+  certificate: /etc/respod/cert.pem
+  key: /etc/respod/key.pem
+  client_ca: /etc/respod/clients.pem
+"#
+            .as_bytes(),
+        );
+        let tls = config.tls.unwrap();
+        assert_eq!(tls.key, Path::new("/etc/respod/key.pem"));
+        assert_eq!(
+            tls.client_ca.as_deref(),
+            Some(Path::new("/etc/respod/clients.pem"))
+        );
+
+        let incomplete = ClientTlsConfig {
+            certificate: Some("client.pem".into()),
+            ..Default::default()
+        };
+        assert!(incomplete.connector().is_err());
+    }
+}
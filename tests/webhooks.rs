@@ -0,0 +1,395 @@
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{build_pool, by_name};
+    use http_body_util::{BodyExt, Full};
+    use hyper::body::{Bytes, Incoming};
+    use hyper::server::conn::http1;
+    use hyper::service::service_fn;
+    use hyper::{Request, Response, StatusCode};
+    use hyper_util::rt::TokioIo;
+    use rp::events::{EventFilter, InventoryEvent};
+    use rp::inventory::{Inventory, InventoryManager};
+    use rp::server::webhooks::{
+        DELIVERY_HEADER, EVENT_HEADER, Outbox, SIGNATURE_HEADER, WebhookConfig, WebhookError, sign,
+        start_webhooks,
+    };
+    use std::convert::Infallible;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+    use tokio::time::{Duration, timeout};
+
+    /// A request as the stub received it
+    struct Received {
+        event: String,
+        delivery: String,
+        signature: Option<String>,
+        body: Bytes,
+    }
+
+    /// Accepts webhook calls, answering 500 to the first `failures` of them
+    async fn start_stub(failures: usize) -> (String, mpsc::UnboundedReceiver<Received>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (sender, received) = mpsc::unbounded_channel();
+        let remaining_failures = Arc::new(AtomicUsize::new(failures));
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let sender = sender.clone();
+                let remaining_failures = remaining_failures.clone();
+                let service = service_fn(move |request: Request<Incoming>| {
+                    let sender = sender.clone();
+                    let remaining_failures = remaining_failures.clone();
+                    async move {
+                        let header = |name: &str| {
+                            request
+                                .headers()
+                                .get(name)
+                                .map(|value| value.to_str().unwrap().to_string())
+                        };
+                        let event = header(EVENT_HEADER).unwrap();
+                        let delivery = header(DELIVERY_HEADER).unwrap();
+                        let signature = header(SIGNATURE_HEADER);
+                        let body = request.into_body().collect().await.unwrap().to_bytes();
+                        let failing = remaining_failures
+                            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                            .is_ok();
+                        let status = if failing {
+                            StatusCode::INTERNAL_SERVER_ERROR
+                        } else {
+                            let _ = sender.send(Received {
+                                event,
+                                delivery,
+                                signature,
+                                body,
+                            });
+                            StatusCode::NO_CONTENT
+                        };
+                        let mut response = Response::new(Full::new(Bytes::new()));
+                        *response.status_mut() = status;
+                        Ok::<_, Infallible>(response)
+                    }
+                });
+                tokio::spawn(http1::Builder::new().serve_connection(TokioIo::new(stream), service));
+            }
+        });
+        (url, received)
+    }
+
+    fn build_manager() -> InventoryManager {
+        InventoryManager::new(Inventory {
+            pools: vec![build_pool("pool1", "lab1"), build_pool("pool2", "lab2")],
+        })
+    }
+    fn fast_hook(url: String) -> WebhookConfig {
+        WebhookConfig {
+            initial_backoff_ms: 10,
+            max_backoff_ms: 50,
+            ..WebhookConfig::new(url)
+        }
+    }
+    fn outbox_path(test: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "respo-webhooks-{}-{}.jsonl",
+            test,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+    async fn next(received: &mut mpsc::UnboundedReceiver<Received>) -> Received {
+        timeout(Duration::from_secs(5), received.recv())
+            .await
+            .expect("no webhook delivery")
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_delivery_is_signed() {
+        let (url, mut received) = start_stub(0).await;
+        let manager = build_manager();
+        let hook = WebhookConfig {
+            secret: Some("s3cret".into()),
+            ..fast_hook(url)
+        };
+        let webhooks = start_webhooks(vec![hook], None, &manager).unwrap();
+
+        let _lease = manager.request_for(&by_name("pool1"), "client_a").await;
+        let delivery = next(&mut received).await;
+        assert_eq!(delivery.event, "LeaseGranted");
+        assert_eq!(delivery.signature, Some(sign("s3cret", &delivery.body)));
+        let event: InventoryEvent = serde_json::from_slice(&delivery.body).unwrap();
+        assert!(matches!(
+            event,
+            InventoryEvent::LeaseGranted { pool, client, .. } if pool == "pool1" && client == "client_a"
+        ));
+        webhooks.abort();
+    }
+
+    #[tokio::test]
+    async fn test_hooks_sharing_a_url() {
+        let (url, mut received) = start_stub(0).await;
+        let manager = build_manager();
+        let all = WebhookConfig {
+            secret: Some("all".into()),
+            ..fast_hook(url.clone())
+        };
+        let released = WebhookConfig {
+            secret: Some("released".into()),
+            events: Some(vec!["LeaseReleased".into()]),
+            ..fast_hook(url)
+        };
+        let webhooks = start_webhooks(vec![all, released], None, &manager).unwrap();
+
+        let lease = manager.request_for(&by_name("pool1"), "client_a").await;
+        drop(lease);
+        let mut deliveries = Vec::new();
+        for _ in 0..3 {
+            let delivery = next(&mut received).await;
+            let secret = ["all", "released"]
+                .into_iter()
+                .find(|secret| delivery.signature == Some(sign(secret, &delivery.body)))
+                .expect("signed with the secret of its hook");
+            deliveries.push((secret, delivery.event));
+        }
+        deliveries.sort();
+        assert_eq!(
+            deliveries,
+            [
+                ("all", "LeaseGranted".to_string()),
+                ("all", "LeaseReleased".to_string()),
+                ("released", "LeaseReleased".to_string()),
+            ]
+        );
+        assert!(
+            timeout(Duration::from_millis(200), received.recv())
+                .await
+                .is_err()
+        );
+        webhooks.abort();
+    }
+
+    #[tokio::test]
+    async fn test_https_hook_is_delivered_over_tls() {
+        // tells what the hook receives first: a TLS handshake record, not the request
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("https://{}/hook", listener.local_addr().unwrap());
+        let manager = build_manager();
+        let webhooks = start_webhooks(vec![fast_hook(url)], None, &manager).unwrap();
+
+        let _lease = manager.request_for(&by_name("pool1"), "client_a").await;
+        let (mut stream, _) = timeout(Duration::from_secs(5), listener.accept())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stream.read_u8().await.unwrap(), 0x16);
+        webhooks.abort();
+
+        assert!(matches!(
+            start_webhooks(vec![fast_hook("ftp://127.0.0.1/hook".into())], None, &manager),
+            Err(WebhookError::UnsupportedScheme(scheme)) if scheme == "ftp"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_failed_delivery_is_retried_in_order() {
+        let (url, mut received) = start_stub(3).await;
+        let manager = build_manager();
+        let webhooks = start_webhooks(vec![fast_hook(url)], None, &manager).unwrap();
+
+        let lease = manager
+            .request_for(&by_name("pool1"), "client_a")
+            .await
+            .unwrap();
+        drop(lease);
+        let first = next(&mut received).await;
+        let second = next(&mut received).await;
+        assert_eq!(first.event, "LeaseGranted");
+        assert_eq!(first.signature, None);
+        assert_eq!(second.event, "LeaseReleased");
+        assert!(first.delivery.parse::<u64>().unwrap() < second.delivery.parse::<u64>().unwrap());
+        webhooks.abort();
+    }
+
+    #[tokio::test]
+    async fn test_events_and_filter_select_deliveries() {
+        let (url, mut received) = start_stub(0).await;
+        let manager = build_manager();
+        let hook = WebhookConfig {
+            events: Some(vec!["LeaseReleased".into()]),
+            filter: EventFilter {
+                pool: None,
+                location: Some("lab2".into()),
+            },
+            ..fast_hook(url)
+        };
+        let webhooks = start_webhooks(vec![hook], None, &manager).unwrap();
+
+        let lease1 = manager.request_for(&by_name("pool1"), "client_a").await;
+        let lease2 = manager.request_for(&by_name("pool2"), "client_a").await;
+        drop(lease1);
+        drop(lease2);
+        let delivery = next(&mut received).await;
+        let event: InventoryEvent = serde_json::from_slice(&delivery.body).unwrap();
+        assert!(matches!(
+            event,
+            InventoryEvent::LeaseReleased { pool, .. } if pool == "pool2"
+        ));
+        assert!(
+            timeout(Duration::from_millis(200), received.recv())
+                .await
+                .is_err()
+        );
+        webhooks.abort();
+    }
+
+    #[tokio::test]
+    async fn test_outbox_survives_restart() {
+        let path = outbox_path("restart");
+        let (url, mut received) = start_stub(usize::MAX).await;
+        let manager = build_manager();
+        let hook = WebhookConfig {
+            max_attempts: u32::MAX,
+            ..fast_hook(url)
+        };
+        let webhooks = start_webhooks(vec![hook.clone()], Some(path.clone()), &manager).unwrap();
+
+        let _lease = manager.request_for(&by_name("pool1"), "client_a").await;
+        timeout(Duration::from_secs(5), async {
+            while Outbox::open(Some(path.clone())).unwrap().pending().count() == 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("delivery never reached the outbox");
+        webhooks.abort();
+        let _ = webhooks.await;
+        assert!(received.try_recv().is_err());
+
+        // a restarted server, with the endpoint working again
+        let (url, mut received) = start_stub(0).await;
+        let restarted = WebhookConfig {
+            url: url.clone(),
+            ..hook
+        };
+        let pending: Vec<_> = Outbox::open(Some(path.clone()))
+            .unwrap()
+            .pending()
+            .cloned()
+            .collect();
+        // the outbox is keyed by URL, move the pending delivery to the new port
+        std::fs::write(
+            &path,
+            pending
+                .into_iter()
+                .map(|mut delivery| {
+                    delivery.url = url.clone();
+                    serde_json::to_string(&delivery).unwrap() + "\n"
+                })
+                .collect::<String>(),
+        )
+        .unwrap();
+        let webhooks =
+            start_webhooks(vec![restarted], Some(path.clone()), &build_manager()).unwrap();
+        let delivery = next(&mut received).await;
+        assert_eq!(delivery.event, "LeaseGranted");
+        timeout(Duration::from_secs(5), async {
+            while Outbox::open(Some(path.clone())).unwrap().pending().count() != 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("delivered event stayed in the outbox");
+        webhooks.abort();
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_outbox_is_appended_and_compacted() {
+        let path = outbox_path("compacted");
+        let (url, mut received) = start_stub(0).await;
+        let manager = build_manager();
+        let hook = WebhookConfig {
+            events: Some(vec!["LeaseGranted".into()]),
+            ..fast_hook(url)
+        };
+        let webhooks = start_webhooks(vec![hook], Some(path.clone()), &manager).unwrap();
+        let lines = || std::fs::read_to_string(&path).unwrap().lines().count();
+        let until_done = || async {
+            timeout(Duration::from_secs(10), async {
+                while Outbox::open(Some(path.clone())).unwrap().pending().count() != 0 {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            })
+            .await
+            .expect("the deliveries stayed in the outbox")
+        };
+
+        drop(manager.request_for(&by_name("pool1"), "client_a").await);
+        next(&mut received).await;
+        // the delivery, then that it is done
+        timeout(Duration::from_secs(5), async {
+            while lines() < 2 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the delivery was not appended");
+        assert_eq!(lines(), 2);
+        until_done().await;
+
+        for _ in 0..600 {
+            drop(manager.request_for(&by_name("pool1"), "client_a").await);
+        }
+        for _ in 0..600 {
+            next(&mut received).await;
+        }
+        until_done().await;
+        assert!(lines() < 1000, "{} lines", lines());
+        webhooks.abort();
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_outbox_skips_a_truncated_line() {
+        let path = outbox_path("truncated");
+        let (url, mut received) = start_stub(0).await;
+        let delivery =
+            format!(r#"{{"id":4,"hook":0,"url":"{url}","event":{{"event":"InventoryUpdated"}}}}"#);
+        // cut short by a crash during the append
+        std::fs::write(&path, format!("{delivery}\n{}", &delivery[..20])).unwrap();
+        assert_eq!(
+            Outbox::open(Some(path.clone())).unwrap().pending().count(),
+            1
+        );
+
+        let webhooks =
+            start_webhooks(vec![fast_hook(url)], Some(path.clone()), &build_manager()).unwrap();
+        assert_eq!(next(&mut received).await.event, "InventoryUpdated");
+        webhooks.abort();
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_outbox_drops_deliveries_of_removed_hooks() {
+        let path = outbox_path("removed");
+        std::fs::write(
+            &path,
+            r#"{"id":4,"url":"http://127.0.0.1:1/gone","event":{"event":"InventoryUpdated"}}"#,
+        )
+        .unwrap();
+        let webhooks = start_webhooks(vec![], Some(path.clone()), &build_manager()).unwrap();
+        assert_eq!(
+            Outbox::open(Some(path.clone())).unwrap().pending().count(),
+            0
+        );
+        webhooks.abort();
+        let _ = std::fs::remove_file(&path);
+    }
+}